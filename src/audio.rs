@@ -0,0 +1,239 @@
+use crate::sfxr;
+
+use ggez::audio::{self, SoundSource};
+use ggez::{Context, GameError, GameResult};
+
+use std::collections::HashMap;
+
+/// Most copies of the same sound effect that can play at once
+pub const MAX_COPIES: usize = 4;
+
+/// Sound effects played in response to gameplay events
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Sound {
+    PlayerShot,
+    EnemyHit,
+    EnemyDeath,
+    PlayerHit,
+    // Nothing plays this yet, it's waiting on there being lives to earn
+    ExtraLife,
+    StageStart,
+}
+
+impl Sound {
+    pub const ALL: [Sound; 6] = [
+        Sound::PlayerShot,
+        Sound::EnemyHit,
+        Sound::EnemyDeath,
+        Sound::PlayerHit,
+        Sound::ExtraLife,
+        Sound::StageStart,
+    ];
+
+    /// Name of the sound's synthesizer preset
+    pub fn name(self) -> &'static str {
+        match self {
+            Sound::PlayerShot => "player_shot",
+            Sound::EnemyHit => "enemy_hit",
+            Sound::EnemyDeath => "enemy_death",
+            Sound::PlayerHit => "player_hit",
+            Sound::ExtraLife => "extra_life",
+            Sound::StageStart => "stage_start",
+        }
+    }
+}
+
+/// Music that loops in the background of each scene
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Music {
+    Stage,
+    GameOver,
+}
+
+impl Music {
+    pub const ALL: [Music; 2] = [Music::Stage, Music::GameOver];
+
+    /// Name of the track's file under `/music`
+    pub fn name(self) -> &'static str {
+        match self {
+            Music::Stage => "stage",
+            Music::GameOver => "game_over",
+        }
+    }
+}
+
+/// Volume of each channel, from 0 to 1.
+/// Effects and music are both scaled by the master volume.
+#[derive(Clone, Copy)]
+pub struct Volumes {
+    pub master: f32,
+    pub sfx: f32,
+    pub music: f32,
+}
+
+impl Default for Volumes {
+    fn default() -> Volumes {
+        Volumes {
+            master: 1.,
+            sfx: 0.8,
+            music: 0.5,
+        }
+    }
+}
+
+/// Something that can actually make noise
+pub trait AudioBackend {
+    /// Play a copy of the sound effect, unless too many are already playing
+    fn play(&mut self, sound: Sound, volume: f32) -> GameResult<()>;
+
+    /// Start looping the given track, or stop the music if `None`
+    fn set_music(&mut self, music: Option<Music>, volume: f32) -> GameResult<()>;
+}
+
+/// Backend that plays nothing, for when there's no audio device
+pub struct NullBackend;
+
+impl AudioBackend for NullBackend {
+    fn play(&mut self, _: Sound, _: f32) -> GameResult<()> {
+        Ok(())
+    }
+
+    fn set_music(&mut self, _: Option<Music>, _: f32) -> GameResult<()> {
+        Ok(())
+    }
+}
+
+/// Backend that plays sounds through ggez
+pub struct GgezBackend {
+    // Each sound gets a few sources so copies can overlap
+    voices: HashMap<Sound, Vec<audio::Source>>,
+    tracks: HashMap<Music, audio::Source>,
+    current: Option<Music>,
+}
+
+impl GgezBackend {
+    /// Synthesize our sound effects and load any music from `/music/<name>.ogg`
+    pub fn new(ctx: &mut Context) -> GameResult<GgezBackend> {
+        let presets = sfxr::presets()?;
+
+        let mut sounds = HashMap::new();
+        for &sound in Sound::ALL.iter() {
+            let params = presets.get(sound.name()).ok_or_else(|| {
+                GameError::ConfigError(format!("no sound preset for {}", sound.name()))
+            })?;
+            let wav = sfxr::render_wav(params);
+            sounds.insert(sound, audio::SoundData::from_bytes(&wav));
+        }
+
+        // Music is optional, we just play nothing if it's missing
+        let mut music = HashMap::new();
+        for &track in Music::ALL.iter() {
+            let path = format!("/music/{}.ogg", track.name());
+            if let Ok(data) = audio::SoundData::new(ctx, path) {
+                music.insert(track, data);
+            }
+        }
+
+        GgezBackend::from_data(ctx, sounds, music)
+    }
+
+    /// Create the backend from already loaded sound data
+    pub fn from_data(
+        ctx: &mut Context,
+        sounds: HashMap<Sound, audio::SoundData>,
+        music: HashMap<Music, audio::SoundData>,
+    ) -> GameResult<GgezBackend> {
+        let mut voices = HashMap::new();
+        for (sound, data) in sounds {
+            let sources = (0..MAX_COPIES)
+                .map(|_| audio::Source::from_data(ctx, data.clone()))
+                .collect::<GameResult<_>>()?;
+            voices.insert(sound, sources);
+        }
+
+        let mut tracks = HashMap::new();
+        for (track, data) in music {
+            let mut source = audio::Source::from_data(ctx, data)?;
+            source.set_repeat(true);
+            tracks.insert(track, source);
+        }
+
+        Ok(GgezBackend {
+            voices,
+            tracks,
+            current: None,
+        })
+    }
+}
+
+impl AudioBackend for GgezBackend {
+    fn play(&mut self, sound: Sound, volume: f32) -> GameResult<()> {
+        // Grab the first free voice, if all of them are busy
+        // then there's enough copies playing already
+        let voice = self
+            .voices
+            .get_mut(&sound)
+            .and_then(|v| v.iter_mut().find(|s| !s.playing()));
+
+        if let Some(voice) = voice {
+            voice.set_volume(volume);
+            voice.play()?;
+        }
+
+        Ok(())
+    }
+
+    fn set_music(&mut self, music: Option<Music>, volume: f32) -> GameResult<()> {
+        if let Some(track) = self.current.and_then(|m| self.tracks.get_mut(&m)) {
+            track.stop();
+        }
+
+        self.current = music;
+
+        if let Some(track) = music.and_then(|m| self.tracks.get_mut(&m)) {
+            track.set_volume(volume);
+            track.play()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Plays sound effects and music at the right volume
+pub struct AudioManager {
+    backend: Box<dyn AudioBackend>,
+    volumes: Volumes,
+    music: Option<Music>,
+}
+
+impl AudioManager {
+    pub fn new(backend: Box<dyn AudioBackend>, volumes: Volumes) -> AudioManager {
+        AudioManager {
+            backend,
+            volumes,
+            music: None,
+        }
+    }
+
+    /// Audio manager that never makes a sound
+    pub fn null() -> AudioManager {
+        AudioManager::new(Box::new(NullBackend), Volumes::default())
+    }
+
+    /// Play a sound effect
+    pub fn play(&mut self, sound: Sound) -> GameResult<()> {
+        let volume = self.volumes.master * self.volumes.sfx;
+        self.backend.play(sound, volume)
+    }
+
+    /// Switch to looping a different track, does nothing if it's already playing
+    pub fn set_music(&mut self, music: Option<Music>) -> GameResult<()> {
+        if self.music == music {
+            return Ok(());
+        }
+
+        self.music = music;
+        let volume = self.volumes.master * self.volumes.music;
+        self.backend.set_music(music, volume)
+    }
+}
@@ -0,0 +1,63 @@
+//! Passes packets between two players who can both reach this machine but
+//! not each other, or who just don't want to swap addresses. Both players
+//! `--connect` to the relay, and whatever one sends the other receives.
+//!
+//! Usage: relay [address to listen on, 0.0.0.0:7000 by default]
+
+use std::env;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Where we listen if not told otherwise
+const DEFAULT_ADDRESS: &str = "0.0.0.0:7000";
+
+/// Biggest packet we'll pass on, the game never sends anything near this big
+const MAX_PACKET_SIZE: usize = 1024;
+
+/// How long a player has to go quiet before someone else can take their place
+const PLAYER_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() -> io::Result<()> {
+    let address = env::args().nth(1).unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let socket = UdpSocket::bind(&address)?;
+    println!("Relaying on {}", socket.local_addr()?);
+
+    // The two players and when we last heard from each
+    let mut players: Vec<(SocketAddr, Instant)> = vec![];
+    let mut buf = [0; MAX_PACKET_SIZE];
+
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(packet) => packet,
+            // A player going away can make the next read fail on some systems
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e),
+        };
+
+        match players.iter_mut().find(|(p, _)| *p == from) {
+            Some((_, heard)) => *heard = Instant::now(),
+            None => {
+                // Someone new can take the place of a player who's gone quiet, who might
+                // be back from a new address. Anyone else is just a stray packet.
+                if players.len() == 2 {
+                    players.sort_by_key(|&(_, heard)| heard);
+                    if players[0].1.elapsed() < PLAYER_TIMEOUT {
+                        continue;
+                    }
+                    let (gone, _) = players.remove(0);
+                    println!("{} left", gone);
+                }
+                println!("{} joined", from);
+                players.push((from, Instant::now()));
+            }
+        }
+
+        for &(player, _) in players.iter().filter(|&&(p, _)| p != from) {
+            // It's only a lost packet as far as the game's concerned
+            if let Err(e) = socket.send_to(&buf[..len], player) {
+                eprintln!("Couldn't send to {}: {}", player, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::components::{Baddy, DamagePlayer, Player, Position, Rendered, Velocity};
+use crate::entities;
+use crate::game::{self, GameOptions, InputState};
+use crate::particles::ParticlePool;
+use crate::sim::Simulation;
+use crate::systems::PLAYER_SPEED;
+
+use ggez::graphics::Rect;
+use ggez::GameResult;
+use specs::{Join, World, WorldExt};
+
+use std::io;
+
+/// How many frames ahead the bot looks for threats
+const LOOKAHEAD: u32 = 20;
+
+/// Extra room the bot tries to leave around threats
+const MARGIN: f32 = 4.;
+
+/// Where the bot likes to hang around, near the bottom like a real player
+const PREFERRED_Y: f32 = game::GAME_HEIGHT - 80.;
+
+/// Speed of the player's projectiles, for leading shots
+const PROJECTILE_SPEED: f32 = 8.;
+
+/// How often the soak test checks for leaking entities and particles
+const SOAK_CHECK_FRAMES: u64 = 600;
+
+/// Most live entities the soak test allows before calling it a leak.
+/// Busy games have well under a hundred.
+pub const SOAK_MAX_ENTITIES: usize = 500;
+
+/// Most live particles the soak test allows before calling it a leak
+pub const SOAK_MAX_PARTICLES: usize = 1000;
+
+/// Something that can hurt the player, and where it's headed
+struct Threat {
+    area: Rect,
+    vel: (f32, f32),
+}
+
+/// Plays the game by itself, producing the input a player would.
+/// Every tick it predicts where everything dangerous will be over the
+/// next few frames and picks whichever way of moving avoids it all
+/// while staying lined up under a baddy to shoot it.
+pub struct Bot {
+    // Which player the bot plays as
+    player: usize,
+}
+
+impl Bot {
+    pub fn new(player: usize) -> Bot {
+        Bot { player }
+    }
+
+    /// Decide what to press this tick
+    pub fn think(&mut self, world: &World) -> InputState {
+        let player = world.read_storage::<Player>();
+        let rendered = world.read_storage::<Rendered>();
+
+        // Nothing to do without a player
+        let me = match (&player, &rendered).join().find(|(p, _)| p.id == self.player) {
+            Some((_, r)) => r.area,
+            None => return InputState::default(),
+        };
+
+        let vel = world.read_storage::<Velocity>();
+        let damage = world.read_storage::<DamagePlayer>();
+        let threats = (&damage, &rendered, vel.maybe())
+            .join()
+            .map(|(_, r, v)| Threat {
+                area: r.area,
+                vel: v.map_or((0., 0.), |v| (v.x, v.y)),
+            }).collect::<Vec<_>>();
+
+        let target = self.target(world, me);
+
+        // Try every direction and keep the safest, most useful one
+        let mut best = (f32::INFINITY, (0., 0.));
+        for &dx in &[-1., 0., 1.] {
+            for &dy in &[-1., 0., 1.] {
+                let cost = cost(me, (dx, dy), &threats, target);
+                if cost < best.0 {
+                    best = (cost, (dx, dy));
+                }
+            }
+        }
+
+        let (dx, dy) = best.1;
+
+        // Only bother shooting when lined up
+        let center = me.x + me.w / 2.;
+        let shoot = match target {
+            Some(x) => (x - center).abs() < entities::NOOB_SIZE,
+            None => false,
+        };
+
+        InputState {
+            left: dx < 0.,
+            right: dx > 0.,
+            up: dy < 0.,
+            down: dy > 0.,
+            shoot,
+            stick: (0., 0.),
+        }
+    }
+
+    /// Where we want to be horizontally to hit the nearest baddy,
+    /// leading it by however far it'll move while our shot travels
+    fn target(&self, world: &World, me: Rect) -> Option<f32> {
+        let baddy = world.read_storage::<Baddy>();
+        let pos = world.read_storage::<Position>();
+        let vel = world.read_storage::<Velocity>();
+        let rendered = world.read_storage::<Rendered>();
+
+        let (mx, my) = (me.x + me.w / 2., me.y);
+
+        (&baddy, &pos, &rendered, vel.maybe())
+            .join()
+            // Can't shoot anything below us
+            .filter(|(_, p, _, _)| p.y < my)
+            .map(|(_, p, r, v)| {
+                let x = p.x + r.area.w / 2.;
+                let t = (my - p.y) / PROJECTILE_SPEED;
+                let lead = v.map_or(0., |v| v.x * t);
+                (x + lead, (x - mx).powi(2) + (p.y - my).powi(2))
+            }).min_by(|a, b| a.1.partial_cmp(&b.1).expect("NaN distance"))
+            .map(|(x, _)| x)
+    }
+}
+
+/// How bad it would be to keep moving in `dir` for the next few frames
+fn cost(me: Rect, dir: (f32, f32), threats: &[Threat], target: Option<f32>) -> f32 {
+    let mut danger = 0.;
+    let mut area = me;
+
+    for t in 1..=LOOKAHEAD {
+        // Move the way the movement system would, staying in bounds
+        area.x = (area.x + dir.0 * PLAYER_SPEED).min(game::GAME_WIDTH - area.w).max(0.);
+        area.y = (area.y + dir.1 * PLAYER_SPEED).min(game::GAME_HEIGHT - area.h).max(0.);
+
+        let padded = Rect::new(area.x - MARGIN, area.y - MARGIN, area.w + 2. * MARGIN, area.h + 2. * MARGIN);
+        for threat in threats {
+            let t = t as f32;
+            let future = Rect::new(
+                threat.area.x + threat.vel.0 * t,
+                threat.area.y + threat.vel.1 * t,
+                threat.area.w,
+                threat.area.h,
+            );
+
+            // Getting hit sooner is worse than getting hit later
+            if padded.overlaps(&future) {
+                danger += (LOOKAHEAD as f32 + 1. - t) * 1000.;
+            }
+        }
+    }
+
+    let center = area.x + area.w / 2.;
+    let aim = target.map_or(0., |x| (x - center).abs());
+    let drift = (area.y - PREFERRED_Y).abs() * 0.5;
+
+    danger + aim + drift
+}
+
+/// Let the bot play for `frames` frames without a window, starting a new
+/// game whenever it dies, and print how it got on. Games get consecutive
+/// seeds starting from the given one so runs can be reproduced.
+///
+/// Fails if there are ever more live entities or particles than the options
+/// allow. Each game gets a fresh world, as it does when playing for real,
+/// so this only catches leaks within a game.
+pub fn soak(options: &GameOptions, frames: u64) -> GameResult<()> {
+    let mut bot = Bot::new(0);
+    let mut seed = options.seed;
+    let mut sim = Simulation::with_rules(seed, 1, &options.rules);
+    sim.set_max_particles(options.max_particles);
+
+    // Stats for every finished game as (seed, frames survived, score)
+    let mut games = vec![];
+    let mut most_entities = 0;
+    let mut most_particles = 0;
+
+    for frame in 0..frames {
+        let input = bot.think(&sim.world);
+        sim.set_input(0, input);
+        sim.tick();
+
+        if frame % SOAK_CHECK_FRAMES == 0 {
+            let entities = sim.world.entities().join().count();
+            let particles = sim.world.read_resource::<ParticlePool>().len();
+            most_entities = most_entities.max(entities);
+            most_particles = most_particles.max(particles);
+
+            if entities > options.soak_max_entities || particles > options.soak_max_particles {
+                return Err(io::Error::other(format!(
+                    "seed {} frame {}: {} live entities and {} particles, more than the {} and {} allowed",
+                    seed,
+                    sim.frame(),
+                    entities,
+                    particles,
+                    options.soak_max_entities,
+                    options.soak_max_particles
+                ))
+                .into());
+            }
+        }
+
+        if sim.game_over() {
+            println!(
+                "seed {}: survived {} frames, scored {}, difficulty level {:.2}",
+                seed,
+                sim.frame(),
+                sim.score(0),
+                sim.director().level()
+            );
+            games.push((seed, sim.frame(), sim.score(0)));
+
+            seed = seed.wrapping_add(1);
+            sim = Simulation::with_rules(seed, 1, &options.rules);
+            sim.set_max_particles(options.max_particles);
+        }
+    }
+
+    let played = games.len().max(1) as f32;
+    let survived = games.iter().map(|g| g.1).sum::<u64>() as f32 / played;
+    let score = games.iter().map(|g| g.2 as f32).sum::<f32>() / played;
+
+    println!("{} frames, {} games finished", frames, games.len());
+    println!(
+        "average survival {:.1}s, average score {:.0}",
+        survived / game::DESIRED_FPS as f32,
+        score
+    );
+    println!("most live entities: {}, most live particles: {}", most_entities, most_particles);
+    println!("difficulty level of the game in progress: {:.2}", sim.director().level());
+
+    // Designers can see how the director treated the last game
+    if let Some(path) = &options.director_log {
+        sim.director().save_log(path)?;
+    }
+
+    Ok(())
+}
@@ -1,23 +1,129 @@
+use crate::particles;
+use crate::stages;
+
 use ggez::graphics;
 use specs::*;
 
+use std::collections::HashMap;
+
 /// Registers all our components with Specs.
 /// Make sure to modify this if any components
 /// are added or removed.
 pub fn register_components(world: &mut World) {
+    world.register::<Animation>();
     world.register::<Baddy>();
+    world.register::<BossBaddy>();
+    world.register::<ChallengeBaddy>();
     world.register::<DamageBaddy>();
     world.register::<DamagePlayer>();
+    world.register::<Dive>();
+    world.register::<Emitter>();
     world.register::<NoobBaddy>();
+    world.register::<OneShot>();
     world.register::<Oscillates>();
     world.register::<Player>();
     world.register::<Position>();
+    world.register::<PreviousPosition>();
     world.register::<Rendered>();
     world.register::<ScoreText>();
     world.register::<Velocity>();
     world.register::<WaverBaddy>();
 }
 
+/// A single frame of an animation clip
+#[derive(Clone, Copy)]
+pub struct AnimFrame {
+    pub colour: (u8, u8, u8),
+}
+
+/// How an animation clip behaves once it reaches its last frame
+#[derive(Clone, Copy, PartialEq)]
+pub enum PlayMode {
+    Loop,
+    Once,
+    PingPong,
+}
+
+/// A named sequence of frames, each shown for `frame_duration` frames
+#[derive(Clone)]
+pub struct AnimationClip {
+    pub frames: Vec<AnimFrame>,
+    pub frame_duration: u64,
+    pub mode: PlayMode,
+    // Clip to switch to once a `Once` clip ends
+    pub next: Option<&'static str>,
+}
+
+impl AnimationClip {
+    /// Which frame should be shown `elapsed` frames into the clip.
+    /// Returns `None` once a `Once` clip has run its course.
+    pub fn frame_at(&self, elapsed: u64) -> Option<&AnimFrame> {
+        let len = self.frames.len() as u64;
+        if len == 0 {
+            return None;
+        }
+
+        let step = elapsed / self.frame_duration.max(1);
+        let idx = match self.mode {
+            PlayMode::Loop => step % len,
+            PlayMode::Once if step >= len => return None,
+            PlayMode::Once => step,
+            PlayMode::PingPong => {
+                // Go forward then backward without repeating the end frames
+                let period = (2 * len - 2).max(1);
+                let step = step % period;
+                if step < len {
+                    step
+                } else {
+                    period - step
+                }
+            }
+        };
+
+        self.frames.get(idx as usize)
+    }
+}
+
+/// Marks entities whose look is driven by a set of animation clips
+#[derive(Clone, Component)]
+#[storage(VecStorage)]
+pub struct Animation {
+    pub clips: HashMap<&'static str, AnimationClip>,
+    pub current: &'static str,
+    pub started: u64,
+    pub finished: bool,
+}
+
+impl Animation {
+    /// Create an animation that starts playing `current` at `frame`
+    pub fn new(current: &'static str, frame: u64) -> Animation {
+        Animation {
+            clips: HashMap::new(),
+            current,
+            started: frame,
+            finished: false,
+        }
+    }
+
+    /// Add a named clip
+    pub fn with_clip(mut self, name: &'static str, clip: AnimationClip) -> Animation {
+        self.clips.insert(name, clip);
+        self
+    }
+
+    /// Switch to the named clip, restarting it from its first frame
+    pub fn play(&mut self, name: &'static str, frame: u64) {
+        self.current = name;
+        self.started = frame;
+        self.finished = false;
+    }
+}
+
+/// Sent whenever a `Once` animation clip reaches its end
+pub struct AnimationEnded {
+    pub entity: Entity,
+}
+
 /// Marks a baddy entity
 #[derive(Clone, Component, Copy)]
 #[storage(VecStorage)]
@@ -27,30 +133,79 @@ pub struct Baddy {
     pub score: u32,
 }
 
-/// Marks entities that can damage baddies
-#[derive(Component, Default)]
-#[storage(NullStorage)]
-pub struct DamageBaddy;
+/// Marks the bosses in a boss rush, and which one it is counting from 0
+#[derive(Clone, Component, Copy)]
+#[storage(VecStorage)]
+pub struct BossBaddy {
+    pub number: u32,
+}
+
+/// Marks baddies flying through a challenging stage,
+/// which group they're in and how they fly
+#[derive(Clone, Component, Copy)]
+#[storage(VecStorage)]
+pub struct ChallengeBaddy {
+    pub group: usize,
+    pub pattern: stages::Pattern,
+}
+
+/// Marks entities that can damage baddies,
+/// and which player gets the credit for it
+#[derive(Clone, Component, Copy)]
+#[storage(VecStorage)]
+pub struct DamageBaddy {
+    pub player: usize,
+}
 
 /// Marks entities that can damage the player
-#[derive(Component, Default)]
+#[derive(Clone, Component, Copy, Default)]
 #[storage(NullStorage)]
 pub struct DamagePlayer;
 
+/// Marks baddies that break off to dive at the players, what they're
+/// worth shot down mid-dive, and whether they've started diving yet
+#[derive(Clone, Component, Copy)]
+#[storage(VecStorage)]
+pub struct Dive {
+    pub score: u32,
+    pub diving: bool,
+}
+
+/// Marks entities that continuously give off particles
+#[derive(Clone, Component, Copy)]
+#[storage(VecStorage)]
+pub struct Emitter {
+    pub config: particles::EmitterConfig,
+    // Where to emit from, relative to the entity's position
+    pub offset: (f32, f32),
+    // Fractional particles left over from previous frames
+    pub carry: f32,
+}
+
 /// Marks which entities are Noob baddies
-#[derive(Component, Default)]
+#[derive(Clone, Component, Copy, Default)]
 #[storage(NullStorage)]
 pub struct NoobBaddy;
 
+/// Marks entities that should be removed once their animation ends
+#[derive(Clone, Component, Copy, Default)]
+#[storage(NullStorage)]
+pub struct OneShot;
+
 /// Marks how many times the Noob baddy oscillates
-#[derive(Component)]
+#[derive(Clone, Component, Copy)]
 #[storage(VecStorage)]
 pub struct Oscillates(pub u8);
 
-/// Marks the player entity so we can control it.
-#[derive(Component, Default)]
-#[storage(NullStorage)]
-pub struct Player;
+/// Marks the player entities so we can control them.
+#[derive(Clone, Component, Copy)]
+#[storage(VecStorage)]
+pub struct Player {
+    // Which player this is, counting from 0
+    pub id: usize,
+    // What was the last frame where they shot a projectile
+    pub last_shot_frame: u64,
+}
 
 /// Marks entities with a position (e.g. player)
 #[derive(Clone, Component, Copy)]
@@ -60,8 +215,17 @@ pub struct Position {
     pub y: f32,
 }
 
+/// Where an entity was before the last time it moved,
+/// so it can be drawn part way between ticks
+#[derive(Clone, Component, Copy)]
+#[storage(VecStorage)]
+pub struct PreviousPosition {
+    pub x: f32,
+    pub y: f32,
+}
+
 /// Marks entities that are to be rendered onscreen
-#[derive(Component)]
+#[derive(Clone, Component, Copy)]
 #[storage(VecStorage)]
 pub struct Rendered {
     pub area: graphics::Rect,
@@ -73,9 +237,22 @@ pub struct Rendered {
 #[storage(VecStorage)]
 pub struct ScoreText {
     pub score: u32,
+    // How long a chain the kill was part of
+    pub chain: u32,
     pub frame: u64,
 }
 
+impl ScoreText {
+    /// What the popup says, with the chain if there was one going
+    pub fn label(&self) -> String {
+        if self.chain > 1 {
+            format!("{}\n{} CHAIN", self.score, self.chain)
+        } else {
+            self.score.to_string()
+        }
+    }
+}
+
 /// Marks entities with a velocity
 #[derive(Clone, Component, Copy)]
 #[storage(VecStorage)]
@@ -89,6 +266,8 @@ pub struct Velocity {
 #[derive(Clone, Component, Copy)]
 #[storage(VecStorage)]
 pub struct WaverBaddy {
+    // Which wave it's part of
+    pub wave: u64,
     pub rank: u64,
     pub pos: Position,
     pub vel: Velocity,
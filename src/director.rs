@@ -0,0 +1,397 @@
+use crate::game::DESIRED_FPS;
+
+use ggez::{GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// How far one of the director's knobs can be turned either way
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Limits {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Limits {
+    /// The knob's setting at `level`, going from `min` at -1
+    /// through `normal` at 0 up to `max` at 1
+    fn at(self, normal: f32, level: f32) -> f32 {
+        if level >= 0. {
+            normal + (self.max - normal) * level
+        } else {
+            normal + (normal - self.min) * level
+        }
+    }
+}
+
+/// Tuning for the director, loaded from a TOML file with `--director`.
+/// Anything left out keeps its default. Field order matters, see `Rules`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct DirectorConfig {
+    // Whether the director changes anything at all
+    pub enabled: bool,
+    // Seconds between each time it looks at how the players are doing
+    pub interval: f32,
+    // Seconds players have to go without getting hit before being pushed harder
+    pub warmup: f32,
+    // How far the level moves each time, out of the -1 to 1 it can go
+    pub step: f32,
+    // Players hitting at least this much of what they shoot are doing well
+    pub target_hit_ratio: f32,
+    // Players losing more health than this a minute are struggling
+    pub max_damage_per_minute: f32,
+    // How far each of the knobs in `Difficulty` can be turned
+    pub spawn_rate: Limits,
+    pub bullet_speed: Limits,
+    pub enemy_health: Limits,
+    pub aggression: Limits,
+}
+
+impl Default for DirectorConfig {
+    fn default() -> DirectorConfig {
+        DirectorConfig {
+            enabled: true,
+            interval: 10.,
+            warmup: 30.,
+            step: 0.25,
+            target_hit_ratio: 0.5,
+            max_damage_per_minute: 3.,
+            spawn_rate: Limits { min: 0.6, max: 2. },
+            bullet_speed: Limits { min: 0.75, max: 1.75 },
+            enemy_health: Limits { min: 0., max: 2. },
+            aggression: Limits { min: 0.5, max: 2.5 },
+        }
+    }
+}
+
+impl DirectorConfig {
+    /// Load the director's tuning from a TOML file
+    pub fn load(path: &Path) -> GameResult<DirectorConfig> {
+        let contents = std::fs::read_to_string(path)?;
+        DirectorConfig::from_toml(&contents)
+    }
+
+    /// Read the tuning back from `to_toml`
+    pub fn from_toml(contents: &str) -> GameResult<DirectorConfig> {
+        toml::from_str(contents).map_err(|e| GameError::ConfigError(format!("bad director config: {}", e)))
+    }
+
+    /// Tuning that leaves the game exactly as it always was
+    pub fn disabled() -> DirectorConfig {
+        DirectorConfig {
+            enabled: false,
+            ..DirectorConfig::default()
+        }
+    }
+}
+
+/// Where the director's knobs are set right now.
+/// The default is the game as it always was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Difficulty {
+    // How often baddies spawn, compared to normal
+    pub spawn_rate: f32,
+    // How fast baddies' projectiles go, compared to normal
+    pub bullet_speed: f32,
+    // Extra hits baddies take
+    pub enemy_health: u64,
+    // How often baddies shoot, compared to normal
+    pub aggression: f32,
+}
+
+impl Default for Difficulty {
+    fn default() -> Difficulty {
+        Difficulty {
+            spawn_rate: 1.,
+            bullet_speed: 1.,
+            enemy_health: 0,
+            aggression: 1.,
+        }
+    }
+}
+
+/// What the director decided to do about how the players are doing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    // They're struggling, go easier on them
+    Ease,
+    // About right, leave things be
+    Hold,
+    // They're cruising, push them harder
+    Push,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Verdict::Ease => "ease",
+            Verdict::Hold => "hold",
+            Verdict::Push => "push",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One look the director took at the game, and what it did about it
+#[derive(Clone, Debug)]
+pub struct Decision {
+    pub frame: u64,
+    // Fraction of shots that hit something since the last look
+    pub hit_ratio: f32,
+    // Health lost since the last look, scaled to a minute
+    pub damage_per_minute: f32,
+    // Seconds since anyone last got hit
+    pub alive: f32,
+    pub verdict: Verdict,
+    // Where the level and knobs ended up
+    pub level: f32,
+    pub difficulty: Difficulty,
+}
+
+/// Keeps an eye on how the players are doing and turns the difficulty
+/// up or down to match, within the configured limits. It only looks at
+/// the game itself, never the clock, see `Simulation` for why.
+#[derive(Clone, Default)]
+pub struct Director {
+    config: DirectorConfig,
+    // From -1 for easiest to 1 for hardest, 0 being normal
+    level: f32,
+    difficulty: Difficulty,
+    // Shots and hits when the director last looked
+    shots: u64,
+    hits: u64,
+    // Health lost since it last looked
+    damage: f32,
+    // Everyone's health last frame, to notice them getting hit
+    health: Option<f32>,
+    // Frame anyone last got hit on
+    last_hurt: u64,
+    // Frame the director last looked on
+    looked: u64,
+    // Baddies spawn on a clock running at the spawn rate rather than by frame
+    spawn_clock: f64,
+    // Every decision made so far
+    log: Vec<Decision>,
+}
+
+impl Director {
+    pub fn new(config: DirectorConfig) -> Director {
+        Director {
+            config,
+            ..Director::default()
+        }
+    }
+
+    /// Where the knobs are set right now
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    /// From -1 for easiest to 1 for hardest, 0 being normal
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Move the spawn clock on a frame, `rate` times as fast on top of
+    /// whatever the director's set, returning the spawn ticks it passed.
+    /// Normally that's just the frame itself.
+    pub fn spawn_ticks(&mut self, rate: f32) -> std::ops::Range<u64> {
+        let start = self.spawn_clock as u64;
+        self.spawn_clock += f64::from(self.difficulty.spawn_rate * rate);
+        start..self.spawn_clock as u64
+    }
+
+    /// Keep track of the game, and every so often decide what to do about it
+    pub fn update(&mut self, frame: u64, shots: u64, hits: u64, health: f32) {
+        if let Some(last) = self.health {
+            if health < last {
+                self.damage += last - health;
+                self.last_hurt = frame;
+            }
+        }
+        self.health = Some(health);
+
+        let interval = (self.config.interval * DESIRED_FPS as f32).max(1.) as u64;
+        if !self.config.enabled || frame - self.looked < interval {
+            return;
+        }
+        self.looked = frame;
+
+        let shot = shots - self.shots;
+        let hit_ratio = if shot > 0 { (hits - self.hits) as f32 / shot as f32 } else { 0. };
+        let damage_per_minute = self.damage * 60. / self.config.interval;
+        let alive = (frame - self.last_hurt) as f32 / DESIRED_FPS as f32;
+
+        let verdict = if damage_per_minute > self.config.max_damage_per_minute {
+            Verdict::Ease
+        } else if alive >= self.config.warmup && hit_ratio >= self.config.target_hit_ratio {
+            Verdict::Push
+        } else {
+            Verdict::Hold
+        };
+
+        match verdict {
+            Verdict::Ease => self.level -= self.config.step,
+            Verdict::Push => self.level += self.config.step,
+            Verdict::Hold => {}
+        }
+        self.level = self.level.clamp(-1., 1.);
+
+        let config = &self.config;
+        self.difficulty = Difficulty {
+            spawn_rate: config.spawn_rate.at(1., self.level),
+            bullet_speed: config.bullet_speed.at(1., self.level),
+            enemy_health: config.enemy_health.at(0., self.level).round().max(0.) as u64,
+            aggression: config.aggression.at(1., self.level).max(0.01),
+        };
+
+        self.log.push(Decision {
+            frame,
+            hit_ratio,
+            damage_per_minute,
+            alive,
+            verdict,
+            level: self.level,
+            difficulty: self.difficulty,
+        });
+
+        self.shots = shots;
+        self.hits = hits;
+        self.damage = 0.;
+    }
+
+    /// Write every decision out as CSV, for designers to look over
+    pub fn write_log<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "frame,hit_ratio,damage_per_minute,alive,verdict,level,spawn_rate,bullet_speed,enemy_health,aggression"
+        )?;
+
+        for d in &self.log {
+            writeln!(
+                out,
+                "{},{:.3},{:.2},{:.1},{},{:.2},{:.2},{:.2},{},{:.2}",
+                d.frame,
+                d.hit_ratio,
+                d.damage_per_minute,
+                d.alive,
+                d.verdict,
+                d.level,
+                d.difficulty.spawn_rate,
+                d.difficulty.bullet_speed,
+                d.difficulty.enemy_health,
+                d.difficulty.aggression,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Save the decisions to a CSV file
+    pub fn save_log(&self, path: &Path) -> GameResult<()> {
+        let mut out = io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_log(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::RangeInclusive;
+
+    /// Have the director watch players firing a shot every frame and hitting `accuracy`
+    /// of them, losing a point of health every `hurt_every` frames if they get hurt at all
+    fn play(director: &mut Director, frames: RangeInclusive<u64>, accuracy: f32, hurt_every: Option<u64>) {
+        for frame in frames {
+            let hits = (frame as f32 * accuracy) as u64;
+            let health = 100. - hurt_every.map_or(0, |every| frame / every) as f32;
+            director.update(frame, frame, hits, health);
+        }
+    }
+
+    fn verdicts(director: &Director) -> Vec<Verdict> {
+        director.log.iter().map(|d| d.verdict).collect()
+    }
+
+    #[test]
+    fn pushes_players_doing_well_once_warmed_up() {
+        let mut director = Director::new(DirectorConfig::default());
+
+        // Looks every 10 seconds, but only pushes after 30 seconds without getting hurt
+        play(&mut director, 1..=1800, 1., None);
+        assert_eq!(verdicts(&director), [Verdict::Hold, Verdict::Hold, Verdict::Push]);
+        assert_eq!(director.level(), 0.25);
+
+        // A quarter of the way to 0 to 2 extra hits rounds up to one
+        let difficulty = director.difficulty();
+        assert_eq!(difficulty.enemy_health, 1);
+        assert_eq!(difficulty.spawn_rate, 1.25);
+
+        // And it never goes past the hardest setting
+        play(&mut director, 1801..=9000, 1., None);
+        assert_eq!(director.level(), 1.);
+        assert_eq!(
+            director.difficulty(),
+            Difficulty {
+                spawn_rate: 2.,
+                bullet_speed: 1.75,
+                enemy_health: 2,
+                aggression: 2.5,
+            }
+        );
+    }
+
+    #[test]
+    fn holds_for_players_missing_their_shots() {
+        let mut director = Director::new(DirectorConfig::default());
+
+        play(&mut director, 1..=6000, 0.25, None);
+        assert!(verdicts(&director).iter().all(|&v| v == Verdict::Hold));
+        assert_eq!(director.difficulty(), Difficulty::default());
+    }
+
+    #[test]
+    fn eases_off_players_getting_hurt() {
+        let mut director = Director::new(DirectorConfig::default());
+
+        // Getting hurt every second is well over the damage a minute it allows,
+        // however well they're shooting
+        play(&mut director, 1..=3000, 1., Some(60));
+        assert_eq!(verdicts(&director), [Verdict::Ease; 5]);
+        assert_eq!(director.level(), -1.);
+        assert_eq!(
+            director.difficulty(),
+            Difficulty {
+                spawn_rate: 0.6,
+                bullet_speed: 0.75,
+                enemy_health: 0,
+                aggression: 0.5,
+            }
+        );
+    }
+
+    #[test]
+    fn disabled_director_leaves_the_game_alone() {
+        let mut director = Director::new(DirectorConfig::disabled());
+
+        play(&mut director, 1..=9000, 1., None);
+        assert!(director.log.is_empty());
+        assert_eq!(director.difficulty(), Difficulty::default());
+    }
+
+    #[test]
+    fn spawn_ticks_keep_up_with_the_spawn_rate() {
+        let mut director = Director::new(DirectorConfig::default());
+
+        let ticks = |director: &mut Director, rate| (0..100).map(|_| director.spawn_ticks(rate).count()).sum::<usize>();
+        assert_eq!(ticks(&mut director, 1.), 100);
+        assert_eq!(ticks(&mut director, 0.5), 50);
+        assert_eq!(ticks(&mut director, 1.5), 150);
+    }
+}
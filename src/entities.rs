@@ -1,7 +1,10 @@
 use crate::components;
 use crate::game;
+use crate::particles;
+use crate::stages;
 
-use rand::{self, Rng};
+use rand::rngs::StdRng;
+use rand::Rng;
 use specs::*;
 
 // Size of player square
@@ -13,6 +16,12 @@ pub const NOOB_SIZE: f32 = 20.;
 // Size of Wave baddy square
 pub const WAVER_SIZE: f32 = 10.;
 
+// Size of challenging stage baddy square
+pub const CHALLENGE_SIZE: f32 = 14.;
+
+// Size of boss square
+pub const BOSS_SIZE: f32 = 48.;
+
 // Width of player's projectile
 pub const PLAYER_PROJ_WIDTH: f32 = 4.;
 // Height of player's projectile
@@ -21,12 +30,41 @@ pub const PLAYER_PROJ_HEIGHT: f32 = 8.;
 // Size of noob's projectile
 pub const NOOB_PROJ_SIZE: f32 = 6.;
 
-/// Creates the player entity and registers it with our world
-pub fn create_player(world: &mut World) {
-    // The player has a position and starts out
-    // at the center of the game area
+// Size of explosion square
+pub const EXPLOSION_SIZE: f32 = 20.;
+
+// Colour of each player's ship, the first player's is the original grey
+pub const PLAYER_COLOURS: [(u8, u8, u8); 4] = [
+    (0xAA, 0xAA, 0xAA),
+    (0x44, 0xAA, 0xFF),
+    (0xFF, 0xAA, 0x44),
+    (0x66, 0xDD, 0x66),
+];
+
+/// Build a clip where each frame only changes colour
+fn colour_clip(
+    colours: &[(u8, u8, u8)],
+    frame_duration: u64,
+    mode: components::PlayMode,
+    next: Option<&'static str>,
+) -> components::AnimationClip {
+    components::AnimationClip {
+        frames: colours
+            .iter()
+            .map(|&colour| components::AnimFrame { colour })
+            .collect(),
+        frame_duration,
+        mode,
+        next,
+    }
+}
+
+/// Creates player `id` of `count` and registers it with our world
+pub fn create_player(world: &mut World, id: usize, count: usize) {
+    // The player has a position and starts out in the middle of the
+    // game area, or spread out evenly across it if there are more of them
     let pos = components::Position {
-        x: game::GAME_WIDTH / 2.,
+        x: game::GAME_WIDTH * (id + 1) as f32 / (count + 1) as f32,
         y: game::GAME_HEIGHT / 2.,
     };
 
@@ -35,12 +73,19 @@ pub fn create_player(world: &mut World) {
 
     // Actually mark this entity as the player so
     // we can control it
-    let player = components::Player;
+    let player = components::Player { id, last_shot_frame: 0 };
 
     // The player is also visible on screen
     let rendered = components::Rendered {
         area: [pos.x, pos.y, PLAYER_SIZE, PLAYER_SIZE].into(),
-        colour: (0xAA, 0xAA, 0xAA),
+        colour: PLAYER_COLOURS[id % PLAYER_COLOURS.len()],
+    };
+
+    // Thrusters trail out from the bottom of the ship
+    let thruster = components::Emitter {
+        config: particles::THRUSTER,
+        offset: (PLAYER_SIZE / 2., PLAYER_SIZE),
+        carry: 0.,
     };
 
     world
@@ -49,11 +94,12 @@ pub fn create_player(world: &mut World) {
         .with(pos)
         .with(vel)
         .with(rendered)
+        .with(thruster)
         .build();
 }
 
-/// Create a projectile entity shot by the player
-pub fn create_player_projectile(e: Entity, p_pos: components::Position, update: &LazyUpdate) {
+/// Create a projectile entity shot by player `id`
+pub fn create_player_projectile(e: Entity, id: usize, p_pos: components::Position, update: &LazyUpdate) {
     // Set projectile's position based on player's position
     let pos = components::Position {
         x: p_pos.x + PLAYER_SIZE / 2. - PLAYER_PROJ_WIDTH / 2.,
@@ -70,7 +116,7 @@ pub fn create_player_projectile(e: Entity, p_pos: components::Position, update:
     };
 
     // Player's projectiles can hurt baddies
-    let damage = components::DamageBaddy;
+    let damage = components::DamageBaddy { player: id };
 
     update.insert(e, damage);
     update.insert(e, pos);
@@ -78,20 +124,37 @@ pub fn create_player_projectile(e: Entity, p_pos: components::Position, update:
     update.insert(e, rendered);
 }
 
-/// Create a projectile entity shot by a Noob baddy
-pub fn create_noob_projectile(e: Entity, b_pos: components::Position, update: &LazyUpdate) {
-    let mut rng = rand::thread_rng();
-
+/// Create a projectile entity shot by a Noob baddy, going `speed` times
+/// as fast as normal and straight at `target` if it's aiming
+pub fn create_noob_projectile(
+    e: Entity,
+    b_pos: components::Position,
+    target: Option<components::Position>,
+    speed: f32,
+    rng: &mut StdRng,
+    update: &LazyUpdate,
+) {
     // Set projectile's position based on player's position
     let pos = components::Position {
         x: b_pos.x + NOOB_SIZE / 2.,
         y: b_pos.y + NOOB_SIZE + 2.,
     };
 
-    // Set the projectile's velocity
-    let vel = components::Velocity {
-        x: rng.gen_range(0, 2) as f32,
-        y: 4.,
+    // Set the projectile's velocity, usually drifting a little to the side
+    let drift = rng.gen_range(0, 2) as f32;
+    let vel = match target {
+        Some(target) => {
+            let (dx, dy) = (target.x + PLAYER_SIZE / 2. - pos.x, target.y + PLAYER_SIZE / 2. - pos.y);
+            let len = (dx * dx + dy * dy).sqrt().max(1.);
+            components::Velocity {
+                x: dx / len * 4. * speed,
+                y: dy / len * 4. * speed,
+            }
+        }
+        None => components::Velocity {
+            x: drift * speed,
+            y: 4. * speed,
+        },
     };
 
     // Set the projectile's size and colour
@@ -109,20 +172,25 @@ pub fn create_noob_projectile(e: Entity, b_pos: components::Position, update: &L
     update.insert(e, rendered);
 }
 
-/// Creates a new `Noob` baddy
-pub fn create_noob_baddy(e: Entity, update: &LazyUpdate) {
-    let mut rng = rand::thread_rng();
-
+/// Creates a new `Noob` baddy that takes `extra_health` more hits
+/// than usual and flies `speed` times as fast
+pub fn create_noob_baddy(e: Entity, extra_health: u64, speed: f32, rng: &mut StdRng, update: &LazyUpdate) {
     // Mark it as a Noob
     let noob = components::NoobBaddy;
 
     // and a baddy entity in general with age, health and score
     let baddy = components::Baddy {
         age: 0,
-        health: 3,
+        health: 3 + extra_health,
         score: 100,
     };
 
+    // Once they're done weaving about they dive at the players, worth double on the way down
+    let dive = components::Dive {
+        score: 200,
+        diving: false,
+    };
+
     // Choose the Noob's starting position
     let pos = components::Position {
         x: if rng.gen::<bool>() {
@@ -135,7 +203,7 @@ pub fn create_noob_baddy(e: Entity, update: &LazyUpdate) {
 
     // Noobs only move side to side
     let vel = components::Velocity {
-        x: rng.gen_range(1u8, 4) as f32,
+        x: rng.gen_range(1u8, 4) as f32 * speed,
         y: 0.,
     };
 
@@ -148,11 +216,33 @@ pub fn create_noob_baddy(e: Entity, update: &LazyUpdate) {
     // Set how many times it oscillates
     let oscs = components::Oscillates(rng.gen_range(1, 4));
 
+    // Noobs flap their wings and flash white when hit
+    let anim = components::Animation::new("idle", 0)
+        .with_clip(
+            "idle",
+            colour_clip(
+                &[(0xDD, 0x66, 0x33), (0xBB, 0x55, 0x22)],
+                12,
+                components::PlayMode::Loop,
+                None,
+            ),
+        ).with_clip(
+            "hit",
+            colour_clip(
+                &[(0xFF, 0xFF, 0xFF), (0xDD, 0x66, 0x33), (0xFF, 0xFF, 0xFF)],
+                3,
+                components::PlayMode::Once,
+                Some("idle"),
+            ),
+        );
+
     // Noobs can hurt the player
     let damage = components::DamagePlayer;
 
+    update.insert(e, anim);
     update.insert(e, baddy);
     update.insert(e, damage);
+    update.insert(e, dive);
     update.insert(e, noob);
     update.insert(e, pos);
     update.insert(e, vel);
@@ -160,10 +250,18 @@ pub fn create_noob_baddy(e: Entity, update: &LazyUpdate) {
     update.insert(e, oscs);
 }
 
-/// Creates a new `Waver` baddy
-pub fn create_waver_baddy(e: Entity, base: Option<components::WaverBaddy>, update: &LazyUpdate) {
-    let mut rng = rand::thread_rng();
-
+/// Creates a new `Waver` baddy that takes `extra_health` more hits
+/// than usual and flies `speed` times as fast. Without a `base` to
+/// follow it leads a new wave, known as `wave`.
+pub fn create_waver_baddy(
+    e: Entity,
+    base: Option<components::WaverBaddy>,
+    wave: u64,
+    extra_health: u64,
+    speed: f32,
+    rng: &mut StdRng,
+    update: &LazyUpdate,
+) {
     let start_left = rng.gen::<bool>();
 
     // Choose the Waver's starting position and velocity
@@ -180,8 +278,8 @@ pub fn create_waver_baddy(e: Entity, base: Option<components::WaverBaddy>, updat
                 y: rng.gen_range(50u8, 149) as f32,
             },
             components::Velocity {
-                x: if start_left { 4. } else { -4. },
-                y: 8.,
+                x: if start_left { 4. * speed } else { -4. * speed },
+                y: 8. * speed,
             },
         )
     });
@@ -193,18 +291,30 @@ pub fn create_waver_baddy(e: Entity, base: Option<components::WaverBaddy>, updat
     };
 
     // Mark it as a Waver
-    let waver = components::WaverBaddy { rank, pos, vel };
+    let waver = components::WaverBaddy { wave, rank, pos, vel };
 
     // and a baddy entity in general with age, health and score
     let baddy = components::Baddy {
         age: 0,
-        health: 1,
+        health: 1 + extra_health,
         score: 10,
     };
 
+    // Wavers shimmer as they fly
+    let anim = components::Animation::new("idle", 0).with_clip(
+        "idle",
+        colour_clip(
+            &[(0xFF, 0x00, 0xFF), (0xCC, 0x33, 0xFF), (0x99, 0x66, 0xFF)],
+            6,
+            components::PlayMode::PingPong,
+            None,
+        ),
+    );
+
     // Wavers can hurt the player
     let damage = components::DamagePlayer;
 
+    update.insert(e, anim);
     update.insert(e, baddy);
     update.insert(e, damage);
     update.insert(e, pos);
@@ -213,16 +323,172 @@ pub fn create_waver_baddy(e: Entity, base: Option<components::WaverBaddy>, updat
     update.insert(e, waver);
 }
 
-/// Creates the score popup after killing a baddy
+/// Creates a baddy that flies through a challenging stage with
+/// the rest of its group, not shooting or hurting anyone
+pub fn create_challenge_baddy(e: Entity, group: usize, speed: f32, update: &LazyUpdate) {
+    let pattern = stages::Pattern::for_group(group);
+
+    // Each pattern starts off somewhere different
+    let pos = match pattern {
+        stages::Pattern::WeaveRight => components::Position {
+            x: 1. - CHALLENGE_SIZE,
+            y: 120.,
+        },
+        stages::Pattern::WeaveLeft => components::Position {
+            x: game::GAME_WIDTH - 1.,
+            y: 200.,
+        },
+        stages::Pattern::Loop => components::Position {
+            x: 240.,
+            y: 1. - CHALLENGE_SIZE,
+        },
+    };
+
+    let vel = pattern.velocity(0);
+    let vel = components::Velocity {
+        x: vel.x * speed,
+        y: vel.y * speed,
+    };
+
+    let challenge = components::ChallengeBaddy { group, pattern };
+
+    let baddy = components::Baddy {
+        age: 0,
+        health: 1,
+        score: 100,
+    };
+
+    let rendered = components::Rendered {
+        area: [pos.x, pos.y, CHALLENGE_SIZE, CHALLENGE_SIZE].into(),
+        colour: (0x33, 0xDD, 0xDD),
+    };
+
+    // They glint as they fly
+    let anim = components::Animation::new("idle", 0).with_clip(
+        "idle",
+        colour_clip(
+            &[(0x33, 0xDD, 0xDD), (0x99, 0xFF, 0xFF)],
+            8,
+            components::PlayMode::Loop,
+            None,
+        ),
+    );
+
+    update.insert(e, anim);
+    update.insert(e, baddy);
+    update.insert(e, challenge);
+    update.insert(e, pos);
+    update.insert(e, vel);
+    update.insert(e, rendered);
+}
+
+/// Creates boss `number` of a boss rush, each tougher and worth more than
+/// the last. Like other baddies it takes `extra_health` more hits than
+/// usual and flies `speed` times as fast.
+pub fn create_boss_baddy(e: Entity, number: u32, extra_health: u64, speed: f32, update: &LazyUpdate) {
+    let boss = components::BossBaddy { number };
+
+    let baddy = components::Baddy {
+        age: 0,
+        health: 20 + 10 * u64::from(number) + extra_health,
+        score: 1000 * (number + 1),
+    };
+
+    // Bosses come down from the top middle
+    let pos = components::Position {
+        x: (game::GAME_WIDTH - BOSS_SIZE) / 2.,
+        y: 1. - BOSS_SIZE,
+    };
+
+    let vel = components::Velocity { x: 0., y: speed };
+
+    let rendered = components::Rendered {
+        area: [pos.x, pos.y, BOSS_SIZE, BOSS_SIZE].into(),
+        colour: (0x22, 0xBB, 0x66),
+    };
+
+    // Bosses throb and flash white when hit, like noobs
+    let anim = components::Animation::new("idle", 0)
+        .with_clip(
+            "idle",
+            colour_clip(
+                &[(0x22, 0xBB, 0x66), (0x22, 0x99, 0xAA), (0x66, 0x44, 0xCC)],
+                10,
+                components::PlayMode::PingPong,
+                None,
+            ),
+        ).with_clip(
+            "hit",
+            colour_clip(
+                &[(0xFF, 0xFF, 0xFF), (0x22, 0xBB, 0x66)],
+                3,
+                components::PlayMode::Once,
+                Some("idle"),
+            ),
+        );
+
+    // Flying into a boss hurts
+    let damage = components::DamagePlayer;
+
+    update.insert(e, anim);
+    update.insert(e, baddy);
+    update.insert(e, boss);
+    update.insert(e, damage);
+    update.insert(e, pos);
+    update.insert(e, vel);
+    update.insert(e, rendered);
+}
+
+/// Creates a short-lived explosion centered where a baddy died
+pub fn create_explosion(e: Entity, center: components::Position, frame: u64, update: &LazyUpdate) {
+    let pos = components::Position {
+        x: center.x - EXPLOSION_SIZE / 2.,
+        y: center.y - EXPLOSION_SIZE / 2.,
+    };
+
+    // The explosion fades out from yellow to dark red
+    let anim = components::Animation::new("explode", frame).with_clip(
+        "explode",
+        colour_clip(
+            &[
+                (0xFF, 0xFF, 0x99),
+                (0xFF, 0xDD, 0x33),
+                (0xFF, 0x88, 0x00),
+                (0xCC, 0x33, 0x00),
+                (0x66, 0x11, 0x00),
+            ],
+            4,
+            components::PlayMode::Once,
+            None,
+        ),
+    );
+
+    let rendered = components::Rendered {
+        area: [pos.x, pos.y, EXPLOSION_SIZE, EXPLOSION_SIZE].into(),
+        colour: (0xFF, 0xFF, 0x99),
+    };
+
+    // Clean up after ourselves once the explosion is done
+    let one_shot = components::OneShot;
+
+    update.insert(e, anim);
+    update.insert(e, one_shot);
+    update.insert(e, pos);
+    update.insert(e, rendered);
+}
+
+/// Creates the score popup after killing a baddy,
+/// along with the chain it was part of
 pub fn create_score_popup(
     e: Entity,
     pos: components::Position,
     score: u32,
+    chain: u32,
     frame: u64,
     update: &LazyUpdate,
 ) {
     // Mark it as score text popup
-    let score_text = components::ScoreText { score, frame };
+    let score_text = components::ScoreText { score, chain, frame };
 
     update.insert(e, score_text);
     update.insert(e, pos);
@@ -0,0 +1,309 @@
+use crate::components::{Baddy, DamagePlayer, Player, Rendered, Velocity};
+use crate::game::{self, GameOptions, InputState, PlayerHealth};
+use crate::sim::Simulation;
+
+use ggez::graphics::Rect;
+use ggez::{GameError, GameResult};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use specs::{Join, World, WorldExt};
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::thread;
+use std::time::Instant;
+
+/// Every combination of a direction (or none) with shooting or not
+pub const ACTION_COUNT: u8 = 18;
+
+/// How many of the nearest threats go into the feature vector
+const NEAREST_THREATS: usize = 8;
+
+/// And how many of the nearest baddies
+const NEAREST_BADDIES: usize = 4;
+
+/// Player x, y and health, then position and velocity of each nearby entity
+pub const FEATURE_COUNT: usize = 3 + (NEAREST_THREATS + NEAREST_BADDIES) * 4;
+
+/// Size of the pixel observation, a tenth of the game area each way
+pub const PIXELS_WIDTH: usize = 40;
+pub const PIXELS_HEIGHT: usize = 60;
+
+/// Reward for every point scored
+const SCORE_REWARD: f32 = 0.01;
+
+/// Penalty for every point of health lost
+const DAMAGE_PENALTY: f32 = 1.;
+
+/// Steps each environment takes when benchmarking
+const BENCH_STEPS: u64 = 10_000;
+
+/// What an agent gets to see after every step
+pub struct Observation {
+    /// Normalised player state then the nearest threats and baddies
+    /// relative to the player, padded with zeros when there aren't enough
+    pub features: Vec<f32>,
+    /// Greyscale picture of the game area, row by row
+    pub pixels: Vec<u8>,
+}
+
+/// Extra details about a step that aren't part of the reward
+#[derive(Clone, Copy, Debug)]
+pub struct Info {
+    pub frame: u64,
+    pub score: u32,
+    pub health: f32,
+}
+
+/// Gym-style wrapper around the simulation: reset it, then step it with one
+/// of `ACTION_COUNT` discrete actions and get back an observation, a reward
+/// and whether the game is over. Each environment owns its own world, so
+/// any number can run side by side on their own threads.
+pub struct Env<'a, 'b> {
+    sim: Simulation<'a, 'b>,
+    // Frames each action is held for
+    frame_skip: u32,
+}
+
+impl<'a, 'b> Env<'a, 'b> {
+    /// A new environment that repeats every action for `frame_skip` frames
+    pub fn new(frame_skip: u32) -> Env<'a, 'b> {
+        Env {
+            sim: Simulation::new(0),
+            frame_skip: frame_skip.max(1),
+        }
+    }
+
+    /// Start a new game with the given seed
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.sim = Simulation::new(seed);
+        self.observe()
+    }
+
+    /// Play `action` and see how it went
+    pub fn step(&mut self, action: u8) -> (Observation, f32, bool, Info) {
+        let (score, health) = (self.sim.score(0), self.sim.health(0));
+
+        self.sim.set_input(0, action_input(action));
+        for _ in 0..self.frame_skip {
+            if self.sim.game_over() {
+                break;
+            }
+            self.sim.tick();
+        }
+
+        let scored = self.sim.score(0).saturating_sub(score) as f32;
+        let lost = (health - self.sim.health(0)).max(0.);
+        let reward = scored * SCORE_REWARD - lost * DAMAGE_PENALTY;
+
+        let info = Info {
+            frame: self.sim.frame(),
+            score: self.sim.score(0),
+            health: self.sim.health(0),
+        };
+
+        (self.observe(), reward, self.sim.game_over(), info)
+    }
+
+    /// What the game looks like right now
+    pub fn observe(&self) -> Observation {
+        Observation {
+            features: features(&self.sim.world),
+            pixels: pixels(&self.sim.world),
+        }
+    }
+}
+
+/// The input a discrete action stands for. The first nine are the
+/// directions, row by row starting up and left with standing still in
+/// the middle, and the next nine are the same while shooting.
+pub fn action_input(action: u8) -> InputState {
+    let dir = action % 9;
+    let (dx, dy) = (dir % 3, dir / 3);
+
+    InputState {
+        left: dx == 0,
+        right: dx == 2,
+        up: dy == 0,
+        down: dy == 2,
+        shoot: action >= 9,
+        stick: (0., 0.),
+    }
+}
+
+fn center(area: Rect) -> (f32, f32) {
+    (area.x + area.w / 2., area.y + area.h / 2.)
+}
+
+fn features(world: &World) -> Vec<f32> {
+    let player = world.read_storage::<Player>();
+    let rendered = world.read_storage::<Rendered>();
+    let vel = world.read_storage::<Velocity>();
+    let health = world.read_resource::<PlayerHealth>().0[0];
+
+    // Nothing near a player that isn't there
+    let me = match (&player, &rendered).join().next() {
+        Some((_, r)) => center(r.area),
+        None => return vec![0.; FEATURE_COUNT],
+    };
+
+    let mut features = Vec::with_capacity(FEATURE_COUNT);
+    features.push(me.0 / game::GAME_WIDTH);
+    features.push(me.1 / game::GAME_HEIGHT);
+    features.push(health / game::MAX_PLAYER_HEALTH);
+
+    // Position and velocity relative to the player, nearest first
+    let mut nearest = |entities: Vec<(&Rendered, Option<&Velocity>)>, count: usize| {
+        let mut entities = entities
+            .into_iter()
+            .map(|(r, v)| {
+                let (x, y) = center(r.area);
+                let (vx, vy) = v.map_or((0., 0.), |v| (v.x, v.y));
+                [
+                    (x - me.0) / game::GAME_WIDTH,
+                    (y - me.1) / game::GAME_HEIGHT,
+                    vx / game::GAME_WIDTH,
+                    vy / game::GAME_HEIGHT,
+                ]
+            }).collect::<Vec<_>>();
+        entities.sort_by(|a, b| {
+            let (a, b) = (a[0].powi(2) + a[1].powi(2), b[0].powi(2) + b[1].powi(2));
+            a.partial_cmp(&b).expect("NaN distance")
+        });
+        entities.resize(count, [0.; 4]);
+        features.extend(entities.iter().flatten());
+    };
+
+    let damage = world.read_storage::<DamagePlayer>();
+    let baddy = world.read_storage::<Baddy>();
+    nearest((&damage, &rendered, vel.maybe()).join().map(|(_, r, v)| (r, v)).collect(), NEAREST_THREATS);
+    nearest((&baddy, &rendered, vel.maybe()).join().map(|(_, r, v)| (r, v)).collect(), NEAREST_BADDIES);
+
+    features
+}
+
+/// Drawn straight at low resolution rather than scaled down from a full
+/// size render, so projectiles smaller than a pixel still show up
+fn pixels(world: &World) -> Vec<u8> {
+    let mut pixels = vec![0; PIXELS_WIDTH * PIXELS_HEIGHT];
+    let scale = game::GAME_WIDTH / PIXELS_WIDTH as f32;
+    let to_px = |v: f32, max: usize| ((v / scale).max(0.) as usize).min(max - 1);
+
+    for rendered in (&world.read_storage::<Rendered>()).join() {
+        let area = rendered.area;
+        let (r, g, b) = rendered.colour;
+        let luma = ((u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114) / 1000) as u8;
+
+        for y in to_px(area.y, PIXELS_HEIGHT)..=to_px(area.y + area.h, PIXELS_HEIGHT) {
+            for x in to_px(area.x, PIXELS_WIDTH)..=to_px(area.x + area.w, PIXELS_WIDTH) {
+                let pixel = &mut pixels[y * PIXELS_WIDTH + x];
+                *pixel = (*pixel).max(luma);
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Drive an environment over stdin and stdout so agents written in other
+/// languages can train against the game, one process per environment.
+///
+/// Requests are a single byte: `r` followed by a little endian u64 seed to
+/// reset, or `s` followed by an action byte to step. Every request is
+/// answered with the reward (f32), whether the game is over (u8), the frame
+/// (u64), score (u32) and health (f32), then the `FEATURE_COUNT` features as
+/// f32s and the `PIXELS_WIDTH` by `PIXELS_HEIGHT` greyscale pixels.
+/// Everything is little endian. The stream ending stops the environment.
+pub fn serve(options: &GameOptions) -> GameResult<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = BufReader::new(stdin.lock());
+    let mut out = BufWriter::new(stdout.lock());
+
+    let mut env = Env::new(options.frame_skip as u32 + 1);
+    env.reset(options.seed);
+
+    loop {
+        let mut request = [0];
+        match input.read_exact(&mut request) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        let (obs, reward, done, info) = match request[0] {
+            b'r' => {
+                let mut seed = [0; 8];
+                input.read_exact(&mut seed)?;
+                let obs = env.reset(u64::from_le_bytes(seed));
+                let info = Info {
+                    frame: 0,
+                    score: 0,
+                    health: game::MAX_PLAYER_HEALTH,
+                };
+                (obs, 0., false, info)
+            }
+            b's' => {
+                let mut action = [0];
+                input.read_exact(&mut action)?;
+                if action[0] >= ACTION_COUNT {
+                    return Err(GameError::ConfigError(format!("no such action: {}", action[0])));
+                }
+                env.step(action[0])
+            }
+            other => return Err(GameError::ConfigError(format!("unknown env request: {:?}", other as char))),
+        };
+
+        out.write_all(&reward.to_le_bytes())?;
+        out.write_all(&[done as u8])?;
+        out.write_all(&info.frame.to_le_bytes())?;
+        out.write_all(&info.score.to_le_bytes())?;
+        out.write_all(&info.health.to_le_bytes())?;
+        for feature in &obs.features {
+            out.write_all(&feature.to_le_bytes())?;
+        }
+        out.write_all(&obs.pixels)?;
+        out.flush()?;
+    }
+}
+
+/// Run `count` environments at once on their own threads taking random
+/// actions, and report how much faster than real time they go
+pub fn bench(options: &GameOptions, count: usize) -> GameResult<()> {
+    let frame_skip = options.frame_skip as u32 + 1;
+    let start = Instant::now();
+
+    let workers = (0..count as u64)
+        .map(|i| {
+            let seed = options.seed.wrapping_add(i);
+            thread::spawn(move || {
+                // Worlds can't move between threads, so each one builds its own
+                let mut env = Env::new(frame_skip);
+                let mut rng = StdRng::seed_from_u64(seed);
+                env.reset(seed);
+
+                let mut frames = 0;
+                for _ in 0..BENCH_STEPS {
+                    let (_, _, done, info) = env.step(rng.gen_range(0, ACTION_COUNT));
+                    if done {
+                        frames += info.frame;
+                        env.reset(rng.gen());
+                    }
+                }
+                frames + env.sim.frame()
+            })
+        }).collect::<Vec<_>>();
+
+    let mut frames = 0;
+    for worker in workers {
+        frames += worker
+            .join()
+            .map_err(|_| GameError::ConfigError("an environment panicked".to_string()))?;
+    }
+
+    let seconds = start.elapsed().as_secs_f32();
+    let game_seconds = frames as f32 / game::DESIRED_FPS as f32;
+    println!("{} environments ran {} frames in {:.2}s", count, frames, seconds);
+    println!("{:.0}x faster than real time", game_seconds / seconds);
+
+    Ok(())
+}
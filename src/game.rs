@@ -1,177 +1,854 @@
-use crate::components::{self, *};
-use crate::entities;
-use crate::systems;
+use crate::audio::{self, AudioManager, Music, Sound};
+use crate::bot::{self, Bot};
+use crate::components::*;
+use crate::director::DirectorConfig;
+use crate::entities::PLAYER_COLOURS;
+use crate::ghost::{self, Ghost};
+use crate::highscores::{Entry, HighScores};
+use crate::input::{Action, Bindings, ControlsMenu, Rebound};
+use crate::layout::{GameHud, Layout, LayoutKind, PlayerHud};
+use crate::modes::{Date, Mode};
+use crate::netplay::{Session, UdpTransport};
+use crate::particles::{self, ParticlePool};
+use crate::replay::Replay;
+use crate::rewind::{History, REWIND_LIMIT};
+use crate::rules::Rules;
+use crate::sim::Simulation;
+use crate::starfield::Starfield;
+use crate::turns::Turns;
+use crate::viewport::Viewport;
 
 use ggez::graphics::{Align, DrawParam, FilterMode, Font, MeshBuilder, Text, TextFragment};
-use ggez::{event, graphics, timer, Context, GameResult};
-use specs::{Dispatcher, DispatcherBuilder, Join, World, WorldExt};
+use ggez::input::gamepad::GamepadId;
+use ggez::{event, graphics, timer, Context, GameError, GameResult};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use specs::shrev::EventChannel;
+use specs::{Join, ReaderId, WorldExt};
 
 use std::collections::HashMap;
 use std::f32;
-
-/// The entire game window width
-pub const WINDOW_WIDTH: f32 = 500.;
-
-/// The entire game window height
-pub const WINDOW_HEIGHT: f32 = 600.;
-
-/// How much of the game window width is taken up by the ui
-const SIDEBAR_WIDTH: f32 = 100.;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 /// The playable game area width
-pub const GAME_WIDTH: f32 = WINDOW_WIDTH - SIDEBAR_WIDTH;
+pub const GAME_WIDTH: f32 = 400.;
 
 /// The playable game area height
-pub const GAME_HEIGHT: f32 = WINDOW_HEIGHT;
+pub const GAME_HEIGHT: f32 = 600.;
 
 /// Playable area
 pub const GAME_AREA: [f32; 4] = [0., 0., GAME_WIDTH, GAME_HEIGHT];
 
-/// Area occupied by sidebar ui
-const SIDEBAR_AREA: [f32; 4] = [
-    GAME_WIDTH,
-    0.,
-    SIDEBAR_WIDTH,
-    WINDOW_HEIGHT,
-];
-
-/// Health bar
-const HEALTHBAR_BG: [f32; 4] = [SIDEBAR_AREA[0] + 27., 47., 46., 206.];
-
 /// Max player health
-const MAX_PLAYER_HEALTH: f32 = 10.;
+pub const MAX_PLAYER_HEALTH: f32 = 10.;
 
-/// BG colour of sidebar ui
-const SIDEBAR_COLOUR: (u8, u8, u8) = (0x55, 0x55, 0x55);
+/// Most players that can play together on one machine,
+/// one for each colour of ship
+pub const MAX_LOCAL_PLAYERS: usize = PLAYER_COLOURS.len();
 
 /// Our desired FPS
-const DESIRED_FPS: u32 = 60;
+pub const DESIRED_FPS: u32 = 60;
+
+/// Most ticks to run in one update when catching up after a stall.
+/// Any more time than that is dropped, so a slow machine runs the game
+/// slower instead of falling further and further behind.
+const MAX_CATCH_UP_TICKS: u32 = 5;
+
+/// How long the title screen waits for input before showing the demo
+const ATTRACT_DELAY: u32 = 10 * DESIRED_FPS;
+
+/// How many high scores are shown on the title screen and after a game
+const HIGH_SCORES_SHOWN: usize = 5;
+
+/// Frames the game goes back each tick while rewind's held
+const REWIND_SPEED: u64 = 2;
+
+/// How fast the stars drift while a stage's results are up
+const RESULTS_STAR_SPEED: f32 = 0.2;
+
+/// Replay the game plays by itself in attract mode.
+/// It's just recorded input, so re-record it with `--record`
+/// if changes to gameplay make it play out badly.
+const DEMO_REPLAY: &[u8] = include_bytes!("../resources/demo.rpl");
+
+/// Options picked when launching the game
+pub struct GameOptions {
+    // Seed for anything procedurally generated
+    pub seed: u64,
+    // Whether to draw the scrolling starfield
+    pub starfield: bool,
+    // Whether to play any sound at all
+    pub audio: bool,
+    pub volumes: audio::Volumes,
+    // Write the synthesized sound effects here instead of playing
+    pub export_sfx: Option<PathBuf>,
+    // Start out fullscreen
+    pub fullscreen: bool,
+    // Only scale the game by whole numbers
+    pub integer_scaling: bool,
+    // Where the HUD goes, if not where the player's config says
+    pub layout: Option<LayoutKind>,
+    // Rotate everything for portrait (TATE) monitors, if the player's config doesn't already
+    pub tate: bool,
+    // Play, or watch a replay, in the terminal instead of a window
+    pub tui: bool,
+    // Save a screenshot here instead of playing
+    pub screenshot: Option<PathBuf>,
+    // Or compare it against this golden image
+    pub golden: Option<PathBuf>,
+    // How many frames to run before taking the screenshot
+    pub frame: u64,
+    // Fraction of pixels allowed to differ from the golden image
+    pub tolerance: f32,
+    // Record a replay of the game to this file
+    pub record: Option<PathBuf>,
+    // Play this replay back for screenshots and exports
+    pub replay: Option<PathBuf>,
+    // Draw the replay's frames here, a `.gif` or a directory for PNGs
+    pub export: Option<PathBuf>,
+    // Frames to leave out between each exported one
+    pub frame_skip: u64,
+    // How much to scale exported frames by
+    pub scale: f32,
+    // Let the bot play instead of the player
+    pub bot: bool,
+    // Have the bot play this many frames without a window and report how it did
+    pub soak: Option<u64>,
+    // Most particles that can be alive at once, fewer for slower machines
+    pub max_particles: usize,
+    // Fail the soak test if there are ever more live entities or particles than these
+    pub soak_max_entities: usize,
+    pub soak_max_particles: usize,
+    // Game ticks per second, however often the screen refreshes. This speeds the
+    // whole game up or slows it down, so it's only allowed while debugging.
+    pub tick_rate: u32,
+    // Play online with whoever's at this address, or the relay there
+    pub connect: Option<SocketAddr>,
+    // Where to listen for the other player when playing online
+    pub bind: SocketAddr,
+    // Which player we are online, counting from 0 though `--player` counts from 1
+    pub player: usize,
+    // Have the bot play itself online for this many frames and check both sides agree
+    pub netplay_check: Option<u64>,
+    // Serve a training environment over stdin and stdout
+    pub env: bool,
+    // Time this many training environments running at once
+    pub env_bench: Option<usize>,
+    // How many players are playing together on this machine
+    pub players: usize,
+    // Two players taking turns instead of playing together
+    pub alternate: bool,
+    // Game mode, difficulty preset, mutators and how the director is tuned
+    pub rules: Rules,
+    // Save what the director decided here once the game's over
+    pub director_log: Option<PathBuf>,
+    // A previous run to race, which decides the seed and rules
+    pub ghost: Option<Replay>,
+    // Let the game be stepped through and rewound, with a timeline showing where it's at
+    pub debug: bool,
+}
+
+impl Default for GameOptions {
+    fn default() -> GameOptions {
+        GameOptions {
+            seed: rand::random(),
+            starfield: true,
+            audio: true,
+            volumes: Default::default(),
+            export_sfx: None,
+            fullscreen: false,
+            integer_scaling: false,
+            layout: None,
+            tate: false,
+            tui: false,
+            screenshot: None,
+            golden: None,
+            frame: 0,
+            tolerance: 0.001,
+            record: None,
+            replay: None,
+            export: None,
+            frame_skip: 0,
+            scale: 1.,
+            bot: false,
+            soak: None,
+            max_particles: particles::DEFAULT_MAX_PARTICLES,
+            soak_max_entities: bot::SOAK_MAX_ENTITIES,
+            soak_max_particles: bot::SOAK_MAX_PARTICLES,
+            tick_rate: DESIRED_FPS,
+            connect: None,
+            bind: SocketAddr::from(([0, 0, 0, 0], 0)),
+            player: 0,
+            netplay_check: None,
+            env: false,
+            env_bench: None,
+            players: 1,
+            alternate: false,
+            rules: Rules::default(),
+            director_log: None,
+            ghost: None,
+            debug: false,
+        }
+    }
+}
+
+impl GameOptions {
+    /// Parse options from the command line arguments
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> GameResult<GameOptions> {
+        let mut options = GameOptions::default();
+        let mut director = true;
+        let mut seeded = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => {
+                    options.seed = arg_value(&arg, args.next())?;
+                    seeded = true;
+                }
+                "--no-stars" => options.starfield = false,
+                "--mute" => options.audio = false,
+                "--sfx-volume" => options.volumes.sfx = arg_value(&arg, args.next())?,
+                "--music-volume" => options.volumes.music = arg_value(&arg, args.next())?,
+                "--export-sfx" => options.export_sfx = Some(arg_value(&arg, args.next())?),
+                "--fullscreen" => options.fullscreen = true,
+                "--integer-scale" => options.integer_scaling = true,
+                "--layout" => options.layout = Some(arg_value(&arg, args.next())?),
+                "--tate" => options.tate = true,
+                "--tui" => options.tui = true,
+                "--screenshot" => options.screenshot = Some(arg_value(&arg, args.next())?),
+                "--golden" => options.golden = Some(arg_value(&arg, args.next())?),
+                "--frame" => options.frame = arg_value(&arg, args.next())?,
+                "--tolerance" => options.tolerance = arg_value(&arg, args.next())?,
+                "--record" => options.record = Some(arg_value(&arg, args.next())?),
+                "--replay" => options.replay = Some(arg_value(&arg, args.next())?),
+                "--export" => options.export = Some(arg_value(&arg, args.next())?),
+                "--frame-skip" => options.frame_skip = arg_value(&arg, args.next())?,
+                "--scale" => options.scale = arg_value(&arg, args.next())?,
+                "--bot" => options.bot = true,
+                "--soak" => options.soak = Some(arg_value(&arg, args.next())?),
+                "--max-particles" => options.max_particles = arg_value(&arg, args.next())?,
+                "--soak-max-entities" => options.soak_max_entities = arg_value(&arg, args.next())?,
+                "--soak-max-particles" => options.soak_max_particles = arg_value(&arg, args.next())?,
+                "--tick-rate" => options.tick_rate = arg_value(&arg, args.next())?,
+                "--connect" => options.connect = Some(arg_value(&arg, args.next())?),
+                "--bind" => options.bind = arg_value(&arg, args.next())?,
+                "--player" => options.player = arg_value::<usize>(&arg, args.next())?.saturating_sub(1),
+                "--netplay-check" => options.netplay_check = Some(arg_value(&arg, args.next())?),
+                "--env" => options.env = true,
+                "--env-bench" => options.env_bench = Some(arg_value(&arg, args.next())?),
+                "--players" => options.players = arg_value(&arg, args.next())?,
+                "--alternate" => options.alternate = true,
+                "--mode" => options.rules.mode = arg_value(&arg, args.next())?,
+                "--preset" => options.rules.preset = arg_value(&arg, args.next())?,
+                "--mutator" => options.rules.add_mutator(arg_value(&arg, args.next())?),
+                "--director" => {
+                    options.rules.director = DirectorConfig::load(&arg_value::<PathBuf>(&arg, args.next())?)?
+                }
+                "--no-director" => director = false,
+                "--director-log" => options.director_log = Some(arg_value(&arg, args.next())?),
+                "--ghost" => options.ghost = Some(Replay::load(&arg_value::<PathBuf>(&arg, args.next())?)?),
+                "--debug" => options.debug = true,
+                _ => return Err(GameError::ConfigError(format!("unknown argument: {}", arg))),
+            }
+        }
+
+        // However it's tuned, the director can still be turned off
+        if !director {
+            options.rules.director.enabled = false;
+        }
+
+        // Everyone gets the same daily challenge, so nothing about it can be changed
+        if options.rules.mode == Mode::Daily {
+            let daily = Rules {
+                mode: Mode::Daily,
+                ..Rules::default()
+            };
+            if seeded || options.rules != daily {
+                return Err(GameError::ConfigError(
+                    "the daily challenge has the same seed and rules for everyone".to_string(),
+                ));
+            }
+
+            let today = Date::today();
+            options.seed = today.seed();
+            options.rules = Rules::daily(today);
+            seeded = true;
+        }
+
+        // Racing a ghost only makes sense on the same game it played
+        if let Some(ghost) = &options.ghost {
+            if options.players > 1 || options.alternate || options.connect.is_some() {
+                return Err(GameError::ConfigError("ghosts can only be raced by one player".to_string()));
+            }
+            if seeded && options.seed != ghost.seed {
+                return Err(GameError::ConfigError(format!("the ghost was recorded with seed {}", ghost.seed)));
+            }
+            if options.rules != Rules::default() && options.rules != ghost.rules {
+                return Err(GameError::ConfigError(format!(
+                    "the ghost was recorded playing {} {}",
+                    ghost.rules.mode.label(),
+                    ghost.rules.describe()
+                )));
+            }
+
+            options.seed = ghost.seed;
+            options.rules = ghost.rules.clone();
+        }
+
+        if options.tick_rate == 0 {
+            return Err(GameError::ConfigError("--tick-rate can't be 0".to_string()));
+        }
+        if options.tick_rate != DESIRED_FPS && !options.debug {
+            return Err(GameError::ConfigError(
+                "--tick-rate changes how fast the game plays, so it needs --debug".to_string(),
+            ));
+        }
+
+        if options.players == 0 || options.players > MAX_LOCAL_PLAYERS {
+            return Err(GameError::ConfigError(format!(
+                "--players has to be between 1 and {}",
+                MAX_LOCAL_PLAYERS
+            )));
+        }
+
+        // Replays only know about one player's input
+        if options.players > 1 && options.record.is_some() {
+            return Err(GameError::ConfigError("games with more than one player can't be recorded".to_string()));
+        }
+
+        if options.alternate {
+            if options.players > 1 {
+                return Err(GameError::ConfigError("players either take turns or play together".to_string()));
+            }
+            // Nor does it know about switching between games
+            if options.record.is_some() {
+                return Err(GameError::ConfigError("games taking turns can't be recorded".to_string()));
+            }
+            if options.connect.is_some() {
+                return Err(GameError::ConfigError("online games can't take turns".to_string()));
+            }
+        }
+
+        if options.connect.is_some() {
+            if options.players > 1 {
+                return Err(GameError::ConfigError("online games are one player on each side".to_string()));
+            }
+            if options.player > 1 {
+                return Err(GameError::ConfigError("online games only have players 1 and 2".to_string()));
+            }
+            // Replays only know about one player's input
+            if options.record.is_some() {
+                return Err(GameError::ConfigError("online games can't be recorded".to_string()));
+            }
+            // Both sides have to be playing the same game
+            if options.rules != Rules::default() {
+                return Err(GameError::ConfigError("online games always use the default rules".to_string()));
+            }
+        }
+
+        // Rewinding only knows how to take back one game on this machine
+        if options.debug {
+            if options.connect.is_some() {
+                return Err(GameError::ConfigError("online games can't be rewound".to_string()));
+            }
+            if options.alternate {
+                return Err(GameError::ConfigError("games taking turns can't be rewound".to_string()));
+            }
+            // Nor can a ghost's run, which goes on regardless
+            if options.ghost.is_some() {
+                return Err(GameError::ConfigError("races against a ghost can't be rewound".to_string()));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Parse the value that followed a command line argument
+fn arg_value<T: FromStr>(arg: &str, value: Option<String>) -> GameResult<T> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| GameError::ConfigError(format!("bad or missing value for {}", arg)))
+}
 
 struct UITexts {
-    health_hdr: Text,
-    score_hdr: Text,
-    score: Text,
+    scores: Vec<Text>,
+    // Each player's multiplier, and the text showing it
+    multipliers: Vec<(u32, Text)>,
     game_over: Text,
+    rules: Text,
+    // The top of the mode's high score table
+    high_scores: Text,
+    // The best score there's been, and the text showing it
+    high_score: (u32, Text),
+    // The player's and the ghost's scores when the difference
+    // between them was last worked out, and the text showing it
+    ghost: (Option<(u32, u32)>, Text),
 }
 
 /// Represents current state of the input
 /// keys. (i.e. are they currently being pressed)
-#[derive(Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct InputState {
     pub up: bool,
     pub down: bool,
     pub left: bool,
     pub right: bool,
     pub shoot: bool,
+    // Analog stick position, from -1 to 1 on each axis
+    pub stick: (f32, f32),
+}
+
+impl InputState {
+    /// The same input with left and right swapped
+    pub fn mirrored(self) -> InputState {
+        InputState {
+            left: self.right,
+            right: self.left,
+            stick: (-self.stick.0, self.stick.1),
+            ..self
+        }
+    }
 }
 
+/// What each player is pressing, indexed by their id
+#[derive(Clone, Default)]
+pub struct Inputs(pub Vec<InputState>);
+
 /// How many frames have elapsed
 /// Note, this doesn't necessarily mean how many frames were
 /// rendered to the screen but rather how many frames were computed.
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 pub struct Frames(pub u64);
 
-/// Player's current health
-#[derive(Default)]
-pub struct PlayerHealth(pub f32);
+/// Each player's current health, indexed by their id
+#[derive(Clone, Default)]
+pub struct PlayerHealth(pub Vec<f32>);
+
+/// Each player's current score, indexed by their id
+#[derive(Clone, Default)]
+pub struct PlayerScore(pub Vec<u32>);
+
+/// How many shots the players have fired, and how many hit something
+#[derive(Clone, Copy, Default)]
+pub struct Accuracy {
+    pub shots: u64,
+    pub hits: u64,
+}
+
+/// Where the game gets its randomness from.
+/// It's seeded so the same seed and inputs always play out the same way.
+#[derive(Clone)]
+pub struct GameRng(pub StdRng);
+
+impl Default for GameRng {
+    fn default() -> GameRng {
+        GameRng(StdRng::seed_from_u64(0))
+    }
+}
 
-/// Player's current score
-#[derive(Default)]
-pub struct PlayerScore(pub u32);
+/// Which part of the game we're in
+enum Scene<'a, 'b> {
+    // Waiting for the player to start, counting how long nothing's been pressed
+    Title { idle: u32 },
+    // The game playing itself on its own simulation, so nothing
+    // that happens in the demo touches the real game
+    Demo { replay: Box<Replay>, sim: Simulation<'a, 'b> },
+    // Actually playing
+    Playing,
+}
 
 /// Main game state.
 pub struct Galaga<'a, 'b> {
+    // Title screen, demo or the game itself
+    scene: Scene<'a, 'b>,
+
     // Whether the game is over yet
     game_over: bool,
 
+    // Whether the player paused the game
+    paused: bool,
+
+    // In debug mode, the last stretch of the game to rewind through, whether
+    // to run one tick while paused, and whether rewind's being held
+    history: Option<History>,
+    step: bool,
+    rewinding: bool,
+
+    // What the player's keys and buttons do
+    bindings: Bindings,
+
+    // Open while the player is rebinding controls
+    controls_menu: Option<ControlsMenu>,
+
+    // Fits the game to the window
+    viewport: Viewport,
+
     // UI text items
     ui_texts: UITexts,
 
+    // Where the HUD goes, laid out for whoever's on screen
+    layout_kind: LayoutKind,
+    layout: Layout,
+
     // Scores that show briefly after killing a baddy
-    score_popup_texts: HashMap<u32, Text>,
+    score_popup_texts: HashMap<(u32, u32), Text>,
+
+    // Background stars, if enabled
+    starfield: Option<Starfield>,
+
+    // Plays sounds for whatever the systems tell us happened
+    audio: AudioManager,
+    sound_reader: ReaderId<Sound>,
+
+    // The game itself
+    sim: Simulation<'a, 'b>,
+
+    // Every frame's input, if we're recording a replay, and where to save it
+    recording: Option<(Replay, PathBuf)>,
+
+    // Where to save what the director decided, if anywhere
+    director_log: Option<PathBuf>,
+
+    // Every mode's best scores, which table this game goes in,
+    // and the seed and rules its entries are marked with
+    high_scores: HighScores,
+    score_table: String,
+    seed: u64,
+    rules: String,
+    // Whether this game's scores can go in the table at all
+    ranked: bool,
+
+    // What attract mode plays
+    demo_replay: Replay,
 
-    // ECS world
-    world: World,
+    // Most particles that can be alive at once, in the demo too
+    max_particles: usize,
 
-    // Runs our various systems
-    dispatcher: Dispatcher<'a, 'b>,
+    // Plays for the player, if asked to
+    bot: Option<Bot>,
+
+    // A previous run being raced, if any
+    ghost: Option<Ghost<'a, 'b>>,
+
+    // What each player on this machine is pressing right now
+    inputs: Vec<InputState>,
+
+    // Gamepads in the order they were first used,
+    // the first goes to player 1, the next to player 2 and so on
+    gamepads: Vec<GamepadId>,
+
+    // Keeps us in step with the other player when playing online
+    netplay: Option<Session<UdpTransport>>,
+
+    // Everyone's games when taking turns
+    turns: Option<Turns>,
+
+    // How long each tick lasts, and how much time we have yet to tick through
+    tick_length: Duration,
+    accumulator: Duration,
+
+    // Whether the game moved on its last update, so it's worth drawing
+    // things part way between where they were and where they are
+    interpolate: bool,
 }
 
 impl<'a, 'b> Galaga<'a, 'b> {
     /// Create new instance of our game state
-    pub fn new() -> Galaga<'a, 'b> {
+    pub fn new(ctx: &mut Context, options: GameOptions) -> GameResult<Galaga<'a, 'b>> {
         // Now let's create the various text fragments in our game
         let mut ui_texts = UITexts {
-            health_hdr: Text::new("HEALTH"),
-            score_hdr: Text::new("SCORE"),
-            score: Text::new("000000"),
-            game_over: Text::new(("GAME\nOVER", Font::default(), 80.)),
+            scores: vec![],
+            multipliers: vec![],
+            game_over: game_over_text("GAME\nOVER"),
+            rules: Text::new(format!("{}\n{}", options.rules.mode.heading(options.seed), options.rules.describe())),
+            high_scores: Text::new(""),
+            high_score: (0, Text::new("000000")),
+            ghost: (None, Text::new("")),
         };
-
-        // Center the text in the sidebar by setting the width to
-        // the width of the entire sidebar and telling it to align
-        // horizontally center.
-        for txt in [
-            &mut ui_texts.health_hdr,
-            &mut ui_texts.score_hdr,
-            &mut ui_texts.score,
-        ]
-            .iter_mut()
-        {
-            txt.set_bounds(
-                [SIDEBAR_WIDTH, f32::INFINITY],
-                Align::Center
-            );
-        }
-
-        ui_texts.game_over.set_bounds(
-            [240., f32::INFINITY],
-            Align::Center
-        );
+        ui_texts.rules.set_bounds([GAME_WIDTH, f32::INFINITY], Align::Center);
+        ui_texts.high_scores.set_bounds([GAME_WIDTH, f32::INFINITY], Align::Center);
 
         let score_popup_texts = HashMap::new();
 
-        // Let's setup our ECS
-        let mut world = World::new();
-
-        // Register our components
-        components::register_components(&mut world);
+        // Generate the background from the game seed
+        let starfield = if options.starfield {
+            Some(Starfield::new(options.seed))
+        } else {
+            None
+        };
 
-        // Create our player entity
-        entities::create_player(&mut world);
+        // Find the other player first if we're playing online,
+        // since they might be the one picking the seed
+        let netplay = match options.connect {
+            Some(peer) => {
+                println!("Waiting for the other player at {}", peer);
+                let transport = UdpTransport::new(options.bind, peer)?;
+                Some(Session::connect(transport, options.player, options.seed)?)
+            }
+            None => None,
+        };
 
-        // Register our systems
-        let dispatcher = DispatcherBuilder::new()
-            .with(systems::BaddySpawner, "baddy_spawner", &[])
-            .with(systems::BaddyActions, "baddy_actions", &[])
-            .with(systems::PlayerControlSystem::new(), "control", &[])
-            .with(
-                systems::MovementSystem,
-                "movement",
-                &["baddy_actions", "control"],
-            ).with(systems::CollisionSystem, "collision", &["movement"])
-            .build();
+        // Set up the game itself
+        let (seed, mut sim, player) = match &netplay {
+            Some(session) => (session.seed(), Simulation::with_players(session.seed(), 2), options.player),
+            None => {
+                let sim = Simulation::with_rules(options.seed, options.players, &options.rules);
+                (options.seed, sim, 0)
+            }
+        };
 
-        // Initialize input state and provide it as resource
-        // to be read by any system
-        world.insert::<InputState>(Default::default());
+        sim.set_max_particles(options.max_particles);
 
-        // Also provide frame count as resource
-        world.insert::<Frames>(Default::default());
+        // Taking turns, everyone starts out on the same game
+        let turns = if options.alternate {
+            Some(Turns::new(&mut sim, 2))
+        } else {
+            None
+        };
 
-        // And player health and score
-        world.insert::<PlayerHealth>(PlayerHealth(MAX_PLAYER_HEALTH));
-        world.insert::<PlayerScore>(Default::default());
+        // Listen for what sounds the systems want played
+        let sound_reader = sim
+            .world
+            .write_resource::<EventChannel<Sound>>()
+            .register_reader();
+
+        // Fall back to silence if the sounds couldn't be loaded
+        let audio = if options.audio {
+            match audio::GgezBackend::new(ctx) {
+                Ok(backend) => AudioManager::new(Box::new(backend), options.volumes),
+                Err(e) => {
+                    eprintln!("Couldn't load sounds, continuing without audio: {}", e);
+                    AudioManager::null()
+                }
+            }
+        } else {
+            AudioManager::null()
+        };
 
         // We play until health goes to 0
         let game_over = false;
 
-        Galaga {
+        // Fit the game and HUD to however big the window is
+        let players = match &turns {
+            Some(turns) => turns.players(),
+            None => sim.players(),
+        };
+        let layout_kind = options.layout.unwrap_or_default();
+        let mut layout = Layout::new(layout_kind, players);
+        if options.ghost.is_some() {
+            layout = layout.with_ghost(layout_kind);
+        }
+        let viewport = Viewport::new(
+            ctx,
+            layout.bounds,
+            options.integer_scaling,
+            options.fullscreen,
+            options.tate,
+        )?;
+
+        // Use the player's own controls if they've set any
+        let bindings = Bindings::load(ctx).unwrap_or_else(|e| {
+            eprintln!("Couldn't load controls, using the defaults: {}", e);
+            Bindings::default()
+        });
+
+        // And their high scores
+        let high_scores = HighScores::load(ctx).unwrap_or_else(|e| {
+            eprintln!("Couldn't load high scores, starting afresh: {}", e);
+            HighScores::default()
+        });
+
+        let rules = options.rules.clone();
+        let score_table = rules.mode.table(seed);
+        let mut galaga = Galaga {
+            scene: Scene::Title { idle: 0 },
             game_over,
+            paused: false,
+            history: if options.debug { Some(History::new()) } else { None },
+            step: false,
+            rewinding: false,
+            bindings,
+            controls_menu: None,
+            viewport,
             ui_texts,
+            layout_kind,
+            layout,
             score_popup_texts,
-            world,
-            dispatcher,
+            starfield,
+            audio,
+            sound_reader,
+            sim,
+            recording: options.record.map(|path| (Replay::new(seed, rules), path)),
+            director_log: options.director_log,
+            high_scores,
+            score_table,
+            seed,
+            rules: options.rules.describe(),
+            ranked: options.rules.is_standard() && options.tick_rate == DESIRED_FPS,
+            demo_replay: Replay::from_bytes(DEMO_REPLAY, "demo replay")?,
+            max_particles: options.max_particles,
+            bot: if options.bot { Some(Bot::new(player)) } else { None },
+            ghost: options.ghost.map(Ghost::new),
+            inputs: vec![InputState::default(); options.players],
+            gamepads: vec![],
+            netplay,
+            turns,
+            tick_length: Duration::from_secs(1) / options.tick_rate,
+            accumulator: Duration::from_secs(0),
+            interpolate: false,
+        };
+
+        galaga.set_score_texts();
+        galaga.set_high_score_text();
+
+        // Both players are already here, no need for the title screen
+        if galaga.netplay.is_some() {
+            galaga.start_game();
+        }
+
+        Ok(galaga)
+    }
+
+    /// Leave the title screen and start playing
+    fn start_game(&mut self) {
+        self.scene = Scene::Playing;
+
+        // Let's go! The game starts whether or not the audio does.
+        if let Err(e) = self.audio.play(Sound::StageStart) {
+            eprintln!("Couldn't play {:?}: {}", Sound::StageStart, e);
+        }
+        self.set_music(Music::Stage);
+    }
+
+    /// Switch to `music`, which isn't worth stopping the game over if it won't play
+    fn set_music(&mut self, music: Music) {
+        if let Err(e) = self.audio.set_music(Some(music)) {
+            eprintln!("Couldn't play {:?} music: {}", music, e);
+        }
+    }
+
+    /// Start the game playing by itself
+    fn start_demo(&mut self) {
+        let replay = Box::new(self.demo_replay.clone());
+        let mut sim = replay.simulation();
+        sim.set_max_particles(self.max_particles);
+        self.scene = Scene::Demo { replay, sim };
+        self.show_players();
+    }
+
+    /// Back to the title screen, showing the real score again
+    fn show_title(&mut self) {
+        self.scene = Scene::Title { idle: 0 };
+        self.show_players();
+    }
+
+    /// Lay the HUD out for whoever's playing in the simulation on screen
+    fn show_players(&mut self) {
+        let layout = Layout::new(self.layout_kind, self.player_stats().len());
+        self.layout = if self.ghost.is_some() {
+            layout.with_ghost(self.layout_kind)
+        } else {
+            layout
+        };
+        self.set_score_texts();
+    }
+
+    /// Each player's score, health and multiplier, whether
+    /// they're on screen or waiting their turn
+    fn player_stats(&self) -> Vec<(u32, f32, u32)> {
+        match (&self.scene, &self.turns) {
+            (Scene::Demo { .. }, _) | (_, None) => {
+                let sim = self.shown();
+                (0..sim.players())
+                    .map(|id| (sim.score(id), sim.health(id), sim.multiplier(id)))
+                    .collect()
+            }
+            (_, Some(turns)) => (0..turns.players())
+                .map(|id| {
+                    (
+                        turns.score(&self.sim, id),
+                        turns.health(&self.sim, id),
+                        turns.multiplier(&self.sim, id),
+                    )
+                }).collect(),
+        }
+    }
+
+    /// The simulation on screen, either the real game or the demo
+    fn shown(&self) -> &Simulation<'a, 'b> {
+        match &self.scene {
+            Scene::Demo { sim, .. } => sim,
+            _ => &self.sim,
+        }
+    }
+
+    /// Update every player's score text from the simulation on screen
+    fn set_score_texts(&mut self) {
+        let stats = self.player_stats();
+
+        let texts = &mut self.ui_texts.scores;
+        texts.resize_with(stats.len(), || Text::new(""));
+        for (text, (score, _, _)) in texts.iter_mut().zip(stats) {
+            text.fragments_mut()[0] = TextFragment::new(format!("{:06}", score));
+        }
+    }
+
+    /// Show the top of this mode's high score table
+    fn set_high_score_text(&mut self) {
+        let entries = self.high_scores.table(&self.score_table);
+        let mut lines = vec!["HIGH SCORES".to_string()];
+        lines.extend(
+            entries
+                .iter()
+                .take(HIGH_SCORES_SHOWN)
+                .enumerate()
+                .map(|(i, e)| format!("{}. {:06} {}", i + 1, e.score, e.rules)),
+        );
+
+        self.ui_texts.high_scores.fragments_mut()[0] = TextFragment::new(lines.join("\n"));
+    }
+
+    /// Put everyone's scores in the mode's high score table once the game's over.
+    /// Games the bot played don't count, nor do ones that could be rewound,
+    /// nor ones with the director retuned or turned off, the tick rate changed,
+    /// or anything else changed that the table's labels wouldn't show.
+    fn record_high_scores(&mut self, ctx: &mut Context) {
+        if self.bot.is_some() || self.history.is_some() || !self.ranked {
+            return;
+        }
+
+        for (score, _, _) in self.player_stats() {
+            let entry = Entry {
+                score,
+                rules: self.rules.clone(),
+                seed: self.seed,
+            };
+            self.high_scores.add(&self.score_table, entry);
+        }
+
+        if let Err(e) = self.high_scores.save(ctx) {
+            eprintln!("Couldn't save high scores: {}", e);
+        }
+        self.set_high_score_text();
+    }
+
+    /// Any key or button press counts as activity on the title screen,
+    /// and ends the demo. Returns whether the press was used up doing so.
+    fn any_input(&mut self) -> bool {
+        match &mut self.scene {
+            Scene::Title { idle } => {
+                *idle = 0;
+                false
+            }
+            Scene::Demo { .. } => {
+                self.show_title();
+                true
+            }
+            Scene::Playing => false,
         }
     }
 
@@ -179,24 +856,81 @@ impl<'a, 'b> Galaga<'a, 'b> {
     fn draw_ui(&mut self, ctx: &mut Context) -> GameResult<()> {
         let mut ui = MeshBuilder::new();
 
-        // Set the colour and the sidebar UI bg
-        ui.rectangle(graphics::DrawMode::fill(), SIDEBAR_AREA.into(), SIDEBAR_COLOUR.into());
+        // Multipliers run out on their own, so check them every time.
+        // Only make new text when one's changed though.
+        let stats = self.player_stats();
+        let multipliers = &mut self.ui_texts.multipliers;
+        multipliers.resize_with(stats.len(), || (0, Text::new("")));
+        for ((shown, text), &(_, _, multiplier)) in multipliers.iter_mut().zip(&stats) {
+            if *shown != multiplier {
+                *shown = multiplier;
+                *text = Text::new(format!("x{}", multiplier));
+            }
+        }
 
-        // The health bar BG
-        ui.rectangle(graphics::DrawMode::fill(), HEALTHBAR_BG.into(), graphics::BLACK);
+        // And the high score, which the score being played takes over once it's better
+        let best = self.high_scores.table(&self.score_table).first().map_or(0, |e| e.score);
+        let best = stats.iter().map(|&(score, _, _)| score).fold(best, u32::max);
+        let (shown, text) = &mut self.ui_texts.high_score;
+        if *shown != best {
+            *shown = best;
+            *text = Text::new(format!("{:06}", best));
+        }
 
-        // The health bar
-        let health = self.world.read_resource::<PlayerHealth>();
-        let lvl = 250. - 200. * health.0 / MAX_PLAYER_HEALTH;
-        let health_rect = [HEALTHBAR_BG[0] + 3., lvl, 40., 250. - lvl].into();
-        ui.rectangle(graphics::DrawMode::fill(), health_rect, (0x00, 0xFF, 0x00).into());
+        // Same goes for how far ahead of any ghost the player is, which shows while they're racing it
+        let racing = match (&self.scene, &self.ghost) {
+            (Scene::Playing, Some(ghost)) => {
+                let scores = (self.sim.score(0), ghost.score());
+                let (shown, text) = &mut self.ui_texts.ghost;
+                if *shown != Some(scores) {
+                    *shown = Some(scores);
+                    let (difference, colour) = ghost::score_difference(scores.0, scores.1);
+                    *text = Text::new(TextFragment::new(difference).color(colour.into()));
+                }
+                true
+            }
+            _ => false,
+        };
 
-        // Queue up the text to draw
-        graphics::queue_text(ctx, &self.ui_texts.health_hdr, [SIDEBAR_AREA[0], 15.], Some(graphics::WHITE));
-        graphics::queue_text(ctx, &self.ui_texts.score_hdr, [SIDEBAR_AREA[0], 315.], Some(graphics::WHITE));
-        graphics::queue_text(ctx, &self.ui_texts.score, [SIDEBAR_AREA[0], 335.], Some(graphics::WHITE));
+        // Lay out the HUD widgets, health bars showing how much of their starting health players have left
+        let mods = self.shown().modifiers();
+        let players = stats
+            .iter()
+            .zip(&self.ui_texts.scores)
+            .zip(&self.ui_texts.multipliers)
+            .map(|((&(_, health, _), score), (_, multiplier))| PlayerHud {
+                score,
+                multiplier,
+                health: health / mods.player_health,
+            }).collect::<Vec<_>>();
+        let up = match (&self.scene, &self.turns) {
+            (Scene::Playing, Some(turns)) => Some(turns.current()),
+            _ => None,
+        };
+        let stages = self.shown().stages();
+        let game = GameHud {
+            high_score: &self.ui_texts.high_score.1,
+            stage: if stages.enabled() { Some(stages.number()) } else { None },
+            ghost: if racing { Some(&self.ui_texts.ghost.1) } else { None },
+        };
+        self.layout.draw(ctx, &mut ui, &game, &players, up, mods.hud);
+
+        // Let whoever's up next know it's their turn
+        if let (Scene::Playing, Some(turns)) = (&self.scene, &self.turns) {
+            if turns.ready() && !self.game_over {
+                let ready = format!("PLAYER {}\nREADY", turns.current() + 1);
+                let mut ready = Text::new((ready, Font::default(), 32.));
+                ready.set_bounds([GAME_WIDTH, f32::INFINITY], Align::Center);
+                graphics::queue_text(ctx, &ready, [0., 240.], Some((0x44, 0xAA, 0xFF, 0xFF).into()));
+            }
+        }
 
-        // Queue draw GAMEOVER text if needed
+        // Announce each stage, and how the last one went in between
+        if !self.game_over {
+            self.draw_stage(ctx);
+        }
+
+        // Queue draw GAMEOVER text if needed, along with the high scores
         if self.game_over {
             graphics::queue_text(
                 ctx,
@@ -204,6 +938,26 @@ impl<'a, 'b> Galaga<'a, 'b> {
                 [80., 220.],
                 Some((0xFF, 0x00, 0x00, 0xFF).into()),
             );
+            graphics::queue_text(ctx, &self.ui_texts.high_scores, [0., 420.], Some(graphics::WHITE));
+        }
+
+        // Title screen and demo, with a blinking prompt
+        if self.controls_menu.is_none() {
+            self.draw_title(ctx);
+        }
+
+        // Let the player know why nothing's moving
+        if self.paused && self.controls_menu.is_none() {
+            graphics::queue_text(ctx, &Text::new("PAUSED"), [170., 280.], Some(graphics::WHITE));
+        }
+
+        if let Some(menu) = &self.controls_menu {
+            self.draw_controls_menu(ctx, menu);
+        }
+
+        // Where the game's at when debugging
+        if let (Scene::Playing, Some(history)) = (&self.scene, &self.history) {
+            self.draw_timeline(ctx, &mut ui, history);
         }
 
         // Draw UI
@@ -213,15 +967,316 @@ impl<'a, 'b> Galaga<'a, 'b> {
         Ok(())
     }
 
+    /// Draw the stage's name as it starts, or the results between stages,
+    /// and how the mode's goal is going up top
+    fn draw_stage(&self, ctx: &mut Context) {
+        let sim = self.shown();
+        if let Some(status) = sim.progress().status(sim.frame()) {
+            let mut status = Text::new(status);
+            status.set_bounds([GAME_WIDTH, f32::INFINITY], Align::Center);
+            graphics::queue_text(ctx, &status, [0., 10.], Some(graphics::WHITE));
+        }
+
+        let stages = sim.stages();
+
+        if let Some(banner) = stages.banner() {
+            let mut banner = Text::new((banner, Font::default(), 24.));
+            banner.set_bounds([GAME_WIDTH, f32::INFINITY], Align::Center);
+            graphics::queue_text(ctx, &banner, [0., 240.], Some((0x33, 0xDD, 0xDD, 0xFF).into()));
+        }
+
+        if let Some(results) = stages.results() {
+            let mut results = Text::new((results.lines().join("\n"), Font::default(), 24.));
+            results.set_bounds([GAME_WIDTH, f32::INFINITY], Align::Center);
+            graphics::queue_text(ctx, &results, [0., 200.], Some(graphics::WHITE));
+        }
+    }
+
+    /// Draw how far back the game can be rewound, with the current frame
+    /// at the end, along the bottom of the game area
+    fn draw_timeline(&self, ctx: &mut Context, ui: &mut MeshBuilder, history: &History) {
+        let frame = self.sim.frame();
+        let (x, y, width) = (20., GAME_HEIGHT - 24., GAME_WIDTH - 40.);
+
+        let back = (frame - history.earliest()).min(REWIND_LIMIT) as f32 / REWIND_LIMIT as f32;
+        let now = x + width * back;
+        ui.rectangle(
+            graphics::DrawMode::stroke(1.),
+            [x, y, width, 6.].into(),
+            (0x99, 0x99, 0x99, 0xFF).into(),
+        );
+        ui.rectangle(
+            graphics::DrawMode::fill(),
+            [x, y, now - x, 6.].into(),
+            (0x44, 0xAA, 0xFF, 0xFF).into(),
+        );
+        ui.rectangle(graphics::DrawMode::fill(), [now - 1., y - 3., 3., 12.].into(), graphics::WHITE);
+
+        let state = if self.rewinding {
+            " REWIND"
+        } else if self.paused {
+            " STEP"
+        } else {
+            ""
+        };
+        let text = Text::new(format!("FRAME {}{}", frame, state));
+        graphics::queue_text(ctx, &text, [x, y - 20.], Some(graphics::WHITE));
+    }
+
+    /// Draw the title screen or the demo overlay, if we're on either
+    fn draw_title(&self, ctx: &mut Context) {
+        let blink = timer::ticks(ctx) % 60 < 30;
+
+        match self.scene {
+            Scene::Title { .. } => {
+                let mut title = Text::new(("GALAGA", Font::default(), 64.));
+                title.set_bounds([GAME_WIDTH, f32::INFINITY], Align::Center);
+                graphics::queue_text(ctx, &title, [0., 200.], Some((0xFF, 0xFF, 0x00, 0xFF).into()));
+
+                // So nobody's surprised by what they're in for
+                graphics::queue_text(ctx, &self.ui_texts.rules, [0., 290.], Some((0x99, 0x99, 0x99, 0xFF).into()));
+                graphics::queue_text(ctx, &self.ui_texts.high_scores, [0., 400.], Some(graphics::WHITE));
+            }
+            Scene::Demo { .. } => {
+                let mut demo = Text::new(("DEMO", Font::default(), 32.));
+                demo.set_bounds([GAME_WIDTH, f32::INFINITY], Align::Center);
+                graphics::queue_text(ctx, &demo, [0., 220.], Some((0xFF, 0x00, 0x00, 0xFF).into()));
+            }
+            Scene::Playing => return,
+        }
+
+        if blink {
+            let mut start = Text::new("PRESS START");
+            start.set_bounds([GAME_WIDTH, f32::INFINITY], Align::Center);
+            graphics::queue_text(ctx, &start, [0., 360.], Some(graphics::WHITE));
+        }
+    }
+
+    /// Draw the list of actions and what they're bound to
+    fn draw_controls_menu(&self, ctx: &mut Context, menu: &ControlsMenu) {
+        let title = Text::new(("CONTROLS", Font::default(), 32.));
+        graphics::queue_text(ctx, &title, [20., 40.], Some(graphics::WHITE));
+
+        for (i, (player, action)) in ControlsMenu::entries().enumerate() {
+            let selected = i == menu.selected;
+            let binding = if selected && menu.waiting {
+                if player == 0 { "press a key or button..." } else { "press a key..." }.to_string()
+            } else {
+                self.bindings.describe(player, action)
+            };
+
+            let line = Text::new(format!(
+                "{} {}{:<10}{}",
+                if selected { ">" } else { " " },
+                if player == 0 { "" } else { "P2 " },
+                action.label(),
+                binding
+            ));
+            let colour = if selected {
+                (0xFF, 0xFF, 0x00, 0xFF).into()
+            } else {
+                graphics::WHITE
+            };
+            graphics::queue_text(ctx, &line, [20., 100. + 25. * i as f32], Some(colour));
+        }
+
+        let help = Text::new("Fire to rebind, Controls to close");
+        let y = 110. + 25. * ControlsMenu::entries().count() as f32;
+        graphics::queue_text(ctx, &help, [20., y], Some((0x99, 0x99, 0x99, 0xFF).into()));
+
+        if let Some(message) = &menu.message {
+            let message = Text::new(message.as_str());
+            graphics::queue_text(ctx, &message, [20., y + 25.], Some((0xFF, 0xFF, 0x00, 0xFF).into()));
+        }
+    }
+
+    /// Respond to an action being pressed or released by one of the players
+    fn handle_action(&mut self, ctx: &mut Context, player: usize, action: Action, pressed: bool) {
+        // The controls menu gets first dibs on any input
+        if let Some(menu) = &mut self.controls_menu {
+            if pressed {
+                match action {
+                    Action::MoveUp => menu.move_selection(false),
+                    Action::MoveDown => menu.move_selection(true),
+                    Action::Fire => menu.waiting = true,
+                    Action::Controls | Action::Quit => self.controls_menu = None,
+                    _ => {}
+                }
+            }
+            return;
+        }
+
+        // Fire or start gets the game going from the title screen
+        let start = pressed && (action == Action::Fire || action == Action::Pause);
+        if start && matches!(self.scene, Scene::Title { .. }) {
+            self.start_game();
+            return;
+        }
+
+        match action {
+            Action::Pause if pressed => self.paused = !self.paused,
+
+            // Stepping and rewinding are only for debugging
+            Action::Step if pressed && self.history.is_some() => self.step = true,
+            Action::Rewind if self.history.is_some() => self.rewinding = pressed,
+
+            Action::Controls if pressed => {
+                // Let go of everything so nobody keeps moving
+                for input in &mut self.inputs {
+                    *input = InputState::default();
+                }
+                self.controls_menu = Some(ControlsMenu::default());
+            }
+
+            Action::Fullscreen if pressed => {
+                if let Err(e) = self.viewport.toggle_fullscreen(ctx) {
+                    eprintln!("Couldn't toggle fullscreen: {}", e);
+                }
+            }
+
+            // Quit on Escape
+            Action::Quit if pressed => event::quit(ctx),
+
+            _ => {
+                if let Some(input) = self.inputs.get_mut(player) {
+                    action.apply(input, pressed);
+                }
+            }
+        }
+    }
+
+    /// Which player a gamepad belongs to, handing it to the next one along
+    /// the first time it's used. Extra gamepads double up from player 1.
+    fn gamepad_player(&mut self, id: GamepadId) -> usize {
+        let index = match self.gamepads.iter().position(|&g| g == id) {
+            Some(index) => index,
+            None => {
+                self.gamepads.push(id);
+                self.gamepads.len() - 1
+            }
+        };
+        index % self.inputs.len()
+    }
+
+    /// Write out the replay being recorded, if any.
+    /// Only happens once, whichever of game over or quitting comes first.
+    fn save_replay(&mut self) {
+        if let Some((replay, path)) = self.recording.take() {
+            if let Err(e) = replay.save(&path) {
+                eprintln!("Couldn't save replay: {}", e);
+            }
+        }
+    }
+
+    /// Write out what the director decided, if asked to.
+    /// Like the replay, only happens once.
+    fn save_director_log(&mut self) {
+        if let Some(path) = self.director_log.take() {
+            if let Err(e) = self.sim.director().save_log(&path) {
+                eprintln!("Couldn't save the director's log: {}", e);
+            }
+        }
+    }
+
+    /// Take the game back a little, picking it up again if it was over
+    fn rewind(&mut self) -> GameResult<()> {
+        let history = match &mut self.history {
+            Some(history) => history,
+            None => return Ok(()),
+        };
+        history.rewind(&mut self.sim, REWIND_SPEED);
+
+        // Sounds were already played the first time round
+        {
+            let sounds = self.sim.world.read_resource::<EventChannel<Sound>>();
+            for _ in sounds.read(&mut self.sound_reader) {}
+        }
+
+        // The replay forgets what got undone too, so it still plays back the same
+        if let Some((replay, _)) = &mut self.recording {
+            replay.inputs.truncate(self.sim.frame() as usize);
+        }
+
+        if self.game_over && !self.sim.game_over() {
+            self.game_over = false;
+            self.set_music(Music::Stage);
+            if let Some(starfield) = &mut self.starfield {
+                starfield.set_speed(1.);
+            }
+        }
+
+        self.set_score_texts();
+        Ok(())
+    }
+
+    /// Which player and action the controls menu is waiting to bind something to, if any
+    fn waiting_for_binding(&self) -> Option<(usize, Action)> {
+        self.controls_menu
+            .as_ref()
+            .filter(|m| m.waiting)
+            .map(ControlsMenu::entry)
+    }
+
+    /// Save the controls once `control` has been bound, letting
+    /// the player know if that meant changing some other action
+    fn finish_binding(&mut self, ctx: &mut Context, control: String, rebound: Rebound) {
+        if let Some(menu) = &mut self.controls_menu {
+            let (_, action) = menu.entry();
+            menu.waiting = false;
+            menu.message = match rebound {
+                Rebound::Bound => None,
+                Rebound::Swapped(other) => {
+                    Some(format!("{} swapped from {} to {}", control, other.label(), action.label()))
+                }
+                Rebound::Taken(other) => Some(format!("{} is already used by {}", control, other.label())),
+            };
+        }
+
+        if let Err(e) = self.bindings.save(ctx) {
+            eprintln!("Couldn't save controls: {}", e);
+        }
+    }
+
+    /// How far we are between the last tick and the next one, from 0 to 1
+    fn blend(&self) -> f32 {
+        if self.interpolate {
+            self.accumulator.as_secs_f32() / self.tick_length.as_secs_f32()
+        } else {
+            1.
+        }
+    }
+
     /// Draw all entities that should be rendered
     fn draw_entities(&mut self, ctx: &mut Context) -> GameResult<()> {
         // Create meshes for all entities marked with Rendered
         let mut rendered_ents = MeshBuilder::new();
         {
-            let rendered = self.world.read_storage::<Rendered>();
+            let blend = self.blend();
+            let world = &self.shown().world;
+            let rendered = world.read_storage::<Rendered>();
+            let position = world.read_storage::<Position>();
+            let prev = world.read_storage::<PreviousPosition>();
+
+            let mods = self.shown().modifiers();
+
+            // The ghost's ship goes under everything that's really there
+            if let (Scene::Playing, Some(ghost)) = (&self.scene, &self.ghost) {
+                if let Some((rendered, pos, prev)) = ghost.ship() {
+                    let mut area = rendered.area;
+                    area.move_to(drawn_at(&pos, prev.as_ref(), blend));
+                    area.x = mods.screen_x(area.x, area.w);
+                    let (r, g, b) = rendered.colour;
+                    rendered_ents.rectangle(graphics::DrawMode::fill(), area, (r, g, b, ghost::GHOST_ALPHA).into());
+                }
+            }
 
-            for rendered in (&rendered).join() {
-                rendered_ents.rectangle(graphics::DrawMode::fill(), rendered.area, rendered.colour.into());
+            for (rendered, pos, prev) in (&rendered, position.maybe(), prev.maybe()).join() {
+                let mut area = rendered.area;
+                if let Some(pos) = pos {
+                    area.move_to(drawn_at(pos, prev, blend));
+                }
+                area.x = mods.screen_x(area.x, area.w);
+                rendered_ents.rectangle(graphics::DrawMode::fill(), area, rendered.colour.into());
             }
         }
 
@@ -235,72 +1290,252 @@ impl<'a, 'b> Galaga<'a, 'b> {
         Ok(())
     }
 
+    /// Draw all live particles
+    fn draw_particles(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let mods = self.shown().modifiers();
+        let pool = self.shown().world.read_resource::<ParticlePool>();
+
+        // Nothing to draw
+        if pool.is_empty() {
+            return Ok(());
+        }
+
+        let mut particles = MeshBuilder::new();
+        for ([x, y], size, colour) in pool.iter() {
+            let area = [mods.screen_x(x - size / 2., size), y - size / 2., size, size].into();
+            particles.rectangle(graphics::DrawMode::fill(), area, colour.into());
+        }
+
+        let particles = particles.build(ctx)?;
+        graphics::draw(ctx, &particles, DrawParam::default())?;
+
+        Ok(())
+    }
+
     /// Draw temporary popup text
     fn draw_text_popups(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let frames = self.world.read_resource::<Frames>();
-        let ent = self.world.entities();
-        let score_text = self.world.read_storage::<ScoreText>();
-        let position = self.world.read_storage::<Position>();
+        let blend = self.blend();
+
+        // Can't use `shown` here as we need to update the text cache too
+        let (world, mods) = match &self.scene {
+            Scene::Demo { sim, .. } => (&sim.world, sim.modifiers()),
+            _ => (&self.sim.world, self.sim.modifiers()),
+        };
+        let score_text = world.read_storage::<ScoreText>();
+        let position = world.read_storage::<Position>();
+        let prev = world.read_storage::<PreviousPosition>();
 
         // Draw score text
-        for (e, score_text, pos) in (&ent, &score_text, &position).join() {
+        for (score_text, pos, prev) in (&score_text, &position, prev.maybe()).join() {
             // We don't want to create a new Text every frame,
             // so we first look it up in the hashmap before just making a new one
             let text = self
                 .score_popup_texts
-                .entry(score_text.score)
-                .or_insert_with(|| Text::new(format!("{}", score_text.score)));
+                .entry((score_text.score, score_text.chain))
+                .or_insert_with(|| Text::new(score_text.label()));
 
             // Draw the text
-            graphics::queue_text(ctx, &text, [pos.x, pos.y], Some((0x99, 0x99, 0x99).into()));
-
-            // We only display the text for 60 frames,
-            // remove it after that time
-            if frames.0 > score_text.frame + 60 {
-                ent.delete(e).expect("unexepected generation error");
-            }
+            let [x, y] = drawn_at(pos, prev, blend);
+            let x = mods.screen_x(x, text.width(ctx) as f32);
+            graphics::queue_text(ctx, &text, [x, y], Some((0x99, 0x99, 0x99).into()));
         }
 
         Ok(())
     }
 }
 
+/// Where to draw something `blend` of the way from where it was last tick to
+/// where it is now. Things that just appeared haven't been anywhere else yet.
+fn drawn_at(pos: &Position, prev: Option<&PreviousPosition>, blend: f32) -> [f32; 2] {
+    match prev {
+        Some(prev) => [prev.x + (pos.x - prev.x) * blend, prev.y + (pos.y - prev.y) * blend],
+        None => [pos.x, pos.y],
+    }
+}
+
+/// The big text over the game once it's over, saying how it ended
+fn game_over_text(ending: &str) -> Text {
+    let mut text = Text::new((ending, Font::default(), 80.));
+    text.set_bounds(
+        [240., f32::INFINITY],
+        Align::Center
+    );
+    text
+}
+
 /// Implmentation for our game mainloop.
 impl<'a, 'b> event::EventHandler for Galaga<'a, 'b> {
     /// Called on every tick; where we handle the game logic.
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        // Do nothing if game is over
-        if self.game_over {
-            return Ok(());
-        }
+        self.accumulator += timer::delta(ctx);
+        self.accumulator = self.accumulator.min(self.tick_length * MAX_CATCH_UP_TICKS);
+
+        // Only keep interpolating if the game actually moves this time
+        let frame = self.shown().frame();
+        let ticking = self.accumulator >= self.tick_length;
+
+        while self.accumulator >= self.tick_length {
+            self.accumulator -= self.tick_length;
 
-        while timer::check_update_time(ctx, DESIRED_FPS) {
-            // Read the current score
-            let score = self.world.read_resource::<PlayerScore>().0;
+            // The stars keep drifting even once the game is over
+            if let Some(starfield) = &mut self.starfield {
+                starfield.update();
+            }
 
-            // Run the systems!
-            self.dispatcher.dispatch(&self.world);
+            // Nothing moves while the controls are being changed
+            if self.controls_menu.is_some() {
+                continue;
+            }
 
-            // Let any changes get reflected
-            self.world.maintain();
+            match &mut self.scene {
+                // Show the demo if nobody's around to play
+                Scene::Title { idle } => {
+                    *idle += 1;
+                    if *idle >= ATTRACT_DELAY {
+                        self.start_demo();
+                    }
+                    continue;
+                }
+
+                // Play the demo until its replay runs out or its player dies
+                Scene::Demo { replay, sim } => {
+                    let score = sim.total_score();
+                    let playing = replay.step(sim) && !sim.game_over();
+                    let new_score = sim.total_score();
+
+                    if !playing {
+                        self.show_title();
+                    } else if score != new_score {
+                        self.set_score_texts();
+                    }
+                    continue;
+                }
+
+                Scene::Playing => {}
+            }
+
+            // Go back over what just happened, even once it's over
+            if self.rewinding {
+                self.rewind()?;
+                continue;
+            }
 
-            // Check if health has gone to 0
-            let health = self.world.read_resource::<PlayerHealth>().0;
-            if health <= 0. {
+            // Do nothing else if game is over or on hold, unless it's being stepped through
+            let step = std::mem::take(&mut self.step);
+            if self.game_over || (self.paused && !step) {
+                // Other than keeping the other player from waiting on us
+                if let Some(session) = &mut self.netplay {
+                    session.service(&mut self.sim)?;
+                }
+                continue;
+            }
+
+            // Give whoever's up a moment before their turn starts
+            if let Some(turns) = &mut self.turns {
+                if turns.getting_ready() {
+                    continue;
+                }
+            }
+
+            // Read the current score, and health for seeing if a ship was lost
+            let score = self.sim.total_score();
+            let health = self.sim.health(0);
+
+            // Mirrored, pressing left on screen is going right in the game.
+            // The bot sees the game itself so it doesn't need flipping.
+            let mut inputs = self.inputs.clone();
+            if self.sim.modifiers().mirror {
+                for input in &mut inputs {
+                    *input = input.mirrored();
+                }
+            }
+
+            // Let the bot take over the first player's controls
+            if let Some(bot) = &mut self.bot {
+                inputs[0] = bot.think(&self.sim.world);
+            }
+
+            match &mut self.netplay {
+                // Online, the session runs the systems once it's got what it needs
+                Some(session) => {
+                    session.advance(&mut self.sim, inputs[0])?;
+                }
+                None => {
+                    // Remember what the player did this frame
+                    if let Some((replay, _)) = &mut self.recording {
+                        replay.record(&inputs[0]);
+                    }
+                    if let Some(history) = &mut self.history {
+                        history.record(&mut self.sim, &inputs);
+                    }
+
+                    // Run the systems!
+                    for (id, &input) in inputs.iter().enumerate() {
+                        self.sim.set_input(id, input);
+                    }
+                    self.sim.tick();
+                }
+            }
+
+            // The ghost keeps pace with the live game in its own world
+            if let Some(ghost) = &mut self.ghost {
+                ghost.catch_up(self.sim.frame());
+            }
+
+            // Taking turns, losing a ship hands over to the next player
+            let switched = match &mut self.turns {
+                Some(turns) => turns.after_tick(&mut self.sim, health),
+                None => false,
+            };
+
+            // Play any sounds the systems asked for. A sound that won't play
+            // isn't worth stopping the game over.
+            {
+                let sounds = self.sim.world.read_resource::<EventChannel<Sound>>();
+                for &sound in sounds.read(&mut self.sound_reader) {
+                    if let Err(e) = self.audio.play(sound) {
+                        eprintln!("Couldn't play {:?}: {}", sound, e);
+                    }
+                }
+            }
+
+            // The stars slow down between stages
+            if let Some(starfield) = &mut self.starfield {
+                let between = self.sim.stages().results().is_some();
+                starfield.set_speed(if between { RESULTS_STAR_SPEED } else { 1. });
+            }
+
+            // Check if everyone's health has gone to 0. Online, we might only think so
+            // because we guessed wrong about what the other player did.
+            let settled = match &self.netplay {
+                Some(session) => session.settled(&self.sim),
+                None => true,
+            };
+            if self.sim.game_over() && settled {
                 self.game_over = true;
+                self.set_music(Music::GameOver);
+                self.save_replay();
+                self.save_director_log();
+                self.record_high_scores(ctx);
+
+                // The mode might have ended it some other way than everyone going down
+                self.ui_texts.game_over = game_over_text(self.sim.ending());
+
+                // Slow the stars to a stop
+                if let Some(starfield) = &mut self.starfield {
+                    starfield.set_speed(0.);
+                }
             }
 
             // Check if score has changed
-            let new_score = self.world.read_resource::<PlayerScore>().0;
-            if score != new_score {
-                self.ui_texts
-                    .score
-                    .fragments_mut()[0] = TextFragment::new(format!("{:06}", new_score));
+            let new_score = self.sim.total_score();
+            if score != new_score || switched {
+                self.set_score_texts();
             }
+        }
 
-            // Update "frame" count
-            let mut frames = self.world.write_resource::<Frames>();
-            frames.0 += 1;
+        if ticking {
+            self.interpolate = self.shown().frame() != frame;
         }
 
         Ok(())
@@ -308,11 +1543,24 @@ impl<'a, 'b> event::EventHandler for Galaga<'a, 'b> {
 
     /// Called after `update` to render game.
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        self.viewport.begin_frame(ctx)?;
+
         // Clear the old screen
         graphics::clear(ctx, graphics::BLACK);
 
-        // Draw all entities that should be rendered
-        self.draw_entities(ctx)?;
+        // Draw the background under everything else
+        if let Some(starfield) = &self.starfield {
+            starfield.draw(ctx)?;
+        }
+
+        // The title screen has nothing going on yet
+        if !matches!(self.scene, Scene::Title { .. }) {
+            // Draw all entities that should be rendered
+            self.draw_entities(ctx)?;
+
+            // Then any particle effects over them
+            self.draw_particles(ctx)?;
+        }
 
         // Draw the UI
         self.draw_ui(ctx)?;
@@ -320,6 +1568,9 @@ impl<'a, 'b> event::EventHandler for Galaga<'a, 'b> {
         // Draw any queued text
         graphics::draw_queued_text(ctx, DrawParam::default(), None, FilterMode::Linear)?;
 
+        // Fit everything onto the window
+        self.viewport.end_frame(ctx)?;
+
         // Now, actually put everything onto the screen
         graphics::present(ctx)?;
 
@@ -327,41 +1578,75 @@ impl<'a, 'b> event::EventHandler for Galaga<'a, 'b> {
     }
 
     /// Respond to key down event
-    fn key_down_event(&mut self, ctx: &mut Context, key: event::KeyCode, _: event::KeyMods, _: bool) {
-        let mut input_state = self.world.write_resource::<InputState>();
+    fn key_down_event(&mut self, ctx: &mut Context, key: event::KeyCode, _: event::KeyMods, repeat: bool) {
+        // Held keys are already accounted for
+        if repeat || self.any_input() {
+            return;
+        }
 
-        match key {
-            // Quit on Escape
-            event::KeyCode::Escape => event::quit(ctx),
+        if let Some((player, action)) = self.waiting_for_binding() {
+            let rebound = self.bindings.bind_key(player, action, key);
+            self.finish_binding(ctx, format!("{:?}", key), rebound);
+        } else if let Some((player, action)) = self.bindings.player_key(key, self.inputs.len()) {
+            self.handle_action(ctx, player, action, true);
+        }
+    }
 
-            // Fire a projectile
-            event::KeyCode::Space => input_state.shoot = true,
+    /// Respond to key up event
+    fn key_up_event(&mut self, ctx: &mut Context, key: event::KeyCode, _: event::KeyMods) {
+        if let Some((player, action)) = self.bindings.player_key(key, self.inputs.len()) {
+            self.handle_action(ctx, player, action, false);
+        }
+    }
 
-            // Move in some direction
-            event::KeyCode::W => input_state.up = true,
-            event::KeyCode::A => input_state.left = true,
-            event::KeyCode::S => input_state.down = true,
-            event::KeyCode::D => input_state.right = true,
+    /// Respond to gamepad button down event
+    fn gamepad_button_down_event(&mut self, ctx: &mut Context, btn: event::Button, id: GamepadId) {
+        if self.any_input() {
+            return;
+        }
 
-            _ => {}
+        if let Some((player, action)) = self.waiting_for_binding() {
+            // Every gamepad shares player 1's buttons, so player 2 only rebinds keys
+            if player == 0 {
+                let rebound = self.bindings.bind_button(action, btn);
+                self.finish_binding(ctx, format!("Pad {:?}", btn), rebound);
+            }
+        } else if let Some(action) = self.bindings.button(btn) {
+            let player = self.gamepad_player(id);
+            self.handle_action(ctx, player, action, true);
         }
     }
 
-    /// Respond to key up event
-    fn key_up_event(&mut self, _: &mut Context, key: event::KeyCode, _: event::KeyMods) {
-        let mut input_state = self.world.write_resource::<InputState>();
+    /// Respond to gamepad button up event
+    fn gamepad_button_up_event(&mut self, ctx: &mut Context, btn: event::Button, id: GamepadId) {
+        if let Some(action) = self.bindings.button(btn) {
+            let player = self.gamepad_player(id);
+            self.handle_action(ctx, player, action, false);
+        }
+    }
 
-        match key {
-            // Stop shooting
-            event::KeyCode::Space => input_state.shoot = false,
+    /// Keep the game fitted to the window as it changes size
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        if let Err(e) = self.viewport.resize(ctx, width, height) {
+            eprintln!("Couldn't resize: {}", e);
+        }
+    }
 
-            // Stop moving in some direction
-            event::KeyCode::W => input_state.up = false,
-            event::KeyCode::A => input_state.left = false,
-            event::KeyCode::S => input_state.down = false,
-            event::KeyCode::D => input_state.right = false,
+    /// Don't lose the replay or director's log if the player quits mid game
+    fn quit_event(&mut self, _: &mut Context) -> bool {
+        self.save_replay();
+        self.save_director_log();
+        false
+    }
 
-            _ => {}
+    /// Respond to gamepad stick movement
+    fn gamepad_axis_event(&mut self, _: &mut Context, axis: event::Axis, value: f32, id: GamepadId) {
+        // Don't move while the controls menu is open
+        if self.controls_menu.is_some() {
+            return;
         }
+
+        let player = self.gamepad_player(id);
+        self.bindings.axis(&mut self.inputs[player], axis, value);
     }
 }
@@ -0,0 +1,68 @@
+use crate::components::{Player, Position, PreviousPosition, Rendered};
+use crate::replay::Replay;
+use crate::sim::Simulation;
+
+use specs::{Join, WorldExt};
+
+use std::cmp::Ordering;
+
+/// How see-through the ghost's ship is drawn
+pub const GHOST_ALPHA: u8 = 0x66;
+
+/// Colours the score difference is shown in, ahead of the ghost, behind it or level
+const AHEAD_COLOUR: (u8, u8, u8) = (0x66, 0xDD, 0x66);
+const BEHIND_COLOUR: (u8, u8, u8) = (0xFF, 0x44, 0x44);
+const LEVEL_COLOUR: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+
+/// A previous run on the same seed, played back next to the live game for
+/// the player to race. It's got a simulation and so a world all of its own,
+/// kept in step with the live one, so nothing it does can touch the live game.
+pub struct Ghost<'a, 'b> {
+    replay: Replay,
+    sim: Simulation<'a, 'b>,
+}
+
+impl<'a, 'b> Ghost<'a, 'b> {
+    pub fn new(replay: Replay) -> Ghost<'a, 'b> {
+        let sim = replay.simulation();
+        Ghost { replay, sim }
+    }
+
+    /// Play the ghost's run up to `frame`, whatever frame the live game's on.
+    /// Once the run's over the ghost stays how it ended.
+    pub fn catch_up(&mut self, frame: u64) {
+        while self.sim.frame() < frame && self.replay.step(&mut self.sim) {}
+    }
+
+    /// The ghost's ship, where it is and where it was last frame, while it's still flying
+    pub fn ship(&self) -> Option<(Rendered, Position, Option<PreviousPosition>)> {
+        let world = &self.sim.world;
+        let player = world.read_storage::<Player>();
+        let rendered = world.read_storage::<Rendered>();
+        let position = world.read_storage::<Position>();
+        let prev = world.read_storage::<PreviousPosition>();
+
+        (&player, &rendered, &position, prev.maybe())
+            .join()
+            .next()
+            .map(|(_, rendered, pos, prev)| (*rendered, *pos, prev.copied()))
+    }
+
+    /// What the ghost has scored so far
+    pub fn score(&self) -> u32 {
+        self.sim.score(0)
+    }
+}
+
+/// How far `score` is ahead of the ghost's, like `+1200` or `-300`,
+/// and what colour to show it in
+pub fn score_difference(score: u32, ghost: u32) -> (String, (u8, u8, u8)) {
+    let difference = i64::from(score) - i64::from(ghost);
+    let colour = match difference.cmp(&0) {
+        Ordering::Greater => AHEAD_COLOUR,
+        Ordering::Less => BEHIND_COLOUR,
+        Ordering::Equal => LEVEL_COLOUR,
+    };
+
+    (format!("{:+}", difference), colour)
+}
@@ -0,0 +1,75 @@
+use ggez::{filesystem, Context, GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// Where the high scores are kept, in the user's config directory
+const SCORES_PATH: &str = "/highscores.toml";
+
+/// How many scores each table keeps
+const TABLE_SIZE: usize = 10;
+
+/// One score in a high score table, and what it was up against
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub score: u32,
+    // The preset and mutators, as `Rules::describe` puts them
+    pub rules: String,
+    pub seed: u64,
+}
+
+/// Every mode's high score table, best first, named by `Mode::table`
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HighScores {
+    tables: BTreeMap<String, Vec<Entry>>,
+}
+
+impl HighScores {
+    /// Load the high scores from the user's config, starting
+    /// afresh if nobody's set any yet
+    pub fn load(ctx: &mut Context) -> GameResult<HighScores> {
+        if !filesystem::exists(ctx, SCORES_PATH) {
+            return Ok(HighScores::default());
+        }
+
+        let mut contents = String::new();
+        filesystem::open(ctx, SCORES_PATH)?.read_to_string(&mut contents)?;
+
+        toml::from_str(&contents).map_err(|e| GameError::ConfigError(format!("bad high scores: {}", e)))
+    }
+
+    /// Save the high scores to the user's config
+    pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+        let contents =
+            toml::to_string(self).map_err(|e| GameError::ConfigError(format!("couldn't save high scores: {}", e)))?;
+        filesystem::create(ctx, SCORES_PATH)?.write_all(contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// The scores in table `table`, best first
+    pub fn table(&self, table: &str) -> &[Entry] {
+        self.tables.get(table).map_or(&[], |t| t.as_slice())
+    }
+
+    /// Put a score in table `table` if it's good enough,
+    /// returning where it placed counting from 0
+    pub fn add(&mut self, table: &str, entry: Entry) -> Option<usize> {
+        if entry.score == 0 {
+            return None;
+        }
+
+        let entries = self.tables.entry(table.to_string()).or_default();
+
+        // Ties go to whoever got there first
+        let place = entries.iter().position(|e| e.score < entry.score).unwrap_or(entries.len());
+        if place >= TABLE_SIZE {
+            return None;
+        }
+
+        entries.insert(place, entry);
+        entries.truncate(TABLE_SIZE);
+        Some(place)
+    }
+}
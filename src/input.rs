@@ -0,0 +1,510 @@
+use crate::game::InputState;
+
+use ggez::event::{Axis, Button, KeyCode};
+use ggez::{filesystem, Context, GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::io::{Read, Write};
+
+/// Where the controls are saved, relative to the user config dir
+const CONFIG_PATH: &str = "/controls.toml";
+
+/// How far a stick has to move before we count it
+const DEFAULT_DEADZONE: f32 = 0.2;
+
+/// Logical actions the player can take
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Fire,
+    Pause,
+    // Debugging: one tick forward while paused, or hold to go back
+    Step,
+    Rewind,
+    Controls,
+    Fullscreen,
+    Quit,
+}
+
+impl Action {
+    pub const ALL: [Action; 11] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Fire,
+        Action::Pause,
+        Action::Step,
+        Action::Rewind,
+        Action::Controls,
+        Action::Fullscreen,
+        Action::Quit,
+    ];
+
+    /// The actions that move a player's ship, which is all player 2 gets
+    pub const PLAYER: [Action; 5] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Fire,
+    ];
+
+    /// Name used in the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::Fire => "fire",
+            Action::Pause => "pause",
+            Action::Step => "step",
+            Action::Rewind => "rewind",
+            Action::Controls => "controls",
+            Action::Fullscreen => "fullscreen",
+            Action::Quit => "quit",
+        }
+    }
+
+    /// Name shown in the controls menu
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::MoveUp => "Up",
+            Action::MoveDown => "Down",
+            Action::MoveLeft => "Left",
+            Action::MoveRight => "Right",
+            Action::Fire => "Fire",
+            Action::Pause => "Pause",
+            Action::Step => "Step",
+            Action::Rewind => "Rewind",
+            Action::Controls => "Controls",
+            Action::Fullscreen => "Fullscreen",
+            Action::Quit => "Quit",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().cloned().find(|a| a.name() == name)
+    }
+
+    /// Update the input state for a digital press or release
+    pub fn apply(self, input: &mut InputState, pressed: bool) {
+        match self {
+            Action::MoveUp => input.up = pressed,
+            Action::MoveDown => input.down = pressed,
+            Action::MoveLeft => input.left = pressed,
+            Action::MoveRight => input.right = pressed,
+            Action::Fire => input.shoot = pressed,
+
+            // These are handled by the game itself
+            Action::Pause | Action::Step | Action::Rewind | Action::Controls | Action::Fullscreen | Action::Quit => {}
+        }
+    }
+}
+
+/// Keys that can be bound to actions
+const KEYS: &[KeyCode] = &[
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+    KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right,
+    KeyCode::Space, KeyCode::Return, KeyCode::Escape, KeyCode::Tab, KeyCode::Back,
+    KeyCode::LShift, KeyCode::RShift, KeyCode::LControl, KeyCode::RControl,
+    KeyCode::LAlt, KeyCode::RAlt,
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+];
+
+/// Gamepad buttons that can be bound to actions
+const BUTTONS: &[Button] = &[
+    Button::South, Button::East, Button::North, Button::West,
+    Button::LeftTrigger, Button::LeftTrigger2, Button::RightTrigger, Button::RightTrigger2,
+    Button::Select, Button::Start, Button::Mode,
+    Button::DPadUp, Button::DPadDown, Button::DPadLeft, Button::DPadRight,
+];
+
+/// Gamepad axes that can be used for movement
+const AXES: &[Axis] = &[
+    Axis::LeftStickX, Axis::LeftStickY, Axis::RightStickX, Axis::RightStickY,
+    Axis::DPadX, Axis::DPadY,
+];
+
+/// Look something up by the name it's saved under (its `Debug` output)
+fn parse_name<T: Copy + std::fmt::Debug>(all: &[T], name: &str) -> GameResult<T> {
+    all.iter()
+        .cloned()
+        .find(|t| format!("{:?}", t) == name)
+        .ok_or_else(|| GameError::ConfigError(format!("unknown control: {}", name)))
+}
+
+/// Look up a key by the name it's saved under, e.g. `Space` or `F1`
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    parse_name(KEYS, name).ok()
+}
+
+/// The controls as saved in the config file. Field order matters, see `Rules`.
+#[derive(Deserialize, Serialize)]
+struct Config {
+    move_x: String,
+    move_y: String,
+    deadzone: f32,
+    keys: BTreeMap<String, Vec<String>>,
+    // Missing from configs saved before player 2 could rebind, so they get the defaults
+    second_player_keys: Option<BTreeMap<String, Vec<String>>>,
+    buttons: BTreeMap<String, Vec<String>>,
+}
+
+/// Read back a table of controls saved by `save_bound`
+fn load_bound<T: Copy + Eq + Hash + std::fmt::Debug>(
+    all: &[T],
+    saved: &BTreeMap<String, Vec<String>>,
+) -> GameResult<HashMap<T, Action>> {
+    let mut bound = HashMap::new();
+    for (action, names) in saved {
+        let action = Action::from_name(action)
+            .ok_or_else(|| GameError::ConfigError(format!("unknown action: {}", action)))?;
+        for name in names {
+            bound.insert(parse_name(all, name)?, action);
+        }
+    }
+    Ok(bound)
+}
+
+/// Turn some controls into a table of action names to control names
+fn save_bound<T: std::fmt::Debug>(bound: &HashMap<T, Action>) -> BTreeMap<String, Vec<String>> {
+    let mut saved = BTreeMap::<_, Vec<_>>::new();
+    for (control, action) in bound {
+        let names = saved.entry(action.name().to_string()).or_default();
+        names.push(format!("{:?}", control));
+        names.sort();
+    }
+    saved
+}
+
+/// What happened when binding a key or button to an action
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rebound {
+    // It wasn't bound to anything else
+    Bound,
+    // It was, so that action got this one's old keys or buttons
+    Swapped(Action),
+    // It was, and this action had nothing to give back, so nothing changed
+    Taken(Action),
+}
+
+/// Bind `control` to `action` in place of whatever `action` had. Anything
+/// else it was bound to swaps over to `action`'s old controls, so nothing
+/// is ever left unbound and the controls menu can't be locked out.
+fn rebind<T: Copy + Eq + Hash>(bound: &mut HashMap<T, Action>, action: Action, control: T) -> Rebound {
+    let old = bound
+        .iter()
+        .filter(|&(&c, &a)| a == action && c != control)
+        .map(|(&c, _)| c)
+        .collect::<Vec<_>>();
+
+    let rebound = match bound.get(&control) {
+        Some(&other) if other != action && old.is_empty() => return Rebound::Taken(other),
+        Some(&other) if other != action => {
+            for &c in &old {
+                bound.insert(c, other);
+            }
+            Rebound::Swapped(other)
+        }
+        _ => {
+            for c in &old {
+                bound.remove(c);
+            }
+            Rebound::Bound
+        }
+    };
+
+    bound.insert(control, action);
+    rebound
+}
+
+/// Maps keys, buttons and sticks to actions
+pub struct Bindings {
+    keys: HashMap<KeyCode, Action>,
+    // Player 2's keys for sharing the keyboard, which win over player 1's
+    second_keys: HashMap<KeyCode, Action>,
+    buttons: HashMap<Button, Action>,
+    move_x: Axis,
+    move_y: Axis,
+    deadzone: f32,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        let keys = [
+            (KeyCode::W, Action::MoveUp),
+            (KeyCode::Up, Action::MoveUp),
+            (KeyCode::S, Action::MoveDown),
+            (KeyCode::Down, Action::MoveDown),
+            (KeyCode::A, Action::MoveLeft),
+            (KeyCode::Left, Action::MoveLeft),
+            (KeyCode::D, Action::MoveRight),
+            (KeyCode::Right, Action::MoveRight),
+            (KeyCode::Space, Action::Fire),
+            (KeyCode::P, Action::Pause),
+            (KeyCode::N, Action::Step),
+            (KeyCode::R, Action::Rewind),
+            (KeyCode::F1, Action::Controls),
+            (KeyCode::F11, Action::Fullscreen),
+            (KeyCode::Escape, Action::Quit),
+        ];
+
+        let buttons = [
+            (Button::DPadUp, Action::MoveUp),
+            (Button::DPadDown, Action::MoveDown),
+            (Button::DPadLeft, Action::MoveLeft),
+            (Button::DPadRight, Action::MoveRight),
+            (Button::South, Action::Fire),
+            (Button::Start, Action::Pause),
+            (Button::Select, Action::Controls),
+        ];
+
+        Bindings {
+            keys: keys.iter().cloned().collect(),
+            second_keys: Bindings::default_second_keys(),
+            buttons: buttons.iter().cloned().collect(),
+            move_x: Axis::LeftStickX,
+            move_y: Axis::LeftStickY,
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+}
+
+impl Bindings {
+    /// Load the controls from the user's config,
+    /// falling back to the defaults if there aren't any saved
+    pub fn load(ctx: &mut Context) -> GameResult<Bindings> {
+        if !filesystem::exists(ctx, CONFIG_PATH) {
+            return Ok(Bindings::default());
+        }
+
+        let mut contents = String::new();
+        filesystem::open(ctx, CONFIG_PATH)?.read_to_string(&mut contents)?;
+
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| GameError::ConfigError(format!("bad controls config: {}", e)))?;
+
+        Bindings::from_config(&config)
+    }
+
+    fn from_config(config: &Config) -> GameResult<Bindings> {
+        let second_keys = match &config.second_player_keys {
+            Some(saved) => load_bound(KEYS, saved)?,
+            None => Bindings::default_second_keys(),
+        };
+
+        Ok(Bindings {
+            keys: load_bound(KEYS, &config.keys)?,
+            second_keys,
+            buttons: load_bound(BUTTONS, &config.buttons)?,
+            move_x: parse_name(AXES, &config.move_x)?,
+            move_y: parse_name(AXES, &config.move_y)?,
+            deadzone: config.deadzone,
+        })
+    }
+
+    fn to_config(&self) -> Config {
+        Config {
+            move_x: format!("{:?}", self.move_x),
+            move_y: format!("{:?}", self.move_y),
+            deadzone: self.deadzone,
+            keys: save_bound(&self.keys),
+            second_player_keys: Some(save_bound(&self.second_keys)),
+            buttons: save_bound(&self.buttons),
+        }
+    }
+
+    /// Player 2 gets the arrow keys by default
+    fn default_second_keys() -> HashMap<KeyCode, Action> {
+        let keys = [
+            (KeyCode::Up, Action::MoveUp),
+            (KeyCode::Down, Action::MoveDown),
+            (KeyCode::Left, Action::MoveLeft),
+            (KeyCode::Right, Action::MoveRight),
+            (KeyCode::Return, Action::Fire),
+        ];
+        keys.iter().cloned().collect()
+    }
+
+    /// Save the controls to the user's config
+    pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+        let contents = toml::to_string(&self.to_config())
+            .map_err(|e| GameError::ConfigError(format!("couldn't save controls: {}", e)))?;
+        filesystem::create(ctx, CONFIG_PATH)?.write_all(contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// What action, if any, a key is bound to
+    pub fn key(&self, key: KeyCode) -> Option<Action> {
+        self.keys.get(&key).cloned()
+    }
+
+    /// Which player a key is for and what action it's bound to, if any.
+    /// With more than one player, player 2's keys win over player 1's.
+    pub fn player_key(&self, key: KeyCode, players: usize) -> Option<(usize, Action)> {
+        match self.second_keys.get(&key) {
+            Some(&action) if players > 1 => Some((1, action)),
+            _ => self.key(key).map(|action| (0, action)),
+        }
+    }
+
+    /// The keys for a player sharing the keyboard
+    fn player_keys(&self, player: usize) -> &HashMap<KeyCode, Action> {
+        if player == 0 {
+            &self.keys
+        } else {
+            &self.second_keys
+        }
+    }
+
+    /// What action, if any, a gamepad button is bound to
+    pub fn button(&self, button: Button) -> Option<Action> {
+        self.buttons.get(&button).cloned()
+    }
+
+    /// Update the analog stick position in the input state
+    pub fn axis(&self, input: &mut InputState, axis: Axis, value: f32) {
+        let value = if value.abs() < self.deadzone { 0. } else { value };
+
+        if axis == self.move_x {
+            input.stick.0 = value;
+        } else if axis == self.move_y {
+            // Sticks report up as positive but our y axis points down
+            input.stick.1 = -value;
+        }
+    }
+
+    /// Replace the keys bound to one of a player's actions with just `key`, see `rebind`
+    pub fn bind_key(&mut self, player: usize, action: Action, key: KeyCode) -> Rebound {
+        let keys = if player == 0 {
+            &mut self.keys
+        } else {
+            &mut self.second_keys
+        };
+        rebind(keys, action, key)
+    }
+
+    /// Replace the buttons bound to an action with just `button`, see `rebind`
+    pub fn bind_button(&mut self, action: Action, button: Button) -> Rebound {
+        rebind(&mut self.buttons, action, button)
+    }
+
+    /// Describe what one of a player's actions is bound to, for the controls menu.
+    /// Gamepads all share player 1's buttons, so only they get listed.
+    pub fn describe(&self, player: usize, action: Action) -> String {
+        let buttons = if player == 0 { Some(&self.buttons) } else { None };
+        let mut names = self
+            .player_keys(player)
+            .iter()
+            .filter(|&(_, a)| *a == action)
+            .map(|(k, _)| format!("{:?}", k))
+            .chain(
+                buttons
+                    .into_iter()
+                    .flatten()
+                    .filter(|&(_, a)| *a == action)
+                    .map(|(b, _)| format!("Pad {:?}", b)),
+            ).collect::<Vec<_>>();
+        names.sort();
+        names.join(", ")
+    }
+}
+
+/// In-game menu for rebinding controls
+#[derive(Default)]
+pub struct ControlsMenu {
+    // Which entry is highlighted
+    pub selected: usize,
+    // Whether we're waiting for a key or button to bind
+    pub waiting: bool,
+    // How the last binding went, if it touched another action
+    pub message: Option<String>,
+}
+
+impl ControlsMenu {
+    /// Every player and action that can be rebound: all of player 1's,
+    /// then the keys player 2 uses when sharing the keyboard
+    pub fn entries() -> impl Iterator<Item = (usize, Action)> {
+        let first = Action::ALL.iter().map(|&a| (0, a));
+        first.chain(Action::PLAYER.iter().map(|&a| (1, a)))
+    }
+
+    /// The currently highlighted player and action
+    pub fn entry(&self) -> (usize, Action) {
+        ControlsMenu::entries().nth(self.selected).unwrap()
+    }
+
+    /// Move the highlight up or down, wrapping around
+    pub fn move_selection(&mut self, down: bool) {
+        let len = Action::ALL.len() + Action::PLAYER.len();
+        self.selected = if down {
+            (self.selected + 1) % len
+        } else {
+            (self.selected + len - 1) % len
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_swaps_instead_of_unbinding() {
+        let mut bindings = Bindings::default();
+
+        assert_eq!(bindings.bind_key(0, Action::Quit, KeyCode::Space), Rebound::Swapped(Action::Fire));
+        assert_eq!(bindings.key(KeyCode::Space), Some(Action::Quit));
+        assert_eq!(bindings.key(KeyCode::Escape), Some(Action::Fire));
+
+        // Controls can't be left without a key
+        assert_eq!(bindings.bind_key(0, Action::Fire, KeyCode::F1), Rebound::Swapped(Action::Controls));
+        assert_eq!(bindings.key(KeyCode::Escape), Some(Action::Controls));
+    }
+
+    #[test]
+    fn player_two_rebinds_and_saves_like_player_one() {
+        let mut bindings = Bindings::default();
+        assert_eq!(bindings.player_key(KeyCode::Up, 2), Some((1, Action::MoveUp)));
+        assert_eq!(bindings.player_key(KeyCode::Up, 1), Some((0, Action::MoveUp)));
+
+        // Swapping only touches player 2's own keys
+        assert_eq!(bindings.bind_key(1, Action::Fire, KeyCode::Up), Rebound::Swapped(Action::MoveUp));
+        assert_eq!(bindings.player_key(KeyCode::Up, 2), Some((1, Action::Fire)));
+        assert_eq!(bindings.player_key(KeyCode::Return, 2), Some((1, Action::MoveUp)));
+        assert_eq!(bindings.key(KeyCode::Up), Some(Action::MoveUp));
+
+        let saved = toml::to_string(&bindings.to_config()).unwrap();
+        let loaded = Bindings::from_config(&toml::from_str(&saved).unwrap()).unwrap();
+        assert_eq!(loaded.player_key(KeyCode::Up, 2), Some((1, Action::Fire)));
+        assert_eq!(loaded.player_key(KeyCode::Return, 2), Some((1, Action::MoveUp)));
+
+        // Configs from before player 2 could rebind get the arrow keys
+        let mut old = Bindings::default().to_config();
+        old.second_player_keys = None;
+        let loaded = Bindings::from_config(&toml::from_str(&toml::to_string(&old).unwrap()).unwrap()).unwrap();
+        assert_eq!(loaded.player_key(KeyCode::Return, 2), Some((1, Action::Fire)));
+    }
+
+    #[test]
+    fn rebinding_refuses_when_there_is_nothing_to_swap() {
+        let mut bindings = Bindings::default();
+
+        assert_eq!(bindings.bind_button(Action::Quit, Button::South), Rebound::Taken(Action::Fire));
+        assert_eq!(bindings.button(Button::South), Some(Action::Fire));
+    }
+}
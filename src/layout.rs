@@ -0,0 +1,478 @@
+use crate::entities::PLAYER_COLOURS;
+use crate::game;
+
+use ggez::graphics::{self, Align, Color, MeshBuilder, Rect, Text};
+use ggez::{filesystem, timer, Context, GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use std::f32;
+use std::io::Read;
+use std::str::FromStr;
+
+/// Where the player's pick of layout is kept, in their config directory,
+/// along with whether the screen is rotated
+const CONFIG_PATH: &str = "/layout.toml";
+
+/// How much room the sidebar takes up to the right of the game area
+const SIDEBAR_WIDTH: f32 = 100.;
+
+/// BG colour of sidebar ui
+const SIDEBAR_COLOUR: (u8, u8, u8) = (0x55, 0x55, 0x55);
+
+/// How tall the arcade style HUD strips above and below the game area are
+const HUD_HEIGHT: f32 = 40.;
+
+/// How much of the arcade HUD's bottom strip the stage badges get
+const BADGES_WIDTH: f32 = 90.;
+
+/// Stage badges, biggest first: how many stages each stands for and its colour.
+/// Like the original, stage 17 shows a 10, a 5 and two 1s.
+const BADGES: [(u32, (u8, u8, u8)); 6] = [
+    (50, (0xFF, 0xFF, 0xFF)),
+    (30, (0x33, 0xFF, 0x66)),
+    (20, (0xFF, 0x66, 0xFF)),
+    (10, (0x33, 0xAA, 0xFF)),
+    (5, (0xFF, 0xDD, 0x33)),
+    (1, (0xFF, 0x33, 0x33)),
+];
+
+/// How wide each stage badge is, and the gap between them
+const BADGE_WIDTH: f32 = 6.;
+const BADGE_GAP: f32 = 3.;
+
+/// Where the HUD goes around the game area
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutKind {
+    // Everything in a column to the right
+    #[default]
+    Sidebar,
+    // Score on top, health along the bottom like the original Galaga
+    Arcade,
+}
+
+impl FromStr for LayoutKind {
+    type Err = GameError;
+
+    fn from_str(s: &str) -> GameResult<LayoutKind> {
+        match s {
+            "sidebar" => Ok(LayoutKind::Sidebar),
+            "arcade" => Ok(LayoutKind::Arcade),
+            _ => Err(GameError::ConfigError(format!("unknown layout: {}", s))),
+        }
+    }
+}
+
+/// The layout as saved in the config file, e.g. `layout = "arcade"` and `tate = true`.
+/// Either can be left out, which gets the sidebar on an unrotated screen.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub layout: LayoutKind,
+    // Rotate everything for portrait (TATE) monitors
+    pub tate: bool,
+}
+
+impl Config {
+    /// Load the player's pick of layout from their config,
+    /// the defaults if they haven't picked one
+    pub fn load(ctx: &mut Context) -> GameResult<Config> {
+        if !filesystem::exists(ctx, CONFIG_PATH) {
+            return Ok(Config::default());
+        }
+
+        let mut contents = String::new();
+        filesystem::open(ctx, CONFIG_PATH)?.read_to_string(&mut contents)?;
+
+        toml::from_str(&contents).map_err(|e| GameError::ConfigError(format!("bad layout config: {}", e)))
+    }
+}
+
+/// What a widget shows
+pub enum WidgetKind {
+    // Solid background
+    Panel,
+    // Some fixed text
+    Label(Text),
+    // A player's name, which blinks while it's their turn
+    PlayerLabel { player: usize, text: Text },
+    // A player's current score
+    Score { player: usize },
+    // What a player's kills are being multiplied by
+    Multiplier { player: usize },
+    // The best score in the mode's table, or the one being played if it's better
+    HighScore,
+    // Badges counting up which stage it is, in modes that have stages
+    Stage,
+    // How much health a player has left, filling up from the
+    // bottom if vertical or from the left otherwise
+    HealthBar { player: usize, vertical: bool },
+    // How far ahead of the ghost the player is, under a label
+    // saying so. Only shown while racing one.
+    GhostDifference(Text),
+}
+
+/// A single piece of the HUD
+pub struct Widget {
+    pub kind: WidgetKind,
+    pub area: Rect,
+    pub colour: Color,
+}
+
+impl Widget {
+    fn panel(area: [f32; 4], colour: (u8, u8, u8)) -> Widget {
+        Widget {
+            kind: WidgetKind::Panel,
+            area: area.into(),
+            colour: colour.into(),
+        }
+    }
+
+    fn label(text: &str, area: [f32; 4], colour: Color) -> Widget {
+        let mut text = Text::new(text);
+        text.set_bounds([area[2], f32::INFINITY], Align::Center);
+
+        Widget {
+            kind: WidgetKind::Label(text),
+            area: area.into(),
+            colour,
+        }
+    }
+
+    fn player_label(player: usize, area: [f32; 4], colour: Color) -> Widget {
+        let mut text = Text::new(format!("{}UP", player + 1));
+        text.set_bounds([area[2], f32::INFINITY], Align::Center);
+
+        Widget {
+            kind: WidgetKind::PlayerLabel { player, text },
+            area: area.into(),
+            colour,
+        }
+    }
+
+    fn score(player: usize, area: [f32; 4], colour: Color) -> Widget {
+        Widget {
+            kind: WidgetKind::Score { player },
+            area: area.into(),
+            colour,
+        }
+    }
+
+    fn multiplier(player: usize, area: [f32; 4], colour: Color) -> Widget {
+        Widget {
+            kind: WidgetKind::Multiplier { player },
+            area: area.into(),
+            colour,
+        }
+    }
+
+    fn high_score(area: [f32; 4], colour: Color) -> Widget {
+        Widget {
+            kind: WidgetKind::HighScore,
+            area: area.into(),
+            colour,
+        }
+    }
+
+    fn stage(area: [f32; 4]) -> Widget {
+        Widget {
+            kind: WidgetKind::Stage,
+            area: area.into(),
+            colour: graphics::WHITE,
+        }
+    }
+
+    fn ghost_difference(area: [f32; 4]) -> Widget {
+        let mut text = Text::new("GHOST");
+        text.set_bounds([area[2], f32::INFINITY], Align::Center);
+
+        Widget {
+            kind: WidgetKind::GhostDifference(text),
+            area: area.into(),
+            colour: graphics::WHITE,
+        }
+    }
+
+    fn health_bar(player: usize, area: [f32; 4], vertical: bool, colour: Color) -> Widget {
+        Widget {
+            kind: WidgetKind::HealthBar { player, vertical },
+            area: area.into(),
+            colour,
+        }
+    }
+}
+
+/// What colour a player's stats are shown in
+fn player_colour(player: usize) -> Color {
+    PLAYER_COLOURS[player % PLAYER_COLOURS.len()].into()
+}
+
+/// Colour the multiplier's shown in, when there's no player colour to use
+const MULTIPLIER_COLOUR: (u8, u8, u8) = (0xFF, 0xDD, 0x33);
+
+/// What the HUD shows for one player
+pub struct PlayerHud<'a> {
+    pub score: &'a Text,
+    pub multiplier: &'a Text,
+    // How much of their starting health they have left, out of 1
+    pub health: f32,
+}
+
+/// What the HUD shows for the game as a whole
+pub struct GameHud<'a> {
+    pub high_score: &'a Text,
+    // Which stage it is, if the game has them
+    pub stage: Option<u32>,
+    // How far ahead of the ghost the player is, while racing one
+    pub ghost: Option<&'a Text>,
+}
+
+/// Where everything on screen goes
+pub struct Layout {
+    // Area covering the game and HUD, in the game area's coordinates
+    pub bounds: Rect,
+    pub widgets: Vec<Widget>,
+}
+
+impl Layout {
+    /// Lay out the HUD for however many players are playing
+    pub fn new(kind: LayoutKind, players: usize) -> Layout {
+        match (kind, players) {
+            (LayoutKind::Sidebar, 1) => Layout::sidebar(),
+            (LayoutKind::Sidebar, _) => Layout::sidebar_players(players),
+            (LayoutKind::Arcade, 1) => Layout::arcade(),
+            (LayoutKind::Arcade, _) => Layout::arcade_players(players),
+        }
+    }
+
+    /// Make room for how the player's doing against a ghost,
+    /// which only ever races one player
+    pub fn with_ghost(mut self, kind: LayoutKind) -> Layout {
+        let area = match kind {
+            LayoutKind::Sidebar => [game::GAME_WIDTH, 475., SIDEBAR_WIDTH, 40.],
+            LayoutKind::Arcade => [game::GAME_WIDTH * 2. / 3., -36., game::GAME_WIDTH / 3. - 60., 34.],
+        };
+        self.widgets.push(Widget::ghost_difference(area));
+        self
+    }
+
+    /// Sidebar to the right of the game area
+    fn sidebar() -> Layout {
+        let x = game::GAME_WIDTH;
+
+        Layout {
+            bounds: [0., 0., game::GAME_WIDTH + SIDEBAR_WIDTH, game::GAME_HEIGHT].into(),
+            widgets: vec![
+                Widget::panel([x, 0., SIDEBAR_WIDTH, game::GAME_HEIGHT], SIDEBAR_COLOUR),
+                Widget::label("HEALTH", [x, 15., SIDEBAR_WIDTH, 20.], graphics::WHITE),
+                Widget::health_bar(0, [x + 27., 47., 46., 206.], true, (0x00, 0xFF, 0x00).into()),
+                Widget::label("SCORE", [x, 315., SIDEBAR_WIDTH, 20.], graphics::WHITE),
+                Widget::score(0, [x, 335., SIDEBAR_WIDTH, 20.], graphics::WHITE),
+                Widget::label("COMBO", [x, 395., SIDEBAR_WIDTH, 20.], graphics::WHITE),
+                Widget::multiplier(0, [x, 415., SIDEBAR_WIDTH, 20.], MULTIPLIER_COLOUR.into()),
+            ],
+        }
+    }
+
+    /// The sidebar split into a section for each player,
+    /// each in that player's colour
+    fn sidebar_players(players: usize) -> Layout {
+        let x = game::GAME_WIDTH;
+        let h = game::GAME_HEIGHT / players as f32;
+
+        let mut widgets = vec![Widget::panel([x, 0., SIDEBAR_WIDTH, game::GAME_HEIGHT], SIDEBAR_COLOUR)];
+        for player in 0..players {
+            let y = h * player as f32;
+            let colour = player_colour(player);
+
+            widgets.push(Widget::player_label(player, [x, y + 10., SIDEBAR_WIDTH, 20.], colour));
+            widgets.push(Widget::health_bar(player, [x + 27., y + 35., 46., h - 95.], true, colour));
+            widgets.push(Widget::score(player, [x, y + h - 45., SIDEBAR_WIDTH, 20.], colour));
+            widgets.push(Widget::multiplier(player, [x, y + h - 25., SIDEBAR_WIDTH, 20.], colour));
+        }
+
+        Layout {
+            bounds: [0., 0., game::GAME_WIDTH + SIDEBAR_WIDTH, game::GAME_HEIGHT].into(),
+            widgets,
+        }
+    }
+
+    /// HUD strips above and below the game area, score and high score
+    /// on top and health and stage badges along the bottom
+    fn arcade() -> Layout {
+        let w = game::GAME_WIDTH;
+        let third = w / 3.;
+        let bottom = game::GAME_HEIGHT;
+        let red = (0xFF, 0x00, 0x00, 0xFF).into();
+
+        Layout {
+            bounds: [0., -HUD_HEIGHT, w, game::GAME_HEIGHT + 2. * HUD_HEIGHT].into(),
+            widgets: vec![
+                Widget::panel([0., -HUD_HEIGHT, w, HUD_HEIGHT], (0x00, 0x00, 0x00)),
+                Widget::panel([0., bottom, w, HUD_HEIGHT], (0x00, 0x00, 0x00)),
+                Widget::label("SCORE", [0., -36., third, 16.], red),
+                Widget::score(0, [0., -18., third, 16.], graphics::WHITE),
+                Widget::label("HIGH SCORE", [third, -36., third, 16.], red),
+                Widget::high_score([third, -18., third, 16.], graphics::WHITE),
+                Widget::multiplier(0, [w - 60., -18., 50., 16.], MULTIPLIER_COLOUR.into()),
+                Widget::label("HEALTH", [10., bottom + 12., 80., 16.], graphics::WHITE),
+                Widget::health_bar(
+                    0,
+                    [100., bottom + 8., w - 110. - BADGES_WIDTH, 24.],
+                    false,
+                    (0x00, 0xFF, 0x00).into(),
+                ),
+                Widget::stage([w - BADGES_WIDTH, bottom + 8., BADGES_WIDTH - 10., 24.]),
+            ],
+        }
+    }
+
+    /// Arcade strips with a column for each player, 1UP, 2UP and so on, with
+    /// the high score in the middle. The stage badges share the bottom strip.
+    fn arcade_players(players: usize) -> Layout {
+        let w = game::GAME_WIDTH / (players + 1) as f32;
+        let bar_w = (game::GAME_WIDTH - BADGES_WIDTH) / players as f32;
+        let middle = players.div_ceil(2);
+        let bottom = game::GAME_HEIGHT;
+        let red = (0xFF, 0x00, 0x00, 0xFF).into();
+
+        let mut widgets = vec![
+            Widget::panel([0., -HUD_HEIGHT, game::GAME_WIDTH, HUD_HEIGHT], (0x00, 0x00, 0x00)),
+            Widget::panel([0., bottom, game::GAME_WIDTH, HUD_HEIGHT], (0x00, 0x00, 0x00)),
+            Widget::label("HIGH SCORE", [w * middle as f32, -36., w, 16.], red),
+            Widget::high_score([w * middle as f32, -18., w, 16.], graphics::WHITE),
+            Widget::stage([game::GAME_WIDTH - BADGES_WIDTH, bottom + 8., BADGES_WIDTH - 10., 24.]),
+        ];
+        for player in 0..players {
+            let column = if player < middle { player } else { player + 1 };
+            let x = w * column as f32;
+            let colour = player_colour(player);
+
+            widgets.push(Widget::player_label(player, [x, -36., w, 16.], red));
+            widgets.push(Widget::score(player, [x, -18., w, 16.], colour));
+            widgets.push(Widget::multiplier(player, [x + w - 30., -36., 30., 16.], colour));
+
+            let x = bar_w * player as f32;
+            widgets.push(Widget::health_bar(player, [x + 10., bottom + 8., bar_w - 20., 24.], false, colour));
+        }
+
+        Layout {
+            bounds: [0., -HUD_HEIGHT, game::GAME_WIDTH, game::GAME_HEIGHT + 2. * HUD_HEIGHT].into(),
+            widgets,
+        }
+    }
+
+    /// Draw the HUD given the game's and each player's current stats and whose
+    /// turn it is if they're taking turns. Without the HUD only the panels get drawn.
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        ui: &mut MeshBuilder,
+        game: &GameHud,
+        players: &[PlayerHud],
+        up: Option<usize>,
+        hud: bool,
+    ) {
+        let fill = graphics::DrawMode::fill();
+        let blink = timer::ticks(ctx) % 60 < 30;
+
+        for widget in self.widgets.iter().filter(|w| hud || matches!(w.kind, WidgetKind::Panel)) {
+            let area = widget.area;
+
+            match &widget.kind {
+                WidgetKind::Panel => {
+                    ui.rectangle(fill, area, widget.colour);
+                }
+
+                WidgetKind::Label(text) => {
+                    graphics::queue_text(ctx, text, [area.x, area.y], Some(widget.colour));
+                }
+
+                WidgetKind::PlayerLabel { player, text } => {
+                    if up != Some(*player) || blink {
+                        graphics::queue_text(ctx, text, [area.x, area.y], Some(widget.colour));
+                    }
+                }
+
+                WidgetKind::Score { player } => {
+                    // The score text is shared, so center it ourselves
+                    if let Some(p) = players.get(*player) {
+                        let x = area.x + (area.w - p.score.width(ctx) as f32) / 2.;
+                        graphics::queue_text(ctx, p.score, [x, area.y], Some(widget.colour));
+                    }
+                }
+
+                WidgetKind::Multiplier { player } => {
+                    if let Some(p) = players.get(*player) {
+                        let x = area.x + (area.w - p.multiplier.width(ctx) as f32) / 2.;
+                        graphics::queue_text(ctx, p.multiplier, [x, area.y], Some(widget.colour));
+                    }
+                }
+
+                WidgetKind::HighScore => {
+                    let x = area.x + (area.w - game.high_score.width(ctx) as f32) / 2.;
+                    graphics::queue_text(ctx, game.high_score, [x, area.y], Some(widget.colour));
+                }
+
+                WidgetKind::Stage => {
+                    for (badge, colour) in game.stage.map(|s| stage_badges(area, s)).unwrap_or_default() {
+                        ui.rectangle(fill, badge, colour);
+                    }
+                }
+
+                WidgetKind::HealthBar { player, vertical } => {
+                    // The health bar BG
+                    ui.rectangle(fill, area, graphics::BLACK);
+
+                    // The health bar itself
+                    let health = players.get(*player).map_or(0., |p| p.health);
+                    ui.rectangle(fill, health_bar_fill(area, *vertical, health), widget.colour);
+                }
+
+                WidgetKind::GhostDifference(label) => {
+                    // The difference is coloured by how the race is going
+                    if let Some(difference) = game.ghost {
+                        graphics::queue_text(ctx, label, [area.x, area.y], Some(widget.colour));
+                        let x = area.x + (area.w - difference.width(ctx) as f32) / 2.;
+                        graphics::queue_text(ctx, difference, [x, area.y + area.h / 2.], None);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The filled in part of a health bar, just inside its BG,
+/// for `health` out of 1 being full
+pub fn health_bar_fill(area: Rect, vertical: bool, health: f32) -> Rect {
+    let inner = Rect::new(area.x + 3., area.y + 3., area.w - 6., area.h - 6.);
+    let health = health.clamp(0., 1.);
+
+    if vertical {
+        let h = inner.h * health;
+        Rect::new(inner.x, inner.y + inner.h - h, inner.w, h)
+    } else {
+        Rect::new(inner.x, inner.y, inner.w * health, inner.h)
+    }
+}
+
+/// Badges showing it's stage `stage`, lined up from the right of `area`, and
+/// what colour each is. The 1s are shorter, and any that don't fit are left off.
+pub fn stage_badges(area: Rect, stage: u32) -> Vec<(Rect, Color)> {
+    let mut badges = vec![];
+    let mut left = stage;
+    let mut x = area.x + area.w;
+
+    for &(worth, colour) in &BADGES {
+        while left >= worth && x - BADGE_WIDTH >= area.x {
+            left -= worth;
+            x -= BADGE_WIDTH;
+
+            let h = if worth == 1 { area.h * 2. / 3. } else { area.h };
+            badges.push((Rect::new(x, area.y + area.h - h, BADGE_WIDTH, h), colour.into()));
+            x -= BADGE_GAP;
+        }
+    }
+
+    badges
+}
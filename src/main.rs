@@ -5,7 +5,7 @@
 #[macro_use]
 extern crate specs_derive;
 
-use ggez::{conf, event, ContextBuilder, GameError};
+use ggez::{conf, event, graphics, ContextBuilder, GameError};
 
 /// The various components the entities in our game can have
 mod components;
@@ -16,19 +16,168 @@ mod entities;
 /// The systems that can act on our entities
 mod systems;
 
+/// Adjusts the difficulty to how the players are doing
+mod director;
+
+/// Difficulty presets and gameplay mutators
+mod rules;
+
+/// Stages, challenging stages and the results between them
+mod stages;
+
+/// Kill chains, multipliers and bonuses
+mod scoring;
+
+/// Game modes and how far through their goals the players are
+mod modes;
+
+/// Each mode's high score table
+mod highscores;
+
+/// Pooled particle effects
+mod particles;
+
+/// Scrolling starfield background
+mod starfield;
+
+/// Sound effects and music
+mod audio;
+
+/// Retro sound effect synthesizer
+mod sfxr;
+
+/// Rebindable keyboard and gamepad controls
+mod input;
+
+/// Where the HUD goes
+mod layout;
+
+/// Scales the game to fit the window
+mod viewport;
+
+/// The game simulation, independent of any frontend
+mod sim;
+
+/// Plays the game in a terminal
+mod tui;
+
+/// Recording and playing back games
+mod replay;
+
+/// Headless software renderer for screenshots
+mod render;
+
+/// Plays the game by itself
+mod bot;
+
+/// Playing together online with rollback
+mod netplay;
+
+/// Gym-style environment for training agents
+mod env;
+
+/// Players taking turns on their own games
+mod turns;
+
+/// Racing a previous run's ghost
+mod ghost;
+
+/// Stepping through and rewinding the game for debugging
+mod rewind;
+
 /// Main game state structure and game loop
 mod game;
 
 fn main() -> Result<(), GameError> {
+    // Figure out how we were asked to run
+    let mut options = game::GameOptions::from_args(std::env::args().skip(1))?;
+
+    // Just dump our sound effects if asked to
+    if let Some(dir) = &options.export_sfx {
+        return sfxr::export(dir);
+    }
+
+    // Soak tests just run the bot as fast as possible
+    if let Some(frames) = options.soak {
+        return bot::soak(&options, frames);
+    }
+
+    // So does checking that online games stay in step
+    if let Some(frames) = options.netplay_check {
+        return netplay::check(&options, frames);
+    }
+
+    // Training environments don't need a window either
+    if options.env {
+        return env::serve(&options);
+    }
+    if let Some(count) = options.env_bench {
+        return env::bench(&options, count);
+    }
+
+    // Replays are exported without a window
+    if let Some(out) = &options.export {
+        let replay = options
+            .replay
+            .as_ref()
+            .ok_or_else(|| GameError::ConfigError("--export needs a --replay to export".to_string()))?;
+        return render::export(&options, replay, out);
+    }
+
+    // Screenshots are drawn without one too
+    if options.screenshot.is_some() || options.golden.is_some() {
+        return render::screenshot(&options);
+    }
+
+    // The terminal frontend doesn't need a window at all
+    if options.tui {
+        return tui::run(&options);
+    }
+
     // Create a new ggez Context
+    // Start with the window just big enough for the game and HUD
+    let players = options.players;
+    let window_size = |kind, tate| {
+        let bounds = layout::Layout::new(kind, players).bounds;
+        viewport::Viewport::window_size(bounds, tate)
+    };
+    let (width, height) = window_size(options.layout.unwrap_or_default(), options.tate);
+
+    // The window can be resized freely, the game is scaled to fit
+    let fullscreen = if options.fullscreen {
+        conf::FullscreenType::Desktop
+    } else {
+        conf::FullscreenType::Windowed
+    };
+
     let (ctx, evt_loop) = &mut ContextBuilder::new("Galaga", "Adcoba")
         .window_setup(conf::WindowSetup::default().title("Galaga"))
         .window_mode(
-            conf::WindowMode::default().dimensions(game::WINDOW_WIDTH, game::WINDOW_HEIGHT),
+            conf::WindowMode::default()
+                .dimensions(width, height)
+                .resizable(true)
+                .fullscreen_type(fullscreen),
         ).build()?;
 
+    // Unless they were picked on the command line, the layout and TATE come from the player's
+    // config, which can only be read once there's a context. Refit the window to them.
+    if options.layout.is_none() || !options.tate {
+        let config = layout::Config::load(ctx).unwrap_or_else(|e| {
+            eprintln!("Couldn't load layout, using the defaults: {}", e);
+            layout::Config::default()
+        });
+        let kind = options.layout.unwrap_or(config.layout);
+        let tate = options.tate || config.tate;
+        if (kind, tate) != (options.layout.unwrap_or_default(), options.tate) {
+            let (width, height) = window_size(kind, tate);
+            graphics::set_drawable_size(ctx, width, height)?;
+        }
+        options.layout = Some(kind);
+        options.tate = tate;
+    }
+
     // Create our main game state
-    let state = &mut game::Galaga::new();
+    let state = &mut game::Galaga::new(ctx, options)?;
 
     // Kick off the main loop
     event::run(ctx, evt_loop, state)
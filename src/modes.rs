@@ -0,0 +1,309 @@
+use crate::game::DESIRED_FPS;
+
+use ggez::{GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How long a score attack lasts
+const TIME_LIMIT: u64 = 3 * 60 * DESIRED_FPS as u64;
+
+/// Bosses to beat in a boss rush
+pub const BOSSES: u32 = 5;
+
+/// Frames before the first boss, and between one going down and the next
+const BOSS_BREAK: u64 = 3 * DESIRED_FPS as u64;
+
+/// What kind of game is being played
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    // Stage after stage, with challenging stages between
+    #[default]
+    Classic,
+    // Baddies just keep coming until everyone's out
+    Endless,
+    // As many points as possible before time's up
+    ScoreAttack,
+    // One boss after another and nothing else
+    BossRush,
+    // Classic stages on a seed and mutator everyone gets for the day
+    Daily,
+}
+
+/// How baddies turn up in a mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Schedule {
+    // Split into stages, see `Stages`
+    Stages,
+    // One long stream of noobs and wavers
+    Waves,
+    // A boss at a time
+    Bosses,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 5] = [Mode::Classic, Mode::Endless, Mode::ScoreAttack, Mode::BossRush, Mode::Daily];
+
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Classic => "classic",
+            Mode::Endless => "endless",
+            Mode::ScoreAttack => "score-attack",
+            Mode::BossRush => "boss-rush",
+            Mode::Daily => "daily",
+        }
+    }
+
+    /// Name shown in menus and high scores
+    pub fn label(self) -> &'static str {
+        match self {
+            Mode::Classic => "CLASSIC",
+            Mode::Endless => "ENDLESS",
+            Mode::ScoreAttack => "SCORE ATTACK",
+            Mode::BossRush => "BOSS RUSH",
+            Mode::Daily => "DAILY CHALLENGE",
+        }
+    }
+
+    pub fn schedule(self) -> Schedule {
+        match self {
+            Mode::Classic | Mode::Daily => Schedule::Stages,
+            Mode::Endless | Mode::ScoreAttack => Schedule::Waves,
+            Mode::BossRush => Schedule::Bosses,
+        }
+    }
+
+    /// Frames the game lasts, if it doesn't just go on until everyone's out
+    pub fn time_limit(self) -> Option<u64> {
+        match self {
+            Mode::ScoreAttack => Some(TIME_LIMIT),
+            _ => None,
+        }
+    }
+
+    /// What the mode's called when playing on `seed`, which
+    /// only matters for the daily challenge
+    pub fn heading(self, seed: u64) -> String {
+        match self {
+            Mode::Daily => format!("{} {}", self.label(), Date::from_seed(seed)),
+            _ => self.label().to_string(),
+        }
+    }
+
+    /// Which high score table games on `seed` go in.
+    /// Every day's challenge gets a table of its own.
+    pub fn table(self, seed: u64) -> String {
+        match self {
+            Mode::Daily => format!("{}-{}", self.name(), seed),
+            _ => self.name().to_string(),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = GameError;
+
+    fn from_str(s: &str) -> GameResult<Mode> {
+        Mode::ALL
+            .iter()
+            .cloned()
+            .find(|m| m.name() == s)
+            .ok_or_else(|| GameError::ConfigError(format!("unknown mode: {}", s)))
+    }
+}
+
+/// A day on the calendar, in UTC so everyone's on the same daily challenge
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Date {
+    pub year: u64,
+    pub month: u64,
+    pub day: u64,
+}
+
+impl Date {
+    pub fn today() -> Date {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        Date::from_days(secs / 86400)
+    }
+
+    /// The date `days` days after 1970-01-01
+    fn from_days(days: u64) -> Date {
+        // Count from March 1st, year 0, so leap days fall at the end of each year
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let m = (5 * day_of_year + 2) / 153;
+
+        let day = day_of_year - (153 * m + 2) / 5 + 1;
+        let month = if m < 10 { m + 3 } else { m - 9 };
+        let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+        Date { year, month, day }
+    }
+
+    /// The daily challenge's seed, which reads as the date, e.g. 20261018
+    pub fn seed(self) -> u64 {
+        self.year * 10000 + self.month * 100 + self.day
+    }
+
+    /// Back from `seed`
+    pub fn from_seed(seed: u64) -> Date {
+        Date {
+            year: seed / 10000,
+            month: seed / 100 % 100,
+            day: seed % 100,
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// How a game ended, other than everyone being out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    // The score attack's time ran out
+    TimeUp,
+    // Every boss went down
+    Cleared,
+}
+
+impl Outcome {
+    /// What's shown over the game once it's ended
+    pub fn label(self) -> &'static str {
+        match self {
+            Outcome::TimeUp => "TIME\nUP",
+            Outcome::Cleared => "ALL\nCLEAR",
+        }
+    }
+}
+
+/// How far through the mode's goal the players are: bosses beaten in a
+/// boss rush, or whether time's up in a score attack.
+#[derive(Clone, Debug)]
+pub struct Progress {
+    mode: Mode,
+    // Bosses shot down so far, and whether there's one on the way now
+    beaten: u32,
+    boss_up: bool,
+    // Frame the next boss shows up on
+    next_boss: u64,
+    outcome: Option<Outcome>,
+}
+
+impl Default for Progress {
+    fn default() -> Progress {
+        Progress::new(Mode::default())
+    }
+}
+
+impl Progress {
+    pub fn new(mode: Mode) -> Progress {
+        Progress {
+            mode,
+            beaten: 0,
+            boss_up: false,
+            next_boss: BOSS_BREAK,
+            outcome: None,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// How the game ended, if it's ended in a way besides everyone being out
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.outcome
+    }
+
+    /// What to show about the goal while playing, e.g. time left
+    pub fn status(&self, frame: u64) -> Option<String> {
+        if let Some(limit) = self.mode.time_limit() {
+            let secs = limit.saturating_sub(frame).div_ceil(DESIRED_FPS as u64);
+            return Some(format!("TIME {}:{:02}", secs / 60, secs % 60));
+        }
+
+        match self.mode.schedule() {
+            Schedule::Bosses => Some(format!("BOSS {}/{}", (self.beaten + 1).min(BOSSES), BOSSES)),
+            _ => None,
+        }
+    }
+
+    /// Move on to `frame` with `bosses` bosses still around.
+    /// Returns which boss should show up this frame, counting from 0.
+    pub fn update(&mut self, frame: u64, bosses: usize) -> Option<u32> {
+        if self.outcome.is_some() {
+            return None;
+        }
+
+        if let Some(limit) = self.mode.time_limit() {
+            if frame >= limit {
+                self.outcome = Some(Outcome::TimeUp);
+            }
+        }
+
+        if self.mode.schedule() != Schedule::Bosses || bosses > 0 {
+            return None;
+        }
+
+        // The last boss just went down
+        if self.boss_up {
+            self.boss_up = false;
+            self.beaten += 1;
+            self.next_boss = frame + BOSS_BREAK;
+            if self.beaten == BOSSES {
+                self.outcome = Some(Outcome::Cleared);
+            }
+            return None;
+        }
+
+        if frame >= self.next_boss {
+            self.boss_up = true;
+            return Some(self.beaten);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: u64, month: u64, day: u64) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn days_count_from_the_epoch() {
+        assert_eq!(Date::from_days(0), date(1970, 1, 1));
+        assert_eq!(Date::from_days(20_744), date(2026, 10, 18));
+
+        // Leap days, including 2000 being a leap year despite the turn of the century
+        assert_eq!(Date::from_days(10_956), date(1999, 12, 31));
+        assert_eq!(Date::from_days(10_957), date(2000, 1, 1));
+        assert_eq!(Date::from_days(11_016), date(2000, 2, 29));
+        assert_eq!(Date::from_days(19_782), date(2024, 2, 29));
+
+        // But 2100 isn't one
+        assert_eq!(Date::from_days(47_540), date(2100, 2, 28));
+        assert_eq!(Date::from_days(47_541), date(2100, 3, 1));
+    }
+
+    #[test]
+    fn seeds_read_as_the_date() {
+        let today = date(2026, 10, 18);
+        assert_eq!(today.seed(), 20_261_018);
+        assert_eq!(Date::from_seed(today.seed()), today);
+    }
+}
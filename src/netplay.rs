@@ -0,0 +1,547 @@
+use crate::audio::Sound;
+use crate::bot::Bot;
+use crate::game::{GameOptions, InputState};
+use crate::replay;
+use crate::sim::{Simulation, Snapshot};
+
+use ggez::{GameError, GameResult};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use specs::shrev::EventChannel;
+
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Packet saying who we are and what seed we'd like to play
+const HELLO: u8 = 0;
+
+/// Packet carrying inputs
+const INPUTS: u8 = 1;
+
+/// Frames our own input is held back for. Giving the other player's
+/// input a little head start means we have to guess less often.
+const INPUT_DELAY: u64 = 2;
+
+/// Furthest we'll run ahead of the last input we heard from the other player
+const MAX_PREDICTION: u64 = 8;
+
+/// Most inputs sent in a single packet
+const MAX_PACKET_INPUTS: u64 = 32;
+
+/// Biggest packet we'll ever get
+const MAX_PACKET_SIZE: usize = 1024;
+
+/// How often to say hello while waiting for the other player
+const HELLO_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for the other player before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Chance of a packet going missing when checking rollback
+const CHECK_LOSS: f64 = 0.1;
+
+/// And of one that's arrived being held back a bit longer
+const CHECK_LAG: f64 = 0.5;
+
+/// Some way of getting packets to the other player and back.
+/// Packets can go missing or turn up out of order.
+pub trait Transport {
+    /// Send a packet to the other player
+    fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+
+    /// The next packet from the other player, without waiting if there isn't one
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// Sends packets over UDP, either straight to the other player or through a relay
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peer: SocketAddr,
+}
+
+impl UdpTransport {
+    /// Listen on `bind` and talk to `peer`
+    pub fn new(bind: SocketAddr, peer: SocketAddr) -> io::Result<UdpTransport> {
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpTransport { socket, peer })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.socket.send_to(packet, self.peer).map(|_| ())
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                // Ignore anyone else who happens to know our port
+                Ok((len, from)) if from == self.peer => return Ok(Some(buf[..len].to_vec())),
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                // Some systems tell us when an earlier packet couldn't be delivered,
+                // which just means the other player isn't there yet
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// One end of a pair of transports that just pass packets
+/// to each other in memory, for running both players in one process
+pub struct Loopback {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+/// Make both ends of an in-memory connection
+pub fn loopback() -> (Loopback, Loopback) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    (Loopback { tx: a_tx, rx: a_rx }, Loopback { tx: b_tx, rx: b_rx })
+}
+
+impl Transport for Loopback {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        // Like UDP, nobody tells us if the other end has gone away
+        let _ = self.tx.send(packet.to_vec());
+        Ok(())
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self.rx.try_recv() {
+            Ok(packet) => Ok(Some(packet)),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => Ok(None),
+        }
+    }
+}
+
+/// Loses and holds back packets, to give rollback a workout
+struct Flaky<T> {
+    inner: T,
+    rng: StdRng,
+    held: VecDeque<Vec<u8>>,
+}
+
+impl<T: Transport> Transport for Flaky<T> {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        if self.rng.gen_bool(CHECK_LOSS) {
+            return Ok(());
+        }
+        self.inner.send(packet)
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        while let Some(packet) = self.inner.recv()? {
+            self.held.push_back(packet);
+        }
+
+        if self.rng.gen_bool(CHECK_LAG) {
+            return Ok(None);
+        }
+        Ok(self.held.pop_front())
+    }
+}
+
+/// Keeps two players' simulations in step over the network with rollback.
+/// We never wait for the other player's input: until it arrives we guess
+/// they kept pressing whatever they last pressed, and if we guessed wrong
+/// we go back to the last snapshot before that and play the frames since
+/// over again with what they actually did.
+pub struct Session<T: Transport> {
+    transport: T,
+    // Which player we are, the other player is the other one of 0 and 1
+    local: usize,
+    // What both players agreed to play with
+    seed: u64,
+    // Our input for every frame, running a few frames ahead
+    local_inputs: Vec<InputState>,
+    // The other player's input for every frame we've heard about
+    remote_inputs: Vec<InputState>,
+    // What we used for the other player's input on every frame we've simulated
+    used: Vec<InputState>,
+    // How many of our inputs the other player has
+    acked: u64,
+    // Earliest frame we guessed the other player's input wrong on
+    rollback: Option<u64>,
+    // The game as it was before each frame we had to guess for
+    snapshots: VecDeque<Snapshot>,
+    // How many frames we've had to play over again
+    resimulated: u64,
+}
+
+impl<T: Transport> Session<T> {
+    /// Wait for the other player to show up. We play with player 0's seed.
+    pub fn connect(mut transport: T, local: usize, seed: u64) -> GameResult<Session<T>> {
+        let start = Instant::now();
+        let mut next_hello = start;
+
+        loop {
+            if Instant::now() >= next_hello {
+                transport.send(&hello(local, seed, false))?;
+                next_hello += HELLO_INTERVAL;
+            }
+
+            while let Some(packet) = transport.recv()? {
+                let (player, remote_seed, _) = match read_hello(&packet) {
+                    Some(hello) => hello,
+                    None => continue,
+                };
+
+                if player == local {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("the other player is also player {}", local + 1),
+                    ).into());
+                }
+
+                // Make sure they know we're here too
+                transport.send(&hello(local, seed, true))?;
+
+                return Ok(Session {
+                    transport,
+                    local,
+                    seed: if local == 0 { seed } else { remote_seed },
+                    local_inputs: vec![InputState::default(); INPUT_DELAY as usize],
+                    remote_inputs: vec![],
+                    used: vec![],
+                    acked: 0,
+                    rollback: None,
+                    snapshots: VecDeque::new(),
+                    resimulated: 0,
+                });
+            }
+
+            if start.elapsed() > CONNECT_TIMEOUT {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the other player never showed up").into());
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// The seed both players are playing with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// How many frames have been played over again after guessing wrong
+    pub fn resimulated(&self) -> u64 {
+        self.resimulated
+    }
+
+    /// Play the next frame with `input` as our input, unless we've got too
+    /// far ahead of the other player or the game is over. Returns whether
+    /// a frame was played.
+    pub fn advance(&mut self, sim: &mut Simulation, input: InputState) -> GameResult<bool> {
+        self.service(sim)?;
+
+        let frame = sim.frame();
+        if sim.game_over() || frame >= self.remote_inputs.len() as u64 + MAX_PREDICTION {
+            return Ok(false);
+        }
+
+        self.local_inputs.push(input);
+        self.tick(sim, frame);
+        self.send()?;
+
+        Ok(true)
+    }
+
+    /// Catch up with the other player without playing a new frame of our
+    /// own, to keep them from waiting on us while we're paused or done
+    pub fn service(&mut self, sim: &mut Simulation) -> GameResult<()> {
+        while let Some(packet) = self.transport.recv()? {
+            self.receive(&packet)?;
+        }
+
+        if let Some(frame) = self.rollback.take() {
+            self.resimulate(sim, frame);
+        }
+
+        // Snapshots before frames we've now heard about are no use any more
+        let confirmed = self.remote_inputs.len() as u64;
+        self.snapshots.retain(|s| s.frame() >= confirmed);
+
+        self.send()
+    }
+
+    /// Whether every frame so far was played with the other player's actual
+    /// input, so what's happened is what really happened for both of us
+    pub fn settled(&self, sim: &Simulation) -> bool {
+        self.remote_inputs.len() as u64 >= sim.frame()
+    }
+
+    /// Play `frame` with the best input we have for the other player
+    fn tick(&mut self, sim: &mut Simulation, frame: u64) {
+        let remote = match self.remote_inputs.get(frame as usize) {
+            Some(&input) => input,
+            None => {
+                // Keep a way back in case we guess wrong
+                self.snapshots.push_back(sim.snapshot());
+                self.remote_inputs.last().copied().unwrap_or_default()
+            }
+        };
+
+        self.used.truncate(frame as usize);
+        self.used.push(remote);
+
+        sim.set_input(self.local, self.local_inputs[frame as usize]);
+        sim.set_input(1 - self.local, remote);
+        sim.tick();
+    }
+
+    /// Go back to before `from` and play every frame since over again
+    fn resimulate(&mut self, sim: &mut Simulation, from: u64) {
+        let to = sim.frame();
+        let snapshot = match self.snapshots.iter().position(|s| s.frame() == from) {
+            Some(i) => self.snapshots.split_off(i).pop_front().expect("snapshot we just found"),
+            None => return,
+        };
+        sim.restore(&snapshot);
+
+        // These frames already made their sounds the first time round
+        let sounds = sim.world.remove::<EventChannel<Sound>>();
+        sim.world.insert(EventChannel::<Sound>::new());
+
+        for frame in from..to {
+            self.tick(sim, frame);
+        }
+        self.resimulated += to - from;
+
+        if let Some(sounds) = sounds {
+            sim.world.insert(sounds);
+        }
+    }
+
+    /// Deal with a packet from the other player
+    fn receive(&mut self, packet: &[u8]) -> GameResult<()> {
+        match packet.first() {
+            Some(&HELLO) => {
+                // They're still waiting to hear from us
+                if let Some((_, _, false)) = read_hello(packet) {
+                    self.transport.send(&hello(self.local, self.seed, true))?;
+                }
+            }
+            Some(&INPUTS) => {
+                // Anything that doesn't make sense is just dropped, like a lost packet
+                let _ = self.read_inputs(&packet[1..]);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn read_inputs(&mut self, mut packet: &[u8]) -> io::Result<()> {
+        let acked = read_u64(&mut packet)?;
+        let first = read_u64(&mut packet)?;
+        let count = read_u64(&mut packet)?;
+
+        // They can't have more of our inputs than we've got, nor send more than fit in a packet
+        let end = match first.checked_add(count) {
+            Some(end) if acked <= self.local_inputs.len() as u64 && count <= MAX_PACKET_INPUTS => end,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad inputs packet")),
+        };
+        self.acked = self.acked.max(acked);
+
+        for frame in first..end {
+            let input = replay::read_input(&mut packet)?;
+
+            // Only take them in order, anything after a gap gets sent again anyway
+            if frame != self.remote_inputs.len() as u64 {
+                continue;
+            }
+
+            if matches!(self.used.get(frame as usize), Some(&used) if used != input) {
+                self.rollback = Some(self.rollback.map_or(frame, |f| f.min(frame)));
+            }
+            self.remote_inputs.push(input);
+        }
+
+        Ok(())
+    }
+
+    /// Send every input the other player hasn't told us they've got,
+    /// along with how many of theirs we have
+    fn send(&mut self) -> GameResult<()> {
+        let first = self.acked;
+        let count = (self.local_inputs.len() as u64 - first).min(MAX_PACKET_INPUTS);
+
+        let mut packet = vec![INPUTS];
+        packet.extend_from_slice(&(self.remote_inputs.len() as u64).to_le_bytes());
+        packet.extend_from_slice(&first.to_le_bytes());
+        packet.extend_from_slice(&count.to_le_bytes());
+        for input in &self.local_inputs[first as usize..(first + count) as usize] {
+            replay::write_input(&mut packet, input)?;
+        }
+
+        self.transport.send(&packet)?;
+        Ok(())
+    }
+}
+
+/// Introduce ourselves, saying whether we've heard from the other player yet
+fn hello(player: usize, seed: u64, heard: bool) -> Vec<u8> {
+    let mut packet = vec![HELLO, player as u8, heard as u8];
+    packet.extend_from_slice(&seed.to_le_bytes());
+    packet
+}
+
+fn read_hello(packet: &[u8]) -> Option<(usize, u64, bool)> {
+    match packet {
+        [HELLO, player, heard, seed @ ..] if seed.len() == 8 => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(seed);
+            Some((usize::from(*player), u64::from_le_bytes(bytes), *heard != 0))
+        }
+        _ => None,
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Have the bot play both players for `frames` frames, each on their own
+/// thread and talking over a connection that loses and holds back packets,
+/// then make sure both ended up with exactly the same game
+pub fn check(options: &GameOptions, frames: u64) -> GameResult<()> {
+    let results = play_both(options.seed, frames)?;
+
+    let (frame, checksum, _) = results[0];
+    for (player, &(their_frame, their_checksum, resimulated)) in results.iter().enumerate() {
+        println!(
+            "player {}: frame {}, checksum {:016x}, {} frames played over",
+            player + 1,
+            their_frame,
+            their_checksum,
+            resimulated
+        );
+        if (their_frame, their_checksum) != (frame, checksum) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the players fell out of sync").into());
+        }
+    }
+
+    println!("both players agree");
+    Ok(())
+}
+
+/// Have the bot play both players on `seed` over a flaky loopback connection.
+/// Returns the frame each ended on, the game's checksum there and how many
+/// frames they each played over.
+fn play_both(seed: u64, frames: u64) -> GameResult<Vec<(u64, u64, u64)>> {
+    let (a, b) = loopback();
+    let done = Arc::new(AtomicUsize::new(0));
+
+    let players = vec![a, b]
+        .into_iter()
+        .enumerate()
+        .map(|(player, transport)| {
+            let done = done.clone();
+
+            thread::spawn(move || -> GameResult<(u64, u64, u64)> {
+                let transport = Flaky {
+                    inner: transport,
+                    rng: StdRng::seed_from_u64(seed.wrapping_add(player as u64)),
+                    held: VecDeque::new(),
+                };
+                let mut session = Session::connect(transport, player, seed)?;
+                let mut sim = Simulation::with_players(session.seed(), 2);
+                let mut bot = Bot::new(player);
+
+                while sim.frame() < frames && !(sim.game_over() && session.settled(&sim)) {
+                    let input = bot.think(&sim.world);
+                    session.advance(&mut sim, input)?;
+                }
+
+                // Keep the other player going until they're done too
+                done.fetch_add(1, Ordering::SeqCst);
+                while !session.settled(&sim) || done.load(Ordering::SeqCst) < 2 {
+                    session.service(&mut sim)?;
+                    thread::yield_now();
+                }
+
+                Ok((sim.frame(), sim.checksum(), session.resimulated()))
+            })
+        }).collect::<Vec<_>>();
+
+    let mut results = vec![];
+    for player in players {
+        results.push(
+            player
+                .join()
+                .map_err(|_| GameError::ConfigError("a player panicked".to_string()))??,
+        );
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands over whatever packets it's given, and keeps what's sent
+    #[derive(Default)]
+    struct Scripted {
+        incoming: VecDeque<Vec<u8>>,
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl Transport for Scripted {
+        fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+            self.sent.push(packet.to_vec());
+            Ok(())
+        }
+
+        fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+            Ok(self.incoming.pop_front())
+        }
+    }
+
+    fn inputs_packet(acked: u64, first: u64, count: u64) -> Vec<u8> {
+        let mut packet = vec![INPUTS];
+        for value in &[acked, first, count] {
+            packet.extend_from_slice(&value.to_le_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn players_agree_over_a_flaky_connection() {
+        let results = play_both(2, 300).unwrap();
+
+        assert_eq!(results[0].0, results[1].0);
+        assert_eq!(results[0].1, results[1].1);
+        assert!(results.iter().all(|&(_, _, resimulated)| resimulated > 0));
+    }
+
+    #[test]
+    fn bad_inputs_packets_are_dropped() {
+        let mut transport = Scripted::default();
+        transport.incoming.push_back(hello(1, 0, true));
+        let mut session = Session::connect(transport, 0, 7).unwrap();
+        let mut sim = Simulation::with_players(session.seed(), 2);
+
+        session.transport.incoming.push_back(inputs_packet(u64::MAX, 0, 0));
+        session.transport.incoming.push_back(inputs_packet(0, u64::MAX, 2));
+        session.transport.incoming.push_back(inputs_packet(0, 0, u64::MAX));
+        session.service(&mut sim).unwrap();
+
+        assert_eq!(session.acked, 0);
+        assert!(session.remote_inputs.is_empty());
+        assert!(session.advance(&mut sim, InputState::default()).unwrap());
+    }
+}
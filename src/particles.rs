@@ -0,0 +1,212 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::f32::consts::PI;
+
+/// How many particles can be alive at once unless told otherwise
+pub const DEFAULT_MAX_PARTICLES: usize = 2000;
+
+/// Colour with alpha so particles can fade out
+pub type Rgba = (u8, u8, u8, u8);
+
+/// Describes how an emitter spawns particles and
+/// how those particles change over their lifetime
+#[derive(Clone, Copy)]
+pub struct EmitterConfig {
+    // Particles spawned all at once when triggered
+    pub burst: u32,
+    // Particles spawned per frame by continuous emitters
+    pub rate: f32,
+    // Range of how many frames a particle lives for
+    pub lifetime: (u64, u64),
+    // Range of initial speeds
+    pub speed: (f32, f32),
+    // Angle (in radians) particles are shot out at
+    // and how far either side of it they may stray
+    pub direction: f32,
+    pub spread: f32,
+    // Added to vertical velocity every frame
+    pub gravity: f32,
+    // Colour and size at the start and end of a particle's life
+    pub colour: (Rgba, Rgba),
+    pub size: (f32, f32),
+}
+
+/// Baddy going up in flames
+pub const EXPLOSION: EmitterConfig = EmitterConfig {
+    burst: 40,
+    rate: 0.,
+    lifetime: (20, 45),
+    speed: (0.5, 3.),
+    direction: 0.,
+    spread: PI,
+    gravity: 0.05,
+    colour: ((0xFF, 0xEE, 0x88, 0xFF), (0xAA, 0x22, 0x00, 0x00)),
+    size: (4., 1.),
+};
+
+/// Player taking damage
+pub const PLAYER_HIT: EmitterConfig = EmitterConfig {
+    burst: 25,
+    rate: 0.,
+    lifetime: (15, 30),
+    speed: (1., 2.5),
+    direction: 0.,
+    spread: PI,
+    gravity: 0.,
+    colour: ((0xFF, 0xFF, 0xFF, 0xFF), (0x44, 0x44, 0xFF, 0x00)),
+    size: (3., 1.),
+};
+
+/// Projectile hitting a baddy
+pub const IMPACT: EmitterConfig = EmitterConfig {
+    burst: 8,
+    rate: 0.,
+    lifetime: (6, 14),
+    speed: (1., 3.),
+    direction: PI / 2.,
+    spread: PI / 3.,
+    gravity: 0.1,
+    colour: ((0xFF, 0xFF, 0xAA, 0xFF), (0xFF, 0x88, 0x00, 0x00)),
+    size: (2., 1.),
+};
+
+/// Exhaust trailing behind the player's ship
+pub const THRUSTER: EmitterConfig = EmitterConfig {
+    burst: 0,
+    rate: 0.75,
+    lifetime: (8, 16),
+    speed: (1.5, 2.5),
+    direction: PI / 2.,
+    spread: PI / 12.,
+    gravity: 0.,
+    colour: ((0x66, 0xCC, 0xFF, 0xCC), (0x22, 0x22, 0x88, 0x00)),
+    size: (3., 1.),
+};
+
+/// A single particle
+#[derive(Clone, Copy)]
+struct Particle {
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    age: u64,
+    lifetime: u64,
+    gravity: f32,
+    colour: (Rgba, Rgba),
+    size: (f32, f32),
+}
+
+impl Particle {
+    /// How far through its life this particle is, from 0 to 1
+    fn life(&self) -> f32 {
+        self.age as f32 / self.lifetime as f32
+    }
+}
+
+/// Fixed size pool of live particles.
+/// We don't make each particle its own entity since there can
+/// be thousands of them and they never interact with anything.
+#[derive(Clone)]
+pub struct ParticlePool {
+    particles: Vec<Particle>,
+    max: usize,
+    // Particles get their own randomness so they
+    // can't change how the rest of the game plays out
+    rng: StdRng,
+}
+
+impl Default for ParticlePool {
+    fn default() -> ParticlePool {
+        ParticlePool::new(DEFAULT_MAX_PARTICLES, 0)
+    }
+}
+
+impl ParticlePool {
+    /// Create a pool that holds at most `max` particles
+    pub fn new(max: usize, seed: u64) -> ParticlePool {
+        ParticlePool {
+            particles: Vec::with_capacity(max),
+            max,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Change how many particles the pool holds, dropping the newest if it's shrinking
+    pub fn set_max(&mut self, max: usize) {
+        self.max = max;
+        self.particles.truncate(max);
+    }
+
+    /// Spawn the emitter's burst of particles at the given point
+    pub fn burst(&mut self, config: &EmitterConfig, x: f32, y: f32) {
+        self.spawn(config, x, y, config.burst);
+    }
+
+    /// Spawn `count` particles at the given point.
+    /// Any that don't fit in the pool are simply dropped.
+    pub fn spawn(&mut self, config: &EmitterConfig, x: f32, y: f32, count: u32) {
+        let rng = &mut self.rng;
+
+        let room = self.max - self.particles.len();
+        for _ in 0..(count as usize).min(room) {
+            let angle = config.direction + rng.gen_range(-1., 1.) * config.spread;
+            let speed = rng.gen_range(config.speed.0, config.speed.1);
+
+            self.particles.push(Particle {
+                x,
+                y,
+                vx: angle.cos() * speed,
+                vy: angle.sin() * speed,
+                age: 0,
+                lifetime: rng.gen_range(config.lifetime.0, config.lifetime.1).max(1),
+                gravity: config.gravity,
+                colour: config.colour,
+                size: config.size,
+            });
+        }
+    }
+
+    /// Move all particles along and get rid of any that are too old
+    pub fn update(&mut self) {
+        for p in &mut self.particles {
+            p.vy += p.gravity;
+            p.x += p.vx;
+            p.y += p.vy;
+            p.age += 1;
+        }
+
+        self.particles.retain(|p| p.age < p.lifetime);
+    }
+
+    /// How many particles are alive
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    /// Whether there are no live particles
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// Iterate over the live particles as their center, size and colour
+    pub fn iter(&self) -> impl Iterator<Item = ([f32; 2], f32, Rgba)> + '_ {
+        self.particles.iter().map(|p| {
+            let t = p.life();
+            let (from, to) = p.colour;
+            let lerp = |a: u8, b: u8| (f32::from(a) + (f32::from(b) - f32::from(a)) * t) as u8;
+
+            (
+                [p.x, p.y],
+                p.size.0 + (p.size.1 - p.size.0) * t,
+                (
+                    lerp(from.0, to.0),
+                    lerp(from.1, to.1),
+                    lerp(from.2, to.2),
+                    lerp(from.3, to.3),
+                ),
+            )
+        })
+    }
+}
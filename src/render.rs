@@ -0,0 +1,537 @@
+use crate::components::{Position, Rendered, ScoreText};
+use crate::game::{self, GameOptions};
+use crate::ghost::{self, Ghost};
+use crate::layout::{self, Layout, WidgetKind};
+use crate::particles::{ParticlePool, Rgba};
+use crate::replay::Replay;
+use crate::sim::Simulation;
+
+use ggez::graphics::Rect;
+use ggez::{GameError, GameResult};
+use gif::SetParameter;
+use specs::{Join, WorldExt};
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Size text is drawn at by default, same as ggez
+const DEFAULT_TEXT_SIZE: f32 = 16.;
+
+/// Glyphs are this many pixels across
+const GLYPH_WIDTH: usize = 5;
+
+/// And this many pixels tall
+const GLYPH_HEIGHT: usize = 7;
+
+/// Trade off between how fast GIF frames are encoded and how good their colours are,
+/// from 1 (slowest, best) to 30
+const GIF_SPEED: i32 = 10;
+
+/// How far apart two pixels' channels can be and still count as the same
+const CHANNEL_TOLERANCE: u8 = 8;
+
+/// Rows of a 5x7 glyph, leftmost pixel in the highest bit.
+/// Lowercase is drawn as uppercase and anything else we don't know is blank.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '>' => [0x10, 0x08, 0x04, 0x02, 0x04, 0x08, 0x10],
+        '%' => [0x19, 0x19, 0x02, 0x04, 0x08, 0x13, 0x13],
+        '/' => [0x01, 0x01, 0x02, 0x04, 0x08, 0x10, 0x10],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// An RGBA image we can draw into without a GPU
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    // Where the top left pixel is in game coordinates
+    origin: (f32, f32),
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// A black image covering `area` of the game, one pixel per unit
+    pub fn new(area: Rect) -> Image {
+        let (width, height) = (area.w as u32, area.h as u32);
+
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        for alpha in pixels.iter_mut().skip(3).step_by(4) {
+            *alpha = 0xFF;
+        }
+
+        Image {
+            width,
+            height,
+            origin: (area.x, area.y),
+            pixels,
+        }
+    }
+
+    /// Fill in a rectangle given in game coordinates, blending by its alpha
+    pub fn fill_rect(&mut self, area: Rect, (r, g, b, a): Rgba) {
+        let x0 = (area.x - self.origin.0).round().max(0.) as u32;
+        let y0 = (area.y - self.origin.1).round().max(0.) as u32;
+        let x1 = ((area.x + area.w - self.origin.0).round().max(0.) as u32).min(self.width);
+        let y1 = ((area.y + area.h - self.origin.1).round().max(0.) as u32).min(self.height);
+
+        let blend = |dst: u8, src: u8| {
+            let (dst, src, a) = (u32::from(dst), u32::from(src), u32::from(a));
+            ((src * a + dst * (0xFF - a)) / 0xFF) as u8
+        };
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let i = ((y * self.width + x) * 4) as usize;
+                self.pixels[i] = blend(self.pixels[i], r);
+                self.pixels[i + 1] = blend(self.pixels[i + 1], g);
+                self.pixels[i + 2] = blend(self.pixels[i + 2], b);
+            }
+        }
+    }
+
+    /// Draw text at the given size with its top left at `pos`.
+    /// If given a width, each line is centered within it.
+    pub fn draw_text(&mut self, text: &str, pos: [f32; 2], size: f32, width: Option<f32>, colour: Rgba) {
+        let px = (size / (GLYPH_HEIGHT + 1) as f32).round().max(1.);
+        let advance = (GLYPH_WIDTH + 1) as f32 * px;
+
+        for (row, line) in text.lines().enumerate() {
+            let line_width = line.chars().count() as f32 * advance - px;
+            let mut x = match width {
+                Some(w) => pos[0] + (w - line_width) / 2.,
+                None => pos[0],
+            };
+            let y = pos[1] + row as f32 * (GLYPH_HEIGHT + 1) as f32 * px;
+
+            for c in line.chars() {
+                for (gy, bits) in glyph(c).iter().enumerate() {
+                    for gx in 0..GLYPH_WIDTH {
+                        if bits & (0x10 >> gx) != 0 {
+                            let area = Rect::new(x + gx as f32 * px, y + gy as f32 * px, px, px);
+                            self.fill_rect(area, colour);
+                        }
+                    }
+                }
+                x += advance;
+            }
+        }
+    }
+
+    /// Resize the image by `scale`, keeping the pixels sharp
+    pub fn scaled(&self, scale: f32) -> Image {
+        let width = ((self.width as f32 * scale) as u32).max(1);
+        let height = ((self.height as f32 * scale) as u32).max(1);
+
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            let sy = (y * self.height / height) as usize;
+            for x in 0..width {
+                let sx = (x * self.width / width) as usize;
+                let i = (sy * self.width as usize + sx) * 4;
+                pixels.extend_from_slice(&self.pixels[i..i + 4]);
+            }
+        }
+
+        Image {
+            width,
+            height,
+            origin: self.origin,
+            pixels,
+        }
+    }
+
+    /// Save the image as a PNG file
+    pub fn save_png(&self, path: &Path) -> GameResult<()> {
+        let out = BufWriter::new(File::create(path)?);
+
+        let mut encoder = png::Encoder::new(out, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(|e| GameError::RenderError(format!("couldn't write PNG: {}", e)))
+    }
+
+    /// Load an 8-bit RGBA PNG file, like the ones we save
+    pub fn load_png(path: &Path) -> GameResult<Image> {
+        let bad_png = |e: png::DecodingError| {
+            GameError::ResourceLoadError(format!("couldn't read {}: {}", path.display(), e))
+        };
+
+        let decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        let (info, mut reader) = decoder.read_info().map_err(bad_png)?;
+        if info.color_type != png::ColorType::RGBA || info.bit_depth != png::BitDepth::Eight {
+            return Err(GameError::ResourceLoadError(format!(
+                "{} isn't an 8-bit RGBA PNG",
+                path.display()
+            )));
+        }
+
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).map_err(bad_png)?;
+
+        Ok(Image {
+            width: info.width,
+            height: info.height,
+            origin: (0., 0.),
+            pixels,
+        })
+    }
+
+    /// Fraction of pixels that noticeably differ from the other image's.
+    /// Images of different sizes are completely different.
+    pub fn difference(&self, other: &Image) -> f32 {
+        if (self.width, self.height) != (other.width, other.height) {
+            return 1.;
+        }
+
+        let differing = self
+            .pixels
+            .chunks(4)
+            .zip(other.pixels.chunks(4))
+            .filter(|(a, b)| {
+                a.iter()
+                    .zip(b.iter())
+                    .any(|(&a, &b)| (i16::from(a) - i16::from(b)).abs() > i16::from(CHANNEL_TOLERANCE))
+            }).count();
+
+        differing as f32 / (self.width * self.height) as f32
+    }
+}
+
+/// Draw the same scene the game would, entities, particles and HUD,
+/// along with the ghost if the game's racing one
+pub fn render(sim: &Simulation, layout: &Layout, ghost: Option<&Ghost>) -> Image {
+    let mut image = Image::new(layout.bounds);
+    let world = &sim.world;
+    let mods = sim.modifiers();
+
+    // The ghost's ship, under everything that's really there
+    if let Some((rendered, _, _)) = ghost.and_then(Ghost::ship) {
+        let (r, g, b) = rendered.colour;
+        let mut area = rendered.area;
+        area.x = mods.screen_x(area.x, area.w);
+        image.fill_rect(area, (r, g, b, ghost::GHOST_ALPHA));
+    }
+
+    // Entities
+    for rendered in (&world.read_storage::<Rendered>()).join() {
+        let (r, g, b) = rendered.colour;
+        let mut area = rendered.area;
+        area.x = mods.screen_x(area.x, area.w);
+        image.fill_rect(area, (r, g, b, 0xFF));
+    }
+
+    // Popup text
+    let score_text = world.read_storage::<ScoreText>();
+    let position = world.read_storage::<Position>();
+    for (score_text, pos) in (&score_text, &position).join() {
+        let text = score_text.label();
+        image.draw_text(&text, [mods.screen_x(pos.x, 0.), pos.y], DEFAULT_TEXT_SIZE, None, (0x99, 0x99, 0x99, 0xFF));
+    }
+
+    // Particles
+    for ([x, y], size, colour) in world.read_resource::<ParticlePool>().iter() {
+        image.fill_rect(Rect::new(mods.screen_x(x - size / 2., size), y - size / 2., size, size), colour);
+    }
+
+    // HUD, or just the panels around the game without it
+    for widget in layout.widgets.iter().filter(|w| mods.hud || matches!(w.kind, WidgetKind::Panel)) {
+        let area = widget.area;
+        let colour = widget.colour.to_rgba();
+
+        match &widget.kind {
+            WidgetKind::Panel => image.fill_rect(area, colour),
+
+            WidgetKind::Label(text) | WidgetKind::PlayerLabel { text, .. } => {
+                image.draw_text(&text.contents(), [area.x, area.y], DEFAULT_TEXT_SIZE, Some(area.w), colour);
+            }
+
+            WidgetKind::Score { player } => {
+                let score = format!("{:06}", sim.score(*player));
+                image.draw_text(&score, [area.x, area.y], DEFAULT_TEXT_SIZE, Some(area.w), colour);
+            }
+
+            WidgetKind::Multiplier { player } => {
+                let multiplier = format!("x{}", sim.multiplier(*player));
+                image.draw_text(&multiplier, [area.x, area.y], DEFAULT_TEXT_SIZE, Some(area.w), colour);
+            }
+
+            // There's no high score table without a window, so it's the best score on screen
+            WidgetKind::HighScore => {
+                let best = (0..sim.players()).map(|id| sim.score(id)).max().unwrap_or(0);
+                image.draw_text(&format!("{:06}", best), [area.x, area.y], DEFAULT_TEXT_SIZE, Some(area.w), colour);
+            }
+
+            WidgetKind::Stage => {
+                let stages = sim.stages();
+                if stages.enabled() {
+                    for (badge, colour) in layout::stage_badges(area, stages.number()) {
+                        image.fill_rect(badge, colour.to_rgba());
+                    }
+                }
+            }
+
+            WidgetKind::HealthBar { player, vertical } => {
+                image.fill_rect(area, (0x00, 0x00, 0x00, 0xFF));
+                image.fill_rect(layout::health_bar_fill(area, *vertical, sim.health(*player) / mods.player_health), colour);
+            }
+
+            WidgetKind::GhostDifference(label) => {
+                if let Some(ghost) = ghost {
+                    let (difference, (r, g, b)) = ghost::score_difference(sim.score(0), ghost.score());
+                    image.draw_text(&label.contents(), [area.x, area.y], DEFAULT_TEXT_SIZE, Some(area.w), colour);
+                    image.draw_text(
+                        &difference,
+                        [area.x, area.y + area.h / 2.],
+                        DEFAULT_TEXT_SIZE,
+                        Some(area.w),
+                        (r, g, b, 0xFF),
+                    );
+                }
+            }
+        }
+    }
+
+    if sim.game_over() {
+        image.draw_text(sim.ending(), [80., 220.], 80., Some(240.), (0xFF, 0x00, 0x00, 0xFF));
+    } else {
+        // How the mode's goal is going, up top
+        if let Some(status) = sim.progress().status(sim.frame()) {
+            image.draw_text(&status, [0., 10.], DEFAULT_TEXT_SIZE, Some(game::GAME_WIDTH), (0xFF, 0xFF, 0xFF, 0xFF));
+        }
+
+
+        // The stage's name as it starts, or how the last one went
+        let stages = sim.stages();
+        if let Some(banner) = stages.banner() {
+            image.draw_text(&banner, [0., 240.], 24., Some(game::GAME_WIDTH), (0x33, 0xDD, 0xDD, 0xFF));
+        }
+        if let Some(results) = stages.results() {
+            let results = results.lines().join("\n");
+            image.draw_text(&results, [0., 200.], 24., Some(game::GAME_WIDTH), (0xFF, 0xFF, 0xFF, 0xFF));
+        }
+    }
+
+    image
+}
+
+/// Run the game without a window for a number of frames and then either
+/// save a screenshot or check it against a golden image.
+/// The frames are played from a replay if given one.
+pub fn screenshot(options: &GameOptions) -> GameResult<()> {
+    let sim = match &options.replay {
+        Some(path) => {
+            let replay = Replay::load(path)?;
+            let mut sim = replay.simulation();
+            sim.set_max_particles(options.max_particles);
+            while sim.frame() < options.frame && replay.step(&mut sim) {}
+            sim
+        }
+
+        None => {
+            let mut sim = Simulation::with_rules(options.seed, options.players, &options.rules);
+            sim.set_max_particles(options.max_particles);
+            for _ in 0..options.frame {
+                sim.tick();
+            }
+            sim
+        }
+    };
+
+    // Any ghost gets played up to the same frame
+    let mut ghost = options.ghost.clone().map(Ghost::new);
+    if let Some(ghost) = &mut ghost {
+        ghost.catch_up(sim.frame());
+    }
+
+    let layout = race_layout(options, sim.players(), ghost.is_some());
+    let image = render(&sim, &layout, ghost.as_ref());
+
+    if let Some(path) = &options.screenshot {
+        image.save_png(path)?;
+    }
+
+    if let Some(path) = &options.golden {
+        let golden = Image::load_png(path)?;
+        let difference = image.difference(&golden);
+
+        if difference > options.tolerance {
+            // Keep what we got around so it's easy to see what changed
+            let actual = path.with_extension("actual.png");
+            image.save_png(&actual)?;
+
+            return Err(GameError::RenderError(format!(
+                "frame {} differs from {} in {:.2}% of pixels, see {}",
+                options.frame,
+                path.display(),
+                difference * 100.,
+                actual.display()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Where exported frames end up
+enum FrameSink {
+    // All frames in a single animated GIF
+    Gif(gif::Encoder<BufWriter<File>>),
+    // Each frame as `frame_<number>.png` in a directory
+    Png(PathBuf),
+}
+
+impl FrameSink {
+    /// GIF if the path ends in `.gif`, otherwise a directory of PNGs
+    fn new(path: &Path, width: u32, height: u32) -> GameResult<FrameSink> {
+        if path.extension().and_then(|ext| ext.to_str()) == Some("gif") {
+            let out = BufWriter::new(File::create(path)?);
+            let mut encoder = gif::Encoder::new(out, width as u16, height as u16, &[])?;
+            encoder.set(gif::Repeat::Infinite)?;
+            Ok(FrameSink::Gif(encoder))
+        } else {
+            std::fs::create_dir_all(path)?;
+            Ok(FrameSink::Png(path.to_path_buf()))
+        }
+    }
+
+    /// Add a frame that's shown for `delay` hundredths of a second
+    fn write(&mut self, mut image: Image, frame: u64, delay: u16) -> GameResult<()> {
+        match self {
+            FrameSink::Gif(encoder) => {
+                let (w, h) = (image.width as u16, image.height as u16);
+                let mut gif_frame = gif::Frame::from_rgba_speed(w, h, &mut image.pixels, GIF_SPEED);
+                gif_frame.delay = delay;
+                encoder.write_frame(&gif_frame)?;
+                Ok(())
+            }
+
+            FrameSink::Png(dir) => image.save_png(&dir.join(format!("frame_{:06}.png", frame))),
+        }
+    }
+}
+
+/// The layout for `players` players, with room to show
+/// how they're doing against the ghost if they're racing one
+fn race_layout(options: &GameOptions, players: usize, ghost: bool) -> Layout {
+    let kind = options.layout.unwrap_or_default();
+    let layout = Layout::new(kind, players);
+    if ghost {
+        layout.with_ghost(kind)
+    } else {
+        layout
+    }
+}
+
+/// How long `frames` frames of the game last, in hundredths of a second
+fn centiseconds(frames: u64) -> u64 {
+    frames * 100 / u64::from(game::DESIRED_FPS)
+}
+
+/// Play a replay back without a window, drawing its frames to
+/// an animated GIF or a directory of PNGs.
+/// Note most GIF viewers slow down anything faster than 50 FPS,
+/// so skipping at least every other frame is best for GIFs.
+pub fn export(options: &GameOptions, replay: &Path, out: &Path) -> GameResult<()> {
+    let replay = Replay::load(replay)?;
+    let mut sim = replay.simulation();
+    sim.set_max_particles(options.max_particles);
+    let mut ghost = options.ghost.clone().map(Ghost::new);
+    let layout = race_layout(options, sim.players(), ghost.is_some());
+
+    let every = options.frame_skip + 1;
+    let last = replay.inputs.len() as u64;
+
+    let mut sink = None;
+    loop {
+        let frame = sim.frame();
+
+        // Always keep the very last frame so clips end where the replay did
+        if frame % every == 0 || frame == last {
+            if let Some(ghost) = &mut ghost {
+                ghost.catch_up(frame);
+            }
+
+            let image = render(&sim, &layout, ghost.as_ref()).scaled(options.scale);
+            let sink = match &mut sink {
+                Some(sink) => sink,
+                None => sink.get_or_insert(FrameSink::new(out, image.width, image.height)?),
+            };
+
+            let delay = centiseconds(frame + every) - centiseconds(frame);
+            sink.write(image, frame, delay as u16)?;
+        }
+
+        if !replay.step(&mut sim) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// If a change is meant to alter how the demo looks, check the new frame by eye and save it over
+    /// the golden with `--replay resources/demo.rpl --frame 1200 --screenshot resources/golden/demo_1200.png`
+    #[test]
+    fn demo_matches_golden_frame() {
+        let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources");
+        let options = GameOptions {
+            replay: Some(resources.join("demo.rpl")),
+            golden: Some(resources.join("golden").join("demo_1200.png")),
+            frame: 1200,
+            ..GameOptions::default()
+        };
+
+        if let Err(e) = screenshot(&options) {
+            panic!("{}", e);
+        }
+    }
+}
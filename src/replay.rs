@@ -0,0 +1,217 @@
+use crate::director::DirectorConfig;
+use crate::game::InputState;
+use crate::rules::Rules;
+use crate::sim::Simulation;
+
+use ggez::{GameError, GameResult};
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Every replay file starts with this
+const MAGIC: &[u8; 4] = b"GRPL";
+
+/// Bumped whenever the file layout or the simulation changes
+/// in a way that makes older replays play out differently
+const VERSION: u32 = 7;
+
+/// Replays from before the director, which play back without it
+const VERSION_BEFORE_DIRECTOR: u32 = 1;
+
+/// Replays from before presets and mutators, which only kept the director's tuning
+const VERSION_BEFORE_RULES: u32 = 2;
+
+/// Replays from before stages, which play straight through without them
+const VERSION_BEFORE_STAGES: u32 = 3;
+
+/// Replays from before combos, which score kills at face value
+const VERSION_BEFORE_COMBOS: u32 = 4;
+
+/// Replays from before noobs dived, which fly off the side instead
+const VERSION_BEFORE_DIVES: u32 = 6;
+
+/// Bits for each button in a recorded frame
+const UP: u8 = 1;
+const DOWN: u8 = 1 << 1;
+const LEFT: u8 = 1 << 2;
+const RIGHT: u8 = 1 << 3;
+const SHOOT: u8 = 1 << 4;
+
+/// Everything needed to play a game back exactly: the seed it
+/// started with, the rules it was played under and the input on every frame
+#[derive(Clone, Debug, Default)]
+pub struct Replay {
+    pub seed: u64,
+    pub rules: Rules,
+    pub inputs: Vec<InputState>,
+}
+
+impl Replay {
+    /// Start recording a game with the given seed and rules
+    pub fn new(seed: u64, rules: Rules) -> Replay {
+        Replay {
+            seed,
+            rules,
+            inputs: vec![],
+        }
+    }
+
+    /// Remember the input used for the next frame
+    pub fn record(&mut self, input: &InputState) {
+        self.inputs.push(*input);
+    }
+
+    /// A fresh simulation to play the replay back with
+    pub fn simulation<'a, 'b>(&self) -> Simulation<'a, 'b> {
+        Simulation::with_rules(self.seed, 1, &self.rules)
+    }
+
+    /// Run the next frame of the replay, returns false once it's over
+    pub fn step(&self, sim: &mut Simulation) -> bool {
+        let frame = sim.frame() as usize;
+        let input = match self.inputs.get(frame) {
+            Some(input) => *input,
+            None => return false,
+        };
+
+        sim.set_input(0, input);
+        sim.tick();
+
+        true
+    }
+
+    /// Write the replay to a file
+    pub fn save(&self, path: &Path) -> GameResult<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()?;
+
+        Ok(())
+    }
+
+    /// Read a replay back from a file
+    pub fn load(path: &Path) -> GameResult<Replay> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Replay::read(&mut reader, &path.display().to_string())
+    }
+
+    /// Read a replay that's already in memory, like one built into the game
+    pub fn from_bytes(mut bytes: &[u8], name: &str) -> GameResult<Replay> {
+        Replay::read(&mut bytes, name)
+    }
+
+    fn read<R: Read>(reader: &mut R, name: &str) -> GameResult<Replay> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(GameError::ResourceLoadError(format!("{} isn't a replay", name)));
+        }
+
+        let version = read_u32(reader)?;
+        if version == 0 || version > VERSION {
+            return Err(GameError::ResourceLoadError(format!(
+                "{} is a version {} replay, we can only play up to version {}",
+                name, version, VERSION
+            )));
+        }
+
+        let mut seed = [0; 8];
+        reader.read_exact(&mut seed)?;
+
+        let mut rules = match version {
+            VERSION_BEFORE_DIRECTOR => Rules::with_director(DirectorConfig::disabled()),
+            VERSION_BEFORE_RULES => Rules::with_director(DirectorConfig::from_toml(&read_string(reader, name)?)?),
+            _ => Rules::from_toml(&read_string(reader, name)?)?,
+        };
+        if version <= VERSION_BEFORE_STAGES {
+            rules.stages = false;
+        }
+        if version <= VERSION_BEFORE_COMBOS {
+            rules.combos = false;
+        }
+        if version <= VERSION_BEFORE_DIVES {
+            rules.dives = false;
+        }
+
+        let count = read_u32(reader)?;
+
+        let mut replay = Replay::new(u64::from_le_bytes(seed), rules);
+        for _ in 0..count {
+            replay.inputs.push(read_input(reader)?);
+        }
+
+        Ok(replay)
+    }
+
+    /// Header followed by one input per frame
+    fn write<W: Write>(&self, out: &mut W) -> GameResult<()> {
+        let rules = self.rules.to_toml()?;
+
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&self.seed.to_le_bytes())?;
+        out.write_all(&(rules.len() as u32).to_le_bytes())?;
+        out.write_all(rules.as_bytes())?;
+        out.write_all(&(self.inputs.len() as u32).to_le_bytes())?;
+
+        for input in &self.inputs {
+            write_input(out, input)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write out one frame's input: a byte of buttons
+/// and then both stick axes as full floats
+pub fn write_input<W: Write>(out: &mut W, input: &InputState) -> io::Result<()> {
+    let buttons = [
+        (input.up, UP),
+        (input.down, DOWN),
+        (input.left, LEFT),
+        (input.right, RIGHT),
+        (input.shoot, SHOOT),
+    ].iter()
+        .filter(|&&(pressed, _)| pressed)
+        .fold(0, |buttons, &(_, bit)| buttons | bit);
+
+    out.write_all(&[buttons])?;
+    out.write_all(&input.stick.0.to_le_bytes())?;
+    out.write_all(&input.stick.1.to_le_bytes())
+}
+
+/// Read back one frame's input written by `write_input`
+pub fn read_input<R: Read>(reader: &mut R) -> io::Result<InputState> {
+    let mut buttons = [0];
+    reader.read_exact(&mut buttons)?;
+    let buttons = buttons[0];
+
+    Ok(InputState {
+        up: buttons & UP != 0,
+        down: buttons & DOWN != 0,
+        left: buttons & LEFT != 0,
+        right: buttons & RIGHT != 0,
+        shoot: buttons & SHOOT != 0,
+        stick: (read_f32(reader)?, read_f32(reader)?),
+    })
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Read a length followed by that much UTF-8, like the rules in the header
+fn read_string<R: Read>(reader: &mut R, name: &str) -> GameResult<String> {
+    let mut bytes = vec![0; read_u32(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| GameError::ResourceLoadError(format!("{} has garbled rules", name)))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
@@ -0,0 +1,148 @@
+use crate::game::{InputState, DESIRED_FPS};
+use crate::sim::{Simulation, Snapshot};
+
+use std::collections::VecDeque;
+
+/// Ticks between snapshots. Rewinding restores the one before where it's
+/// going and plays forward from there, so this trades memory for how much
+/// gets played again.
+const SNAPSHOT_INTERVAL: u64 = 30;
+
+/// About how many frames back the game can be rewound
+pub const REWIND_LIMIT: u64 = 30 * DESIRED_FPS as u64;
+
+/// Snapshots kept to go back that far
+const MAX_SNAPSHOTS: usize = (REWIND_LIMIT / SNAPSHOT_INTERVAL) as usize;
+
+/// The last stretch of the game, for going back over it while tracking down
+/// bugs. Snapshots are taken every so often along with every player's input
+/// on every frame since the oldest, so the game can be put back exactly how
+/// it was on any of those frames.
+pub struct History {
+    // Oldest first
+    snapshots: VecDeque<Snapshot>,
+    // Everyone's input for each frame from the oldest snapshot on
+    inputs: VecDeque<Vec<InputState>>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            snapshots: VecDeque::new(),
+            inputs: VecDeque::new(),
+        }
+    }
+
+    /// The earliest frame the game can go back to
+    pub fn earliest(&self) -> u64 {
+        self.snapshots.front().map_or(0, Snapshot::frame)
+    }
+
+    /// Remember what everyone's pressing for the tick `sim` is about to
+    /// run, taking a snapshot first if one's due
+    pub fn record(&mut self, sim: &mut Simulation, inputs: &[InputState]) {
+        let due = self.snapshots.back().is_none_or(|s| sim.frame() >= s.frame() + SNAPSHOT_INTERVAL);
+        if due {
+            self.snapshots.push_back(sim.snapshot());
+
+            // Forget the oldest snapshot and the input that went with it
+            if self.snapshots.len() > MAX_SNAPSHOTS {
+                let oldest = self.snapshots.pop_front().map_or(0, |s| s.frame());
+                let forgotten = (self.earliest() - oldest) as usize;
+                self.inputs.drain(..forgotten);
+            }
+        }
+
+        self.inputs.push_back(inputs.to_vec());
+    }
+
+    /// Take `sim` back `frames` frames, or as far as the history goes.
+    /// Whatever happened after that is forgotten, as the game carries
+    /// on from there with whatever's pressed next.
+    pub fn rewind(&mut self, sim: &mut Simulation, frames: u64) {
+        let target = sim.frame().saturating_sub(frames).max(self.earliest());
+
+        // Go back to the last snapshot at or before there, dropping any after it
+        while self.snapshots.back().is_some_and(|s| s.frame() > target) {
+            self.snapshots.pop_back();
+        }
+        let snapshot = match self.snapshots.back() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        sim.restore(snapshot);
+
+        // Then play the rest of the way there as it went before
+        let earliest = self.earliest();
+        while sim.frame() < target {
+            let inputs = &self.inputs[(sim.frame() - earliest) as usize];
+            for (id, &input) in inputs.iter().enumerate() {
+                sim.set_input(id, input);
+            }
+            sim.tick();
+        }
+
+        self.inputs.truncate((target - earliest) as usize);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Bot;
+
+    /// Let the bot play `frames` frames, keeping track of them in `history` and `inputs`
+    fn play(sim: &mut Simulation, history: &mut History, inputs: &mut Vec<InputState>, bot: &mut Bot, frames: u64) {
+        for _ in 0..frames {
+            let input = bot.think(&sim.world);
+            history.record(sim, &[input]);
+            sim.set_input(0, input);
+            sim.tick();
+            inputs.push(input);
+        }
+    }
+
+    /// How the game looks after playing `inputs` from the start
+    fn replayed(inputs: &[InputState]) -> u64 {
+        let mut sim = Simulation::new(5);
+        for &input in inputs {
+            sim.set_input(0, input);
+            sim.tick();
+        }
+        sim.checksum()
+    }
+
+    #[test]
+    fn rewinding_matches_playing_straight_through() {
+        let mut sim = Simulation::new(5);
+        let mut history = History::new();
+        let mut inputs = vec![];
+        let mut bot = Bot::new(0);
+
+        // Play long enough that the oldest snapshots have been forgotten
+        play(&mut sim, &mut history, &mut inputs, &mut bot, REWIND_LIMIT + 500);
+        assert!(history.earliest() > 0);
+
+        // Back to between two snapshots
+        history.rewind(&mut sim, 217);
+        inputs.truncate(sim.frame() as usize);
+        assert_eq!(sim.frame(), REWIND_LIMIT + 500 - 217);
+        assert_eq!(sim.checksum(), replayed(&inputs));
+
+        // Then carry on from there and go back over where it picked up again
+        play(&mut sim, &mut history, &mut inputs, &mut bot, 100);
+        history.rewind(&mut sim, 150);
+        inputs.truncate(sim.frame() as usize);
+        assert_eq!(sim.checksum(), replayed(&inputs));
+
+        // And as far back as it goes
+        history.rewind(&mut sim, u64::MAX);
+        inputs.truncate(sim.frame() as usize);
+        assert_eq!(sim.frame(), history.earliest());
+        assert_eq!(sim.checksum(), replayed(&inputs));
+
+        // Which still plays on the same as the game would have
+        play(&mut sim, &mut history, &mut inputs, &mut bot, 300);
+        assert_eq!(sim.checksum(), replayed(&inputs));
+    }
+}
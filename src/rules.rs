@@ -0,0 +1,345 @@
+use crate::director::DirectorConfig;
+use crate::game::{GAME_WIDTH, MAX_PLAYER_HEALTH};
+use crate::modes::{Date, Mode};
+use crate::scoring::CHAIN_WINDOW;
+
+use ggez::{GameError, GameResult};
+use serde::{Deserialize, Serialize};
+
+use std::str::FromStr;
+
+/// How hard the game is to start with, before the director has its say
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    Easy,
+    Normal,
+    Hard,
+    // Few hits to spare and no director going easy on anyone
+    Arcade,
+}
+
+impl Preset {
+    /// Name shown in menus and high scores
+    pub fn label(self) -> &'static str {
+        match self {
+            Preset::Easy => "EASY",
+            Preset::Normal => "NORMAL",
+            Preset::Hard => "HARD",
+            Preset::Arcade => "ARCADE",
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = GameError;
+
+    fn from_str(s: &str) -> GameResult<Preset> {
+        match s {
+            "easy" => Ok(Preset::Easy),
+            "normal" => Ok(Preset::Normal),
+            "hard" => Ok(Preset::Hard),
+            "arcade" => Ok(Preset::Arcade),
+            _ => Err(GameError::ConfigError(format!("unknown difficulty: {}", s))),
+        }
+    }
+}
+
+/// Twists on the rules that can be stacked on top of any preset
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mutator {
+    // Baddies fly twice as fast
+    DoubleSpeed,
+    // Players go down in a single hit
+    OneHitKill,
+    // Nothing drawn over the game but the game itself
+    NoHud,
+    // Everything flipped left to right
+    Mirror,
+    // Baddies are easier to hit than they look
+    BigHead,
+    // Every shot baddies fire comes straight at a player
+    AimedBullets,
+}
+
+impl Mutator {
+    pub const ALL: [Mutator; 6] = [
+        Mutator::DoubleSpeed,
+        Mutator::OneHitKill,
+        Mutator::NoHud,
+        Mutator::Mirror,
+        Mutator::BigHead,
+        Mutator::AimedBullets,
+    ];
+
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Mutator::DoubleSpeed => "double-speed",
+            Mutator::OneHitKill => "one-hit-kill",
+            Mutator::NoHud => "no-hud",
+            Mutator::Mirror => "mirror",
+            Mutator::BigHead => "big-head",
+            Mutator::AimedBullets => "aimed-bullets",
+        }
+    }
+
+    /// Name shown in menus and high scores
+    pub fn label(self) -> &'static str {
+        match self {
+            Mutator::DoubleSpeed => "DOUBLE SPEED",
+            Mutator::OneHitKill => "ONE HIT KILL",
+            Mutator::NoHud => "NO HUD",
+            Mutator::Mirror => "MIRROR",
+            Mutator::BigHead => "BIG HEAD",
+            Mutator::AimedBullets => "AIMED BULLETS",
+        }
+    }
+}
+
+impl FromStr for Mutator {
+    type Err = GameError;
+
+    fn from_str(s: &str) -> GameResult<Mutator> {
+        Mutator::ALL
+            .iter()
+            .cloned()
+            .find(|m| m.name() == s)
+            .ok_or_else(|| GameError::ConfigError(format!("unknown mutator: {}", s)))
+    }
+}
+
+/// Everything that decides how a game plays besides the seed and input.
+/// It's saved with replays so they play back under the same rules.
+/// Note plain values have to come before tables for TOML, so `director` goes
+/// last. The same goes for every struct saved as TOML.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Rules {
+    pub preset: Preset,
+    pub mode: Mode,
+    // Kept sorted with no repeats, so the same set always compares equal
+    pub mutators: Vec<Mutator>,
+    // Whether the game's split into stages, with challenging stages between
+    pub stages: bool,
+    // Whether kills build up chains and multipliers
+    pub combos: bool,
+    // Whether noobs dive at the players once they're done weaving about
+    pub dives: bool,
+    pub director: DirectorConfig,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            preset: Preset::Normal,
+            mode: Mode::Classic,
+            mutators: vec![],
+            stages: true,
+            combos: true,
+            dives: true,
+            director: DirectorConfig::default(),
+        }
+    }
+}
+
+impl Rules {
+    /// Rules with just the director tuned, as replays had before presets
+    pub fn with_director(director: DirectorConfig) -> Rules {
+        Rules {
+            director,
+            ..Rules::default()
+        }
+    }
+
+    /// The daily challenge for `date`: normal difficulty
+    /// with one mutator, which changes from day to day
+    pub fn daily(date: Date) -> Rules {
+        // Hiding the HUD wouldn't make much of a challenge
+        const DAILY_MUTATORS: [Mutator; 5] = [
+            Mutator::DoubleSpeed,
+            Mutator::OneHitKill,
+            Mutator::Mirror,
+            Mutator::BigHead,
+            Mutator::AimedBullets,
+        ];
+
+        let mut rules = Rules {
+            mode: Mode::Daily,
+            ..Rules::default()
+        };
+        rules.add_mutator(DAILY_MUTATORS[(date.seed() % DAILY_MUTATORS.len() as u64) as usize]);
+        rules
+    }
+
+    /// Add a mutator, if it's not on already
+    pub fn add_mutator(&mut self, mutator: Mutator) {
+        if let Err(i) = self.mutators.binary_search(&mutator) {
+            self.mutators.insert(i, mutator);
+        }
+    }
+
+    /// How the director is tuned, which arcade games go without
+    pub fn director(&self) -> DirectorConfig {
+        match self.preset {
+            Preset::Arcade => DirectorConfig::disabled(),
+            _ => self.director.clone(),
+        }
+    }
+
+    /// Whether everything besides the preset and mutators is as it comes, so the
+    /// description tells the whole story and a high score can be compared fairly
+    pub fn is_standard(&self) -> bool {
+        let director = self.preset == Preset::Arcade || self.director == DirectorConfig::default();
+        director && self.stages && self.combos && self.dives
+    }
+
+    /// Short description like `HARD + MIRROR + BIG HEAD`, so runs
+    /// under different rules can be told apart
+    pub fn describe(&self) -> String {
+        let mut parts = vec![self.preset.label()];
+        parts.extend(self.mutators.iter().map(|m| m.label()));
+        parts.join(" + ")
+    }
+
+    /// Read the rules back from `to_toml`
+    pub fn from_toml(contents: &str) -> GameResult<Rules> {
+        toml::from_str(contents).map_err(|e| GameError::ConfigError(format!("bad rules: {}", e)))
+    }
+
+    /// Write the rules out, e.g. to keep with a replay
+    pub fn to_toml(&self) -> GameResult<String> {
+        toml::to_string(self).map_err(|e| GameError::ConfigError(format!("couldn't save rules: {}", e)))
+    }
+
+    /// Work out what the preset, mutators and mode do to the game
+    pub fn modifiers(&self) -> Modifiers {
+        let mut m = match self.preset {
+            Preset::Easy => Modifiers {
+                player_health: 15.,
+                spawn_rate: 0.75,
+                fire_rate: 0.75,
+                bullet_speed: 0.75,
+                ..Modifiers::default()
+            },
+            Preset::Normal => Modifiers::default(),
+            Preset::Hard => Modifiers {
+                player_health: 6.,
+                spawn_rate: 1.25,
+                fire_rate: 1.5,
+                bullet_speed: 1.25,
+                enemy_health: 1,
+                ..Modifiers::default()
+            },
+            Preset::Arcade => Modifiers {
+                player_health: 3.,
+                spawn_rate: 1.25,
+                fire_rate: 1.25,
+                ..Modifiers::default()
+            },
+        };
+
+        for &mutator in &self.mutators {
+            match mutator {
+                Mutator::DoubleSpeed => m.enemy_speed *= 2.,
+                Mutator::OneHitKill => m.player_health = 1.,
+                Mutator::NoHud => m.hud = false,
+                Mutator::Mirror => m.mirror = true,
+                Mutator::BigHead => m.hitbox_scale = 2.,
+                Mutator::AimedBullets => m.aimed_bullets = true,
+            }
+        }
+
+        m.dives = self.dives;
+
+        // Score attacks are all about chains, so there's more to shoot and longer to keep them going
+        if self.mode == Mode::ScoreAttack {
+            m.spawn_rate *= 1.5;
+            m.chain_window = CHAIN_WINDOW * 3 / 2;
+        }
+
+        m
+    }
+}
+
+/// What the rules do to archetype stats, player stats and the systems.
+/// Worked out once when the world's built and kept as a resource.
+/// The default is the game as it always was.
+#[derive(Clone, Copy, Debug)]
+pub struct Modifiers {
+    // Health each player starts with
+    pub player_health: f32,
+    // How fast baddies fly, compared to normal
+    pub enemy_speed: f32,
+    // On top of the director's `Difficulty`, fire rate going with its aggression
+    pub spawn_rate: f32,
+    pub enemy_health: u64,
+    pub fire_rate: f32,
+    pub bullet_speed: f32,
+    // Frames after a kill that the next one still adds to the chain
+    pub chain_window: u64,
+    // How much bigger baddies are to hit than they look
+    pub hitbox_scale: f32,
+    // Whether baddies aim every shot at the nearest player
+    pub aimed_bullets: bool,
+    // As `Rules::dives`
+    pub dives: bool,
+    // Whether frontends flip the game left to right
+    pub mirror: bool,
+    // Whether frontends draw the HUD
+    pub hud: bool,
+}
+
+impl Default for Modifiers {
+    fn default() -> Modifiers {
+        Modifiers {
+            player_health: MAX_PLAYER_HEALTH,
+            spawn_rate: 1.,
+            enemy_speed: 1.,
+            enemy_health: 0,
+            fire_rate: 1.,
+            bullet_speed: 1.,
+            chain_window: CHAIN_WINDOW,
+            hitbox_scale: 1.,
+            aimed_bullets: false,
+            dives: false,
+            mirror: false,
+            hud: true,
+        }
+    }
+}
+
+impl Modifiers {
+    /// Where to draw something `w` wide that's at `x` in the game,
+    /// flipping it over if the game's mirrored
+    pub fn screen_x(&self, x: f32, w: f32) -> f32 {
+        if self.mirror {
+            GAME_WIDTH - x - w
+        } else {
+            x
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everyone_gets_the_same_daily_challenge() {
+        let today = Date::from_seed(20_261_018);
+        assert_eq!(Rules::daily(today), Rules::daily(today));
+        assert_eq!(Rules::daily(today).mode, Mode::Daily);
+
+        // One mutator a day, going through all of them over consecutive days
+        let mut mutators = (0..5)
+            .map(|day| Rules::daily(Date::from_seed(20_261_018 + day)).mutators)
+            .inspect(|mutators| assert_eq!(mutators.len(), 1))
+            .map(|mutators| mutators[0])
+            .collect::<Vec<_>>();
+        mutators.sort();
+        mutators.dedup();
+        assert_eq!(mutators.len(), 5);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::game::DESIRED_FPS;
+
+/// Frames after a kill that the next one still adds to the chain, unless the mode says otherwise
+pub const CHAIN_WINDOW: u64 = 2 * DESIRED_FPS as u64;
+
+/// Kills it takes in a chain for each step up in multiplier
+const KILLS_PER_STEP: u32 = 4;
+
+/// The multiplier doesn't go any higher than this
+pub const MAX_MULTIPLIER: u32 = 8;
+
+/// Wavers in a whole wave, and the bonus for shooting all of them down
+pub const WAVE_SIZE: u32 = 11;
+pub const WAVE_BONUS: u32 = 500;
+
+/// Waves to keep count of at once. Older ones have long since flown off.
+const TRACKED_WAVES: usize = 8;
+
+/// A player's current chain of kills
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Combo {
+    chain: u32,
+    // Frame of the last kill in the chain
+    last_kill: u64,
+}
+
+impl Combo {
+    /// How many kills long the chain is on `frame`, 0 once
+    /// it's gone `window` frames without a kill
+    pub fn chain_at(&self, frame: u64, window: u64) -> u32 {
+        if frame.saturating_sub(self.last_kill) <= window {
+            self.chain
+        } else {
+            0
+        }
+    }
+
+    /// What kills are multiplied by on `frame`
+    pub fn multiplier_at(&self, frame: u64, window: u64) -> u32 {
+        multiplier(self.chain_at(frame, window))
+    }
+}
+
+/// What kills are multiplied by with a chain `chain` kills long
+fn multiplier(chain: u32) -> u32 {
+    (1 + chain / KILLS_PER_STEP).min(MAX_MULTIPLIER)
+}
+
+/// Every player's combo, and how many of each recent wave have been shot down
+#[derive(Clone, Debug, Default)]
+pub struct Combos {
+    // Games from before combos just score what each baddy's worth
+    enabled: bool,
+    // How long chains last between kills
+    window: u64,
+    players: Vec<Combo>,
+    // Wave and how many of it have gone down, oldest first
+    waves: Vec<(u64, u32)>,
+}
+
+impl Combos {
+    pub fn new(enabled: bool, window: u64, players: usize) -> Combos {
+        Combos {
+            enabled,
+            window,
+            players: vec![Combo::default(); players],
+            waves: vec![],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// What player `id`'s kills are multiplied by on `frame`
+    pub fn multiplier(&self, id: usize, frame: u64) -> u32 {
+        let combo = self.players.get(id).copied().unwrap_or_default();
+        combo.multiplier_at(frame, self.window)
+    }
+
+    /// Player `id` shot down a baddy worth `score` on `frame`. Returns what
+    /// it's worth with the multiplier, and how long the chain is now.
+    pub fn kill(&mut self, id: usize, frame: u64, score: u32) -> (u32, u32) {
+        let combo = match self.players.get_mut(id) {
+            Some(combo) if self.enabled => combo,
+            _ => return (score, 0),
+        };
+
+        combo.chain = combo.chain_at(frame, self.window) + 1;
+        combo.last_kill = frame;
+
+        (score * multiplier(combo.chain), combo.chain)
+    }
+
+    /// Player `id` got hit, so their chain's over
+    pub fn break_chain(&mut self, id: usize) {
+        if let Some(combo) = self.players.get_mut(id) {
+            combo.chain = 0;
+        }
+    }
+
+    /// A waver from `wave` got shot down, returns the bonus if that was the last of them
+    pub fn wave_kill(&mut self, wave: u64) -> Option<u32> {
+        if !self.enabled {
+            return None;
+        }
+
+        let i = match self.waves.iter().position(|&(w, _)| w == wave) {
+            Some(i) => i,
+            None => {
+                if self.waves.len() == TRACKED_WAVES {
+                    self.waves.remove(0);
+                }
+                self.waves.push((wave, 0));
+                self.waves.len() - 1
+            }
+        };
+
+        self.waves[i].1 += 1;
+        if self.waves[i].1 == WAVE_SIZE {
+            self.waves.remove(i);
+            Some(WAVE_BONUS)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use ggez::{GameError, GameResult};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Samples per second of the rendered sounds
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// Longest a sound may be, in seconds
+const MAX_LENGTH: f32 = 3.;
+
+/// Presets for all our sound effects
+const PRESETS: &str = include_str!("../resources/sfx.toml");
+
+/// Shape of the oscillator
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Wave {
+    Square,
+    Saw,
+    Sine,
+    Noise,
+}
+
+/// Everything needed to render a single sound effect.
+/// Times are in seconds, frequencies in Hz and slides in octaves per second.
+#[derive(Clone, Debug)]
+pub struct Params {
+    pub wave: Wave,
+    // Fraction of the period a square wave is high for
+    pub duty: f32,
+    // Volume envelope
+    pub attack: f32,
+    pub sustain: f32,
+    pub punch: f32,
+    pub decay: f32,
+    // Pitch and how it changes over time
+    pub base_freq: f32,
+    pub freq_limit: f32,
+    pub freq_slide: f32,
+    pub freq_delta_slide: f32,
+    pub vibrato_depth: f32,
+    pub vibrato_speed: f32,
+    // Jump in pitch by `arp_mod` after `arp_time`
+    pub arp_mod: f32,
+    pub arp_time: f32,
+    // Seeds the noise generator
+    pub seed: u64,
+}
+
+impl Params {
+    /// Render the sound as mono samples at `SAMPLE_RATE`
+    pub fn render(&self) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        let length = (self.attack + self.sustain + self.decay).min(MAX_LENGTH);
+        let count = (length * SAMPLE_RATE as f32) as usize;
+
+        let mut samples = Vec::with_capacity(count);
+        let mut phase = 0.;
+        let mut noise = rng.gen_range(-1., 1.);
+
+        for i in 0..count {
+            let t = i as f32 / SAMPLE_RATE as f32;
+
+            // Slide the pitch, stopping once it drops too low
+            let octaves = self.freq_slide * t + 0.5 * self.freq_delta_slide * t * t;
+            let mut freq = self.base_freq * 2f32.powf(octaves);
+            if freq < self.freq_limit {
+                break;
+            }
+
+            if t >= self.arp_time && self.arp_time > 0. {
+                freq *= self.arp_mod;
+            }
+
+            freq *= 1. + self.vibrato_depth * (2. * PI * self.vibrato_speed * t).sin();
+
+            // Pick a new noise value each time around
+            phase += freq / SAMPLE_RATE as f32;
+            if phase >= 1. {
+                phase -= phase.floor();
+                noise = rng.gen_range(-1., 1.);
+            }
+
+            let sample = match self.wave {
+                Wave::Square if phase < self.duty => 1.,
+                Wave::Square => -1.,
+                Wave::Saw => 1. - 2. * phase,
+                Wave::Sine => (2. * PI * phase).sin(),
+                Wave::Noise => noise,
+            };
+
+            samples.push(sample * self.envelope(t));
+        }
+
+        samples
+    }
+
+    /// Volume at time `t`
+    fn envelope(&self, t: f32) -> f32 {
+        if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.sustain {
+            // Punch starts loud and fades down to normal over the sustain
+            let st = (t - self.attack) / self.sustain;
+            1. + self.punch * (1. - st)
+        } else {
+            let dt = (t - self.attack - self.sustain) / self.decay.max(f32::EPSILON);
+            (1. - dt).max(0.)
+        }
+    }
+}
+
+/// Write mono samples out as a 16-bit PCM WAV file
+pub fn write_wav<W: Write>(out: &mut W, samples: &[f32]) -> io::Result<()> {
+    let data_len = samples.len() as u32 * 2;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    // Format chunk: PCM, mono, 16 bits per sample
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for s in samples {
+        let s = (s.clamp(-1., 1.) * f32::from(i16::MAX)) as i16;
+        out.write_all(&s.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Render the sound straight to an in-memory WAV file
+pub fn render_wav(params: &Params) -> Vec<u8> {
+    let mut wav = vec![];
+    write_wav(&mut wav, &params.render()).expect("writing to a Vec can't fail");
+    wav
+}
+
+/// A `[min, max]` range a parameter is picked from
+#[derive(Clone, Copy, Deserialize)]
+struct Range(f32, f32);
+
+impl Range {
+    fn pick(self, rng: &mut StdRng) -> f32 {
+        if self.0 < self.1 {
+            rng.gen_range(self.0, self.1)
+        } else {
+            self.0
+        }
+    }
+}
+
+fn zero() -> Range {
+    Range(0., 0.)
+}
+
+fn one() -> Range {
+    Range(1., 1.)
+}
+
+fn half() -> Range {
+    Range(0.5, 0.5)
+}
+
+/// A preset as described in the data file
+#[derive(Deserialize)]
+struct Preset {
+    seed: u64,
+    wave: Wave,
+    #[serde(default = "half")]
+    duty: Range,
+    #[serde(default = "zero")]
+    attack: Range,
+    #[serde(default = "zero")]
+    sustain: Range,
+    #[serde(default = "zero")]
+    punch: Range,
+    #[serde(default = "zero")]
+    decay: Range,
+    base_freq: Range,
+    #[serde(default = "zero")]
+    freq_limit: Range,
+    #[serde(default = "zero")]
+    freq_slide: Range,
+    #[serde(default = "zero")]
+    freq_delta_slide: Range,
+    #[serde(default = "zero")]
+    vibrato_depth: Range,
+    #[serde(default = "zero")]
+    vibrato_speed: Range,
+    #[serde(default = "one")]
+    arp_mod: Range,
+    #[serde(default = "zero")]
+    arp_time: Range,
+}
+
+impl Preset {
+    /// Pick concrete parameters using the preset's seed
+    fn params(&self) -> Params {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        Params {
+            wave: self.wave,
+            duty: self.duty.pick(&mut rng),
+            attack: self.attack.pick(&mut rng),
+            sustain: self.sustain.pick(&mut rng),
+            punch: self.punch.pick(&mut rng),
+            decay: self.decay.pick(&mut rng),
+            base_freq: self.base_freq.pick(&mut rng),
+            freq_limit: self.freq_limit.pick(&mut rng),
+            freq_slide: self.freq_slide.pick(&mut rng),
+            freq_delta_slide: self.freq_delta_slide.pick(&mut rng),
+            vibrato_depth: self.vibrato_depth.pick(&mut rng),
+            vibrato_speed: self.vibrato_speed.pick(&mut rng),
+            arp_mod: self.arp_mod.pick(&mut rng),
+            arp_time: self.arp_time.pick(&mut rng),
+            seed: self.seed,
+        }
+    }
+}
+
+/// Load the built-in presets, keyed by name
+pub fn presets() -> GameResult<HashMap<String, Params>> {
+    let presets: HashMap<String, Preset> = toml::from_str(PRESETS)
+        .map_err(|e| GameError::ConfigError(format!("bad sound presets: {}", e)))?;
+
+    Ok(presets
+        .into_iter()
+        .map(|(name, preset)| (name, preset.params()))
+        .collect())
+}
+
+/// Write every preset out as `<name>.wav` in the given directory
+pub fn export(dir: &Path) -> GameResult<()> {
+    std::fs::create_dir_all(dir)?;
+
+    for (name, params) in presets()? {
+        let mut out = BufWriter::new(File::create(dir.join(format!("{}.wav", name)))?);
+        write_wav(&mut out, &params.render())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What every preset rendered to when it was last checked by ear,
+    /// as FNV-1a hashes of the WAV. If a change to the synthesizer or the
+    /// presets is meant to change how they sound, listen to them with
+    /// `--export-sfx` and update these.
+    const GOLDEN: &[(&str, u64)] = &[
+        ("enemy_death", 0x4210_6f6d_87fd_141e),
+        ("enemy_hit", 0xc7bd_8630_4d45_14eb),
+        ("extra_life", 0x4d06_11de_0275_7694),
+        ("player_hit", 0x7e08_f4df_e135_53ed),
+        ("player_shot", 0xb600_0b1e_f079_e381),
+        ("stage_start", 0x68ce_8831_113a_5965),
+    ];
+
+    fn fnv1a(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    #[test]
+    fn presets_sound_the_same() {
+        let presets = presets().unwrap();
+        assert_eq!(presets.len(), GOLDEN.len(), "every preset needs a golden hash");
+
+        for &(name, golden) in GOLDEN {
+            let params = presets.get(name).unwrap_or_else(|| panic!("no preset called {}", name));
+            assert_eq!(fnv1a(&render_wav(params)), golden, "{} sounds different", name);
+        }
+    }
+}
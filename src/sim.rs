@@ -0,0 +1,460 @@
+use crate::audio::Sound;
+use crate::components::*;
+use crate::director::Director;
+use crate::entities;
+use crate::game::{Accuracy, Frames, GameRng, InputState, Inputs, PlayerHealth, PlayerScore};
+use crate::modes::{Progress, Schedule};
+use crate::particles::{ParticlePool, DEFAULT_MAX_PARTICLES};
+use crate::rules::{Modifiers, Rules};
+use crate::scoring::Combos;
+use crate::stages::Stages;
+use crate::systems;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use specs::shred::Fetch;
+use specs::shrev::EventChannel;
+use specs::world::Index;
+use specs::{Builder, Component, Dispatcher, DispatcherBuilder, Entity, Join, World, WorldExt};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+/// The game itself: our ECS world and the systems that act on it.
+/// Frontends just feed it input, tick it and draw what's in the world.
+///
+/// Everything that decides how the game goes, like the director, stages,
+/// combos and the mode's progress, lives in the world and only looks at the
+/// game itself. That way a replay plays out the same way every time, and
+/// snapshots can rewind all of it.
+pub struct Simulation<'a, 'b> {
+    // ECS world
+    pub world: World,
+
+    // Runs our various systems
+    dispatcher: Dispatcher<'a, 'b>,
+}
+
+impl<'a, 'b> Simulation<'a, 'b> {
+    /// Set up a fresh game, the seed decides everything that's left to chance
+    pub fn new(seed: u64) -> Simulation<'a, 'b> {
+        Simulation::with_players(seed, 1)
+    }
+
+    /// Set up a fresh game for `players` players playing together
+    pub fn with_players(seed: u64, players: usize) -> Simulation<'a, 'b> {
+        Simulation::with_rules(seed, players, &Rules::default())
+    }
+
+    /// Set up a fresh game played under `rules`
+    pub fn with_rules(seed: u64, players: usize, rules: &Rules) -> Simulation<'a, 'b> {
+        // Let's setup our ECS
+        let mut world = World::new();
+
+        // Register our components
+        register_components(&mut world);
+
+        // Create our player entities
+        for id in 0..players {
+            entities::create_player(&mut world, id, players);
+        }
+
+        // Animations let others know when they're done
+        world.insert(EventChannel::<AnimationEnded>::new());
+
+        // Particle effects live outside the ECS
+        world.insert(ParticlePool::new(DEFAULT_MAX_PARTICLES, seed));
+
+        // Systems let the frontend know what sounds to play
+        world.insert(EventChannel::<Sound>::new());
+
+        // Register our systems.
+        // They run one after another in this order, rather than in parallel,
+        // so entities are created and random numbers drawn in the same order
+        // every time. Replays depend on that.
+        let dispatcher = DispatcherBuilder::new()
+            .with_thread_local(systems::DirectorSystem)
+            .with_thread_local(systems::StageSystem)
+            .with_thread_local(systems::ModeSystem)
+            .with_thread_local(systems::BaddySpawner)
+            .with_thread_local(systems::BaddyActions)
+            .with_thread_local(systems::PlayerControlSystem)
+            .with_thread_local(systems::MovementSystem)
+            .with_thread_local(systems::CollisionSystem)
+            .with_thread_local(systems::AnimationSystem)
+            .with_thread_local(systems::ParticleSystem)
+            .with_thread_local(systems::AnimationCleanup::new(&mut world))
+            .with_thread_local(systems::ScorePopupExpiry)
+            .build();
+
+        // Initialize every player's input state and provide
+        // it as resource to be read by any system
+        world.insert(Inputs(vec![InputState::default(); players]));
+
+        // Also provide frame count as resource
+        world.insert::<Frames>(Default::default());
+
+        // What the rules change about the game, which stays the same throughout
+        let mods = rules.modifiers();
+        world.insert(mods);
+
+        // And every player's health and score
+        world.insert(PlayerHealth(vec![mods.player_health; players]));
+        world.insert(PlayerScore(vec![0; players]));
+        world.insert(Accuracy::default());
+        world.insert(Combos::new(rules.combos, mods.chain_window, players));
+
+        // Along with the director keeping an eye on how they're doing
+        world.insert(Director::new(rules.director()));
+
+        // And what stage they're on, in modes that have them
+        world.insert(Stages::new(rules.stages && rules.mode.schedule() == Schedule::Stages));
+
+        // And how close they are to the mode's goal
+        world.insert(Progress::new(rules.mode));
+
+        // And the game's own source of randomness
+        world.insert(GameRng(StdRng::seed_from_u64(seed)));
+
+        Simulation { world, dispatcher }
+    }
+
+    /// Let up to `max` particles be alive at once. They're only for show,
+    /// so this can differ between machines without the game playing differently.
+    pub fn set_max_particles(&mut self, max: usize) {
+        self.world.write_resource::<ParticlePool>().set_max(max);
+    }
+
+    /// Compute a single frame of the game
+    pub fn tick(&mut self) {
+        // Run the systems!
+        self.dispatcher.dispatch(&self.world);
+
+        // Let any changes get reflected
+        self.world.maintain();
+
+        // Update "frame" count
+        let mut frames = self.world.write_resource::<Frames>();
+        frames.0 += 1;
+    }
+
+    /// Set what player `id` is pressing for the coming frames
+    pub fn set_input(&mut self, id: usize, input: InputState) {
+        if let Some(state) = self.world.write_resource::<Inputs>().0.get_mut(id) {
+            *state = input;
+        }
+    }
+
+    /// Sums up the state of the game, so two copies that should
+    /// be playing out the same way can easily be checked
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.frame().hash(&mut hasher);
+        for id in 0..self.players() {
+            (self.health(id).to_bits(), self.score(id)).hash(&mut hasher);
+        }
+        self.director().level().to_bits().hash(&mut hasher);
+        self.stages().number().hash(&mut hasher);
+
+        let entities = self.world.entities();
+        let position = self.world.read_storage::<Position>();
+        for (e, pos) in (&entities, &position).join() {
+            (e.id(), pos.x.to_bits(), pos.y.to_bits()).hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
+
+    /// Remember everything about the game right now, to go back to later
+    pub fn snapshot(&mut self) -> Snapshot {
+        let (entity_count, free) = {
+            let entities = self.world.entities();
+            let alive = entities.join().map(|e| e.id()).collect::<HashSet<_>>();
+
+            // Ids get handed out in order, so every id below the first one that's
+            // neither alive nor deleted has been used at some point
+            let mut entity_count = 0;
+            let mut deleted = 0;
+            loop {
+                if !alive.contains(&entity_count) {
+                    // Deleted entities are left with a dead generation, ones never made aren't
+                    if entities.entity(entity_count).gen().is_alive() {
+                        break;
+                    }
+                    deleted += 1;
+                }
+                entity_count += 1;
+            }
+
+            // Specs won't tell us what order deleted ids will be reused in, which decides
+            // what ids new entities get and so what order everything's joined in.
+            // Find out by asking for them all back.
+            let free = (0..deleted).map(|_| entities.create()).collect::<Vec<_>>();
+            (entity_count, free)
+        };
+
+        // Then delete them again last to first, so they're reused in the same order as before
+        let mut reversed = free.clone();
+        reversed.reverse();
+        self.world.delete_entities(&reversed).expect("unexpected generation error");
+
+        let world = &self.world;
+        Snapshot {
+            entity_count,
+            free: free.iter().map(|e| e.id()).collect(),
+            // Any component added to `register_components` needs saving here too
+            components: vec![
+                save::<Animation>(world),
+                save::<Baddy>(world),
+                save::<BossBaddy>(world),
+                save::<ChallengeBaddy>(world),
+                save::<DamageBaddy>(world),
+                save::<DamagePlayer>(world),
+                save::<Dive>(world),
+                save::<Emitter>(world),
+                save::<NoobBaddy>(world),
+                save::<OneShot>(world),
+                save::<Oscillates>(world),
+                save::<Player>(world),
+                save::<Position>(world),
+                save::<PreviousPosition>(world),
+                save::<Rendered>(world),
+                save::<ScoreText>(world),
+                save::<Velocity>(world),
+                save::<WaverBaddy>(world),
+            ],
+            frames: *world.read_resource::<Frames>(),
+            health: (*world.read_resource::<PlayerHealth>()).clone(),
+            score: (*world.read_resource::<PlayerScore>()).clone(),
+            accuracy: *world.read_resource::<Accuracy>(),
+            combos: (*world.read_resource::<Combos>()).clone(),
+            director: (*world.read_resource::<Director>()).clone(),
+            stages: (*world.read_resource::<Stages>()).clone(),
+            progress: (*world.read_resource::<Progress>()).clone(),
+            rng: (*world.read_resource::<GameRng>()).clone(),
+            particles: (*world.read_resource::<ParticlePool>()).clone(),
+            inputs: (*world.read_resource::<Inputs>()).clone(),
+        }
+    }
+
+    /// Put the game back exactly how it was when the snapshot was taken
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let mut world = World::new();
+        register_components(&mut world);
+
+        // Keep the same event channels, so the systems and
+        // whoever's listening for sounds can still read them
+        let animations = self.world.remove::<EventChannel<AnimationEnded>>().unwrap_or_default();
+        let sounds = self.world.remove::<EventChannel<Sound>>().unwrap_or_default();
+        world.insert(animations);
+        world.insert(sounds);
+
+        // The rules never change during a game, so they don't need saving
+        world.insert(*self.world.read_resource::<Modifiers>());
+
+        // Make every entity there's ever been so they all get the same ids,
+        // then delete the dead ones so their ids get reused in the same order
+        let entities = (0..snapshot.entity_count)
+            .map(|_| world.create_entity().build())
+            .collect::<Vec<_>>();
+        let free = snapshot
+            .free
+            .iter()
+            .rev()
+            .map(|&id| entities[id as usize])
+            .collect::<Vec<_>>();
+        world.delete_entities(&free).expect("unexpected generation error");
+
+        for saved in &snapshot.components {
+            saved.restore(&mut world, &entities);
+        }
+
+        world.insert(snapshot.frames);
+        world.insert(snapshot.health.clone());
+        world.insert(snapshot.score.clone());
+        world.insert(snapshot.accuracy);
+        world.insert(snapshot.combos.clone());
+        world.insert(snapshot.director.clone());
+        world.insert(snapshot.stages.clone());
+        world.insert(snapshot.progress.clone());
+        world.insert(snapshot.rng.clone());
+        world.insert(snapshot.particles.clone());
+        world.insert(snapshot.inputs.clone());
+
+        self.world = world;
+    }
+
+    /// How many frames have been computed so far
+    pub fn frame(&self) -> u64 {
+        self.world.read_resource::<Frames>().0
+    }
+
+    /// We play until every player's health goes to 0,
+    /// or the mode decides the game's over some other way
+    pub fn game_over(&self) -> bool {
+        self.world.read_resource::<PlayerHealth>().0.iter().all(|&h| h <= 0.) || self.progress().outcome().is_some()
+    }
+
+    /// What's shown over the game once it's over
+    pub fn ending(&self) -> &'static str {
+        self.progress().outcome().map_or("GAME\nOVER", |outcome| outcome.label())
+    }
+
+    /// How many players are playing
+    pub fn players(&self) -> usize {
+        self.world.read_resource::<PlayerHealth>().0.len()
+    }
+
+    /// Player `id`'s current health
+    pub fn health(&self, id: usize) -> f32 {
+        self.world.read_resource::<PlayerHealth>().0.get(id).copied().unwrap_or_default()
+    }
+
+    /// Player `id`'s current score
+    pub fn score(&self, id: usize) -> u32 {
+        self.world.read_resource::<PlayerScore>().0.get(id).copied().unwrap_or_default()
+    }
+
+    /// What player `id`'s kills are being multiplied by right now
+    pub fn multiplier(&self, id: usize) -> u32 {
+        self.world.read_resource::<Combos>().multiplier(id, self.frame())
+    }
+
+    /// What the rules change about the game
+    pub fn modifiers(&self) -> Modifiers {
+        *self.world.read_resource::<Modifiers>()
+    }
+
+    /// The director and what it's decided so far
+    pub fn director(&self) -> Fetch<'_, Director> {
+        self.world.read_resource::<Director>()
+    }
+
+    /// What stage the game's on and how the last one went
+    pub fn stages(&self) -> Fetch<'_, Stages> {
+        self.world.read_resource::<Stages>()
+    }
+
+    /// How close the players are to the mode's goal
+    pub fn progress(&self) -> Fetch<'_, Progress> {
+        self.world.read_resource::<Progress>()
+    }
+
+    /// Everyone's scores added up
+    pub fn total_score(&self) -> u32 {
+        self.world.read_resource::<PlayerScore>().0.iter().sum()
+    }
+}
+
+/// A copy of the whole game at one moment, see `Simulation::snapshot`
+pub struct Snapshot {
+    // How many entity ids have been handed out
+    entity_count: Index,
+    // Ids of deleted entities, in the order they'll be reused
+    free: Vec<Index>,
+    components: Vec<Box<dyn SavedStorage>>,
+    frames: Frames,
+    health: PlayerHealth,
+    score: PlayerScore,
+    accuracy: Accuracy,
+    combos: Combos,
+    director: Director,
+    stages: Stages,
+    progress: Progress,
+    rng: GameRng,
+    particles: ParticlePool,
+    inputs: Inputs,
+}
+
+impl Snapshot {
+    /// Which frame the snapshot was taken on
+    pub fn frame(&self) -> u64 {
+        self.frames.0
+    }
+
+    /// Player `id`'s health when the snapshot was taken
+    pub fn health(&self, id: usize) -> f32 {
+        self.health.0.get(id).copied().unwrap_or_default()
+    }
+
+    /// Player `id`'s score when the snapshot was taken
+    pub fn score(&self, id: usize) -> u32 {
+        self.score.0.get(id).copied().unwrap_or_default()
+    }
+
+    /// Whether the game was already over when the snapshot was taken
+    pub fn game_over(&self) -> bool {
+        self.health.0.iter().all(|&h| h <= 0.) || self.progress.outcome().is_some()
+    }
+}
+
+/// Every component of one type, saved in a snapshot
+trait SavedStorage: Send + Sync {
+    /// Give the entities their components back
+    fn restore(&self, world: &mut World, entities: &[Entity]);
+}
+
+struct Saved<T>(Vec<(Index, T)>);
+
+impl<T: Component + Clone + Send + Sync> SavedStorage for Saved<T> {
+    fn restore(&self, world: &mut World, entities: &[Entity]) {
+        let mut storage = world.write_storage::<T>();
+        for (id, component) in &self.0 {
+            storage
+                .insert(entities[*id as usize], component.clone())
+                .expect("unexpected generation error");
+        }
+    }
+}
+
+fn save<T: Component + Clone + Send + Sync>(world: &World) -> Box<dyn SavedStorage> {
+    let entities = world.entities();
+    let storage = world.read_storage::<T>();
+    let saved = (&entities, &storage).join().map(|(e, c)| (e.id(), c.clone())).collect();
+    Box::new(Saved(saved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Bot;
+
+    #[test]
+    fn snapshots_restore_the_same_game() {
+        let mut sim = Simulation::with_players(3, 2);
+        let mut bots = [Bot::new(0), Bot::new(1)];
+        let mut play = |sim: &mut Simulation, frames: u64| {
+            let mut inputs = vec![];
+            for _ in 0..frames {
+                let frame = bots.iter_mut().map(|bot| bot.think(&sim.world)).collect::<Vec<_>>();
+                for (id, &input) in frame.iter().enumerate() {
+                    sim.set_input(id, input);
+                }
+                sim.tick();
+                inputs.push(frame);
+            }
+            inputs
+        };
+
+        play(&mut sim, 600);
+        let before = sim.checksum();
+        let snapshot = sim.snapshot();
+        assert_eq!(sim.checksum(), before);
+
+        let inputs = play(&mut sim, 600);
+        let after = sim.checksum();
+
+        sim.restore(&snapshot);
+        assert_eq!(sim.checksum(), before);
+
+        // And it carries on exactly as it did the first time
+        for frame in inputs {
+            for (id, &input) in frame.iter().enumerate() {
+                sim.set_input(id, input);
+            }
+            sim.tick();
+        }
+        assert_eq!(sim.checksum(), after);
+    }
+}
@@ -0,0 +1,300 @@
+use crate::components::Velocity;
+use crate::game::{Accuracy, DESIRED_FPS};
+
+use std::f32::consts::PI;
+
+/// Frames baddies keep coming for in a normal stage
+const STAGE_FRAMES: u64 = 45 * DESIRED_FPS as u64;
+
+/// Frames a stage's results stay up before the next one starts
+const RESULTS_FRAMES: u64 = 4 * DESIRED_FPS as u64;
+
+/// Frames a stage's name is shown for when it starts
+const BANNER_FRAMES: u64 = 2 * DESIRED_FPS as u64;
+
+/// Every so many stages is a challenging stage, starting with stage 3
+const CHALLENGE_EVERY: u32 = 4;
+
+/// Groups flying through a challenging stage, and how many baddies each has
+pub const GROUPS: usize = 5;
+pub const GROUP_SIZE: u32 = 8;
+
+/// Frames between each group flying in, and between baddies in a group
+const GROUP_INTERVAL: u64 = 3 * DESIRED_FPS as u64;
+const FOLLOW_FRAMES: u64 = 8;
+
+/// Score for shooting down every baddy in a group
+pub const GROUP_BONUS: u32 = 1000;
+
+/// How the baddies in a challenging stage group fly
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    // Weaving across from the left
+    WeaveRight,
+    // Weaving across from the right
+    WeaveLeft,
+    // Diving from the top, looping once and carrying on down
+    Loop,
+}
+
+impl Pattern {
+    /// The pattern group `group` flies
+    pub fn for_group(group: usize) -> Pattern {
+        match group % 3 {
+            0 => Pattern::WeaveRight,
+            1 => Pattern::WeaveLeft,
+            _ => Pattern::Loop,
+        }
+    }
+
+    /// How fast a baddy flying the pattern should be going `age` frames in
+    pub fn velocity(self, age: u64) -> Velocity {
+        let t = age as f32;
+        match self {
+            Pattern::WeaveRight => Velocity {
+                x: 3.,
+                y: 3. * (t * 0.05).cos(),
+            },
+            Pattern::WeaveLeft => Velocity {
+                x: -3.,
+                y: 3. * (t * 0.05).cos(),
+            },
+            Pattern::Loop => {
+                // Straight down at first, then turn a full circle
+                let turn = ((t - 40.).max(0.) * 0.06).min(2. * PI);
+                let angle = PI / 2. + turn;
+                Velocity {
+                    x: 4. * angle.cos(),
+                    y: 4. * angle.sin(),
+                }
+            }
+        }
+    }
+}
+
+/// Where a stage is up to
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    // Baddies are coming
+    Fighting,
+    // No more are coming, waiting for the last ones to go
+    Clearing,
+    // Showing how the stage went
+    Results,
+}
+
+/// How many baddies in a challenging stage group flew in and got shot down
+#[derive(Clone, Copy, Debug, Default)]
+struct Group {
+    killed: u32,
+}
+
+/// How the players did over a stage
+#[derive(Clone, Copy, Debug)]
+pub struct StageResults {
+    pub stage: u32,
+    pub challenging: bool,
+    pub shots: u64,
+    pub hits: u64,
+    // Challenging stage groups shot down completely
+    pub perfect_groups: u32,
+    pub bonus: u32,
+}
+
+impl StageResults {
+    /// Fraction of shots that hit something
+    pub fn hit_ratio(&self) -> f32 {
+        if self.shots > 0 {
+            self.hits as f32 / self.shots as f32
+        } else {
+            0.
+        }
+    }
+
+    /// The results as shown between stages, one line each
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("STAGE {} RESULTS", self.stage),
+            format!("SHOTS FIRED {}", self.shots),
+            format!("NUMBER OF HITS {}", self.hits),
+            format!("HIT RATIO {:.1}%", self.hit_ratio() * 100.),
+        ];
+        if self.challenging {
+            lines.push(format!("PERFECT GROUPS {}/{}", self.perfect_groups, GROUPS));
+            lines.push(format!("BONUS {}", self.bonus));
+        }
+        lines
+    }
+}
+
+/// Splits the game into stages, with a challenging stage every so often where
+/// groups of baddies fly scripted patterns without shooting back. Between
+/// stages the game stops for a look at how the last one went.
+#[derive(Clone, Debug)]
+pub struct Stages {
+    // Games from before stages just carry on forever
+    enabled: bool,
+    // Counting from 1
+    number: u32,
+    phase: Phase,
+    // Frames since the phase started
+    elapsed: u64,
+    // Shots and hits when the stage started
+    shots: u64,
+    hits: u64,
+    // Challenging stage baddies flown in so far, and how each group's doing
+    spawned: u32,
+    groups: Vec<Group>,
+    results: Option<StageResults>,
+}
+
+impl Default for Stages {
+    fn default() -> Stages {
+        Stages::new(false)
+    }
+}
+
+impl Stages {
+    pub fn new(enabled: bool) -> Stages {
+        Stages {
+            enabled,
+            number: 1,
+            phase: Phase::Fighting,
+            elapsed: 0,
+            shots: 0,
+            hits: 0,
+            spawned: 0,
+            groups: vec![Group::default(); GROUPS],
+            results: None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Which stage it is, counting from 1
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// Whether this is a challenging stage
+    pub fn challenging(&self) -> bool {
+        self.enabled && self.number % CHALLENGE_EVERY == 3
+    }
+
+    /// Whether the usual baddies should be spawning
+    pub fn spawning(&self) -> bool {
+        !self.enabled || (self.phase == Phase::Fighting && !self.challenging())
+    }
+
+    /// How the last stage went, while it's being shown
+    pub fn results(&self) -> Option<&StageResults> {
+        match self.phase {
+            Phase::Results => self.results.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The stage's name, for a little while after it starts
+    pub fn banner(&self) -> Option<String> {
+        if !self.enabled || self.phase != Phase::Fighting || self.elapsed >= BANNER_FRAMES {
+            return None;
+        }
+
+        if self.challenging() {
+            Some(format!("STAGE {}\nCHALLENGING STAGE", self.number))
+        } else {
+            Some(format!("STAGE {}", self.number))
+        }
+    }
+
+    /// Move the stage on a frame, given how many baddies are still
+    /// around. Returns the group of any challenging stage baddy
+    /// that should fly in this frame.
+    pub fn update(&mut self, accuracy: Accuracy, baddies: usize) -> Option<usize> {
+        self.elapsed += 1;
+
+        match self.phase {
+            Phase::Fighting if self.challenging() => {
+                let total = GROUPS as u32 * GROUP_SIZE;
+                if self.spawned == total {
+                    self.next_phase(Phase::Clearing);
+                    return None;
+                }
+
+                // Each group flies in one after another, nose to tail
+                let group = (self.spawned / GROUP_SIZE) as usize;
+                let index = u64::from(self.spawned % GROUP_SIZE);
+                if self.elapsed >= group as u64 * GROUP_INTERVAL + index * FOLLOW_FRAMES {
+                    self.spawned += 1;
+                    return Some(group);
+                }
+            }
+
+            Phase::Fighting => {
+                if self.elapsed >= STAGE_FRAMES {
+                    self.next_phase(Phase::Clearing);
+                }
+            }
+
+            Phase::Clearing => {
+                if baddies == 0 {
+                    self.results = Some(self.tally(accuracy));
+                    self.next_phase(Phase::Results);
+                }
+            }
+
+            Phase::Results => {
+                if self.elapsed >= RESULTS_FRAMES {
+                    *self = Stages {
+                        number: self.number + 1,
+                        shots: accuracy.shots,
+                        hits: accuracy.hits,
+                        results: self.results,
+                        ..Stages::new(true)
+                    };
+                }
+            }
+        }
+
+        None
+    }
+
+    /// A challenging stage baddy from `group` got shot down,
+    /// returns the bonus if that was the whole group
+    pub fn shot_down(&mut self, group: usize) -> Option<u32> {
+        let group = self.groups.get_mut(group)?;
+        group.killed += 1;
+
+        if group.killed == GROUP_SIZE {
+            Some(GROUP_BONUS)
+        } else {
+            None
+        }
+    }
+
+    fn next_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.elapsed = 0;
+    }
+
+    /// Add up how the stage went
+    fn tally(&self, accuracy: Accuracy) -> StageResults {
+        let challenging = self.challenging();
+        let perfect_groups = if challenging {
+            self.groups.iter().filter(|g| g.killed == GROUP_SIZE).count() as u32
+        } else {
+            0
+        };
+
+        StageResults {
+            stage: self.number,
+            challenging,
+            shots: accuracy.shots - self.shots,
+            hits: accuracy.hits - self.hits,
+            perfect_groups,
+            bonus: perfect_groups * GROUP_BONUS,
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::game;
+
+use ggez::graphics::{self, DrawParam, MeshBuilder};
+use ggez::{Context, GameResult};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// How a single layer of stars looks and moves
+struct Layer {
+    count: usize,
+    speed: f32,
+    size: f32,
+    colour: (u8, u8, u8),
+}
+
+/// Far away stars are small, dim and slow;
+/// close ones are big, bright and fast
+const LAYERS: [Layer; 3] = [
+    Layer {
+        count: 60,
+        speed: 0.5,
+        size: 1.,
+        colour: (0x55, 0x55, 0x77),
+    },
+    Layer {
+        count: 35,
+        speed: 1.,
+        size: 1.,
+        colour: (0x99, 0x99, 0xBB),
+    },
+    Layer {
+        count: 15,
+        speed: 2.,
+        size: 2.,
+        colour: (0xEE, 0xEE, 0xFF),
+    },
+];
+
+/// How quickly the scroll speed catches up to the target speed
+const SPEED_EASING: f32 = 0.02;
+
+struct Star {
+    x: f32,
+    y: f32,
+    layer: usize,
+    // Stars blink off for a few frames every `twinkle_period` frames
+    twinkle_period: u64,
+    twinkle_offset: u64,
+}
+
+/// Scrolling background of stars
+pub struct Starfield {
+    rng: StdRng,
+    stars: Vec<Star>,
+    speed: f32,
+    target_speed: f32,
+    frame: u64,
+}
+
+impl Starfield {
+    /// Generate a new starfield from the game seed
+    pub fn new(seed: u64) -> Starfield {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut stars = vec![];
+        for (layer, l) in LAYERS.iter().enumerate() {
+            for _ in 0..l.count {
+                stars.push(Star {
+                    x: rng.gen_range(0., game::GAME_WIDTH),
+                    y: rng.gen_range(0., game::GAME_HEIGHT),
+                    layer,
+                    twinkle_period: rng.gen_range(60, 240),
+                    twinkle_offset: rng.gen_range(0, 240),
+                });
+            }
+        }
+
+        Starfield {
+            rng,
+            stars,
+            speed: 1.,
+            target_speed: 1.,
+            frame: 0,
+        }
+    }
+
+    /// Gradually change how fast the stars scroll
+    /// (1 is normal speed, 0 stops them altogether)
+    pub fn set_speed(&mut self, speed: f32) {
+        self.target_speed = speed;
+    }
+
+    /// Scroll the stars down by one frame
+    pub fn update(&mut self) {
+        self.speed += (self.target_speed - self.speed) * SPEED_EASING;
+        self.frame += 1;
+
+        for star in &mut self.stars {
+            star.y += LAYERS[star.layer].speed * self.speed;
+
+            // Wrap stars that fall off the bottom back to the top
+            if star.y >= game::GAME_HEIGHT {
+                star.y -= game::GAME_HEIGHT;
+                star.x = self.rng.gen_range(0., game::GAME_WIDTH);
+            }
+        }
+    }
+
+    /// Draw the visible stars
+    pub fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        let mut mesh = MeshBuilder::new();
+
+        for star in &self.stars {
+            // Skip stars that are currently twinkled off
+            if (self.frame + star.twinkle_offset) % star.twinkle_period < 6 {
+                continue;
+            }
+
+            let layer = &LAYERS[star.layer];
+            let area = [star.x, star.y, layer.size, layer.size].into();
+            mesh.rectangle(graphics::DrawMode::fill(), area, layer.colour.into());
+        }
+
+        let mesh = mesh.build(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParam::default())?;
+
+        Ok(())
+    }
+}
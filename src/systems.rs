@@ -1,25 +1,138 @@
+use crate::audio::Sound;
 use crate::components::*;
+use crate::director::Director;
 use crate::entities;
 use crate::game;
+use crate::modes::{Progress, Schedule};
+use crate::particles::{self, ParticlePool};
+use crate::rules::Modifiers;
+use crate::scoring::Combos;
+use crate::stages::Stages;
 
+use specs::shrev::EventChannel;
 use specs::*;
 
+use std::collections::HashSet;
+
+/// How many frames apart Noobs shoot, before the director has its say
+pub const NOOB_FIRE_INTERVAL: u64 = 15;
+
+/// Bosses shoot this many times less often than noobs, but a spread of shots at once
+const BOSS_FIRE_INTERVALS: u64 = 3;
+
+/// How far down bosses come before they start sweeping side to side
+const BOSS_TOP: f32 = 60.;
+
+/// How fast noobs dive at the players, before the rules have their say
+const DIVE_SPEED: f32 = 5.;
+
+/// Lets the director see how the players are doing and adjust the difficulty
+pub struct DirectorSystem;
+
+impl<'a> System<'a> for DirectorSystem {
+    type SystemData = (
+        Read<'a, game::Frames>,
+        Read<'a, game::Accuracy>,
+        Read<'a, game::PlayerHealth>,
+        Write<'a, Director>,
+    );
+
+    fn run(&mut self, (frame, accuracy, health, mut director): Self::SystemData) {
+        let health = health.0.iter().map(|h| h.max(0.)).sum();
+        director.update(frame.0, accuracy.shots, accuracy.hits, health);
+    }
+}
+
+/// Moves the game through its stages, flying in challenging
+/// stage baddies as they're due and announcing each new stage
+pub struct StageSystem;
+
+impl<'a> System<'a> for StageSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, LazyUpdate>,
+        Read<'a, game::Accuracy>,
+        Read<'a, Modifiers>,
+        ReadStorage<'a, Baddy>,
+        Write<'a, EventChannel<Sound>>,
+        Write<'a, Stages>,
+    );
+
+    fn run(&mut self, (ent, lazy, accuracy, mods, baddy, mut sounds, mut stages): Self::SystemData) {
+        if !stages.enabled() {
+            return;
+        }
+
+        let number = stages.number();
+        let baddies = baddy.join().count();
+        if let Some(group) = stages.update(*accuracy, baddies) {
+            entities::create_challenge_baddy(ent.create(), group, mods.enemy_speed, &lazy);
+        }
+
+        if stages.number() != number {
+            sounds.single_write(Sound::StageStart);
+        }
+    }
+}
+
+/// Keeps track of the mode's goal, bringing on each boss in
+/// a boss rush and calling time on a score attack
+pub struct ModeSystem;
+
+impl<'a> System<'a> for ModeSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, LazyUpdate>,
+        Read<'a, game::Frames>,
+        Read<'a, Director>,
+        Read<'a, Modifiers>,
+        ReadStorage<'a, BossBaddy>,
+        Write<'a, Progress>,
+    );
+
+    fn run(&mut self, (ent, lazy, frame, director, mods, boss, mut progress): Self::SystemData) {
+        let bosses = boss.join().count();
+        if let Some(number) = progress.update(frame.0, bosses) {
+            let extra_health = director.difficulty().enemy_health + mods.enemy_health;
+            entities::create_boss_baddy(ent.create(), number, extra_health, mods.enemy_speed, &lazy);
+        }
+    }
+}
+
 pub struct BaddySpawner;
 
 impl<'a> System<'a> for BaddySpawner {
-    type SystemData = (Entities<'a>, Read<'a, LazyUpdate>, Read<'a, game::Frames>);
-
-    fn run(&mut self, (ent, lazy, frame): Self::SystemData) {
-        // Spawn some baddies every so often
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, LazyUpdate>,
+        Read<'a, Modifiers>,
+        Read<'a, Progress>,
+        Read<'a, Stages>,
+        Write<'a, Director>,
+        Write<'a, game::GameRng>,
+    );
 
-        // Noobs
-        if frame.0 % 100 == 50 {
-            entities::create_noob_baddy(ent.create(), &lazy);
+    fn run(&mut self, (ent, lazy, mods, progress, stages, mut director, mut rng): Self::SystemData) {
+        // Nothing new turns up in challenging stages or between stages,
+        // and there's nothing but bosses in a boss rush
+        if !stages.spawning() || progress.mode().schedule() == Schedule::Bosses {
+            return;
         }
 
-        // Wavers
-        if frame.0 % 300 == 200 {
-            entities::create_waver_baddy(ent.create(), None, &lazy);
+        // Spawn some baddies every so often, or more or
+        // less often depending on the rules and the director
+        let extra_health = director.difficulty().enemy_health + mods.enemy_health;
+        let speed = mods.enemy_speed;
+        for tick in director.spawn_ticks(mods.spawn_rate) {
+            // Noobs
+            if tick % 100 == 50 {
+                entities::create_noob_baddy(ent.create(), extra_health, speed, &mut rng.0, &lazy);
+            }
+
+            // Wavers
+            if tick % 300 == 200 {
+                entities::create_waver_baddy(ent.create(), None, tick, extra_health, speed, &mut rng.0, &lazy);
+            }
         }
     }
 }
@@ -30,16 +143,49 @@ impl<'a> System<'a> for BaddyActions {
     type SystemData = (
         Entities<'a>,
         WriteStorage<'a, Baddy>,
+        ReadStorage<'a, BossBaddy>,
+        ReadStorage<'a, ChallengeBaddy>,
+        WriteStorage<'a, Dive>,
         ReadStorage<'a, NoobBaddy>,
         WriteStorage<'a, Oscillates>,
+        ReadStorage<'a, Player>,
         ReadStorage<'a, Position>,
         WriteStorage<'a, Velocity>,
         ReadStorage<'a, WaverBaddy>,
         Read<'a, LazyUpdate>,
+        Read<'a, Director>,
+        Read<'a, Modifiers>,
+        Write<'a, game::GameRng>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (ent, mut baddy, noob, mut oscs, pos, mut vel, waver, lazy) = data;
+        let (
+            ent,
+            mut baddy,
+            boss,
+            challenge,
+            mut dive,
+            noob,
+            mut oscs,
+            player,
+            pos,
+            mut vel,
+            waver,
+            lazy,
+            director,
+            mods,
+            mut rng,
+        ) = data;
+
+        // More aggressive baddies shoot more often
+        let difficulty = director.difficulty();
+        let fire_rate = difficulty.aggression * mods.fire_rate;
+        let fire_interval = (NOOB_FIRE_INTERVAL as f32 / fire_rate).round().max(1.) as u64;
+        let bullet_speed = difficulty.bullet_speed * mods.bullet_speed;
+        let extra_health = difficulty.enemy_health + mods.enemy_health;
+
+        // Where everyone is, in case baddies are aiming
+        let players = (&player, &pos).join().map(|(_, p)| *p).collect::<Vec<_>>();
 
         // Update baddies' ages
         for baddy in (&mut baddy).join() {
@@ -70,12 +216,19 @@ impl<'a> System<'a> for BaddyActions {
                 // component from this Noob baddy
                 if oscs.0 == 0 {
                     lazy.remove::<Oscillates>(e);
+
+                    // Then rather than flying off the side, dive at whoever's nearest
+                    if let Some(dive) = dive.get_mut(e).filter(|_| mods.dives) {
+                        dive.diving = true;
+                        *vel = dive_velocity(pos, nearest(&players, pos), DIVE_SPEED * mods.enemy_speed);
+                    }
                 }
             }
 
             // Noob's fire some projectiles every so often
-            if baddy.age % 15 == 0 {
-                entities::create_noob_projectile(ent.create(), *pos, &lazy);
+            if baddy.age % fire_interval == 0 {
+                let target = if mods.aimed_bullets { nearest(&players, pos) } else { None };
+                entities::create_noob_projectile(ent.create(), *pos, target, bullet_speed, &mut rng.0, &lazy);
             }
         }
 
@@ -83,14 +236,70 @@ impl<'a> System<'a> for BaddyActions {
         for (waver, baddy, vel) in (&waver, &baddy, &mut vel).join() {
             // If we're not the last waver, summon the rest of our wave
             if baddy.age == 15 && waver.rank > 0 {
-                entities::create_waver_baddy(ent.create(), Some(*waver), &lazy);
+                entities::create_waver_baddy(
+                    ent.create(),
+                    Some(*waver),
+                    waver.wave,
+                    extra_health,
+                    mods.enemy_speed,
+                    &mut rng.0,
+                    &lazy,
+                );
             }
 
             // Decrease vertical velocity
             if baddy.age % 8 == 0 {
-                vel.y -= 1.;
+                vel.y -= mods.enemy_speed;
             }
         }
+
+        // Bosses come down to the top of the screen, then sweep side to side firing
+        // spreads of shots. Each one sweeps a little faster than the last.
+        for (boss, baddy, pos, vel) in (&boss, &baddy, &pos, &mut vel).join() {
+            if pos.y < BOSS_TOP {
+                continue;
+            }
+
+            if vel.y > 0. {
+                *vel = Velocity {
+                    x: (2. + 0.5 * boss.number as f32) * mods.enemy_speed,
+                    y: 0.,
+                };
+            }
+            if pos.x < 20. {
+                vel.x = vel.x.abs();
+            }
+            if pos.x > game::GAME_WIDTH - entities::BOSS_SIZE - 20. {
+                vel.x = -vel.x.abs();
+            }
+
+            if baddy.age % (fire_interval * BOSS_FIRE_INTERVALS) == 0 {
+                // Shots come out of the middle of the boss's underside
+                let muzzle = Position {
+                    x: pos.x + (entities::BOSS_SIZE - entities::NOOB_SIZE) / 2.,
+                    y: pos.y + entities::BOSS_SIZE - entities::NOOB_SIZE,
+                };
+                let aimed = if mods.aimed_bullets { nearest(&players, pos) } else { None };
+                for &dx in &[-60., 0., 60.] {
+                    let target = aimed.unwrap_or(Position {
+                        x: muzzle.x + entities::NOOB_SIZE / 2. - entities::PLAYER_SIZE / 2.,
+                        y: muzzle.y + 200.,
+                    });
+                    let target = Position {
+                        x: target.x + dx,
+                        y: target.y,
+                    };
+                    entities::create_noob_projectile(ent.create(), muzzle, Some(target), bullet_speed, &mut rng.0, &lazy);
+                }
+            }
+        }
+
+        // Challenging stage baddies just fly their pattern
+        for (challenge, baddy, vel) in (&challenge, &baddy, &mut vel).join() {
+            let v = challenge.pattern.velocity(baddy.age);
+            vel.x = v.x * mods.enemy_speed;
+            vel.y = v.y * mods.enemy_speed;
+        }
     }
 }
 
@@ -102,19 +311,25 @@ impl<'a> System<'a> for MovementSystem {
         Entities<'a>,
         ReadStorage<'a, Player>,
         WriteStorage<'a, Position>,
+        WriteStorage<'a, PreviousPosition>,
         WriteStorage<'a, Rendered>,
         ReadStorage<'a, Velocity>,
     );
 
-    fn run(&mut self, (ent, player, mut pos, mut rendered, vel): Self::SystemData) {
+    fn run(&mut self, (ent, player, mut pos, mut prev, mut rendered, vel): Self::SystemData) {
+        // Remember where everything was for drawing in between ticks
+        for (e, pos) in (&ent, &pos).join() {
+            prev.insert(e, PreviousPosition { x: pos.x, y: pos.y }).expect("unexpected generation error");
+        }
+
         // Update entities' positions using their velocities'
         for (pos, vel) in (&mut pos, &vel).join() {
             pos.x += vel.x;
             pos.y += vel.y;
         }
 
-        // But make sure the player stays in bounds
-        if let Some((pos, _)) = (&mut pos, &player).join().next() {
+        // But make sure the players stay in bounds
+        for (pos, _) in (&mut pos, &player).join() {
             let x_bound = game::GAME_WIDTH - entities::PLAYER_SIZE;
             let y_bound = game::GAME_HEIGHT - entities::PLAYER_SIZE;
             pos.x = pos.x.min(x_bound).max(0.);
@@ -135,58 +350,176 @@ impl<'a> System<'a> for MovementSystem {
     }
 }
 
-/// Respond to game input and update game state as necessary
-pub struct PlayerControlSystem {
-    // What was the last frame where we shot a projectile
-    last_shot_frame: u64,
-}
+/// How fast the player moves at full tilt
+pub const PLAYER_SPEED: f32 = 4.;
 
-impl PlayerControlSystem {
-    pub fn new() -> PlayerControlSystem {
-        PlayerControlSystem { last_shot_frame: 0 }
-    }
-}
+/// Respond to game input and update game state as necessary
+pub struct PlayerControlSystem;
 
 impl<'a> System<'a> for PlayerControlSystem {
     type SystemData = (
         Entities<'a>,
         Read<'a, LazyUpdate>,
         Read<'a, game::Frames>,
-        Read<'a, game::InputState>,
-        ReadStorage<'a, Player>,
+        Read<'a, game::Inputs>,
+        Write<'a, game::Accuracy>,
+        Write<'a, EventChannel<Sound>>,
+        WriteStorage<'a, Player>,
         ReadStorage<'a, Position>,
         WriteStorage<'a, Velocity>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (ent, lazy, frame, input, player, pos, mut vel) = data;
+        let (ent, lazy, frame, inputs, mut accuracy, mut sounds, mut player, pos, mut vel) = data;
 
-        for (_, pos, vel) in (&player, &pos, &mut vel).join() {
-            // First zero out the player's velocity
-            vel.x = 0.;
-            vel.y = 0.;
+        for (player, pos, vel) in (&mut player, &pos, &mut vel).join() {
+            // Players nobody's controlling just sit there
+            let input = inputs.0.get(player.id).copied().unwrap_or_default();
 
-            // Next read the input state and update velocities
+            // Start off with however far the stick is pushed
+            let (mut x, mut y) = input.stick;
+
+            // Next read the digital input and push all the way
             if input.up {
-                vel.y -= 4.;
+                y -= 1.;
             }
             if input.down {
-                vel.y += 4.;
+                y += 1.;
             }
             if input.left {
-                vel.x -= 4.;
+                x -= 1.;
             }
             if input.right {
-                vel.x += 4.;
+                x += 1.;
             }
 
+            // Then update velocities, never going faster than full tilt
+            vel.x = x.clamp(-1., 1.) * PLAYER_SPEED;
+            vel.y = y.clamp(-1., 1.) * PLAYER_SPEED;
+
             // Are we shooting projectiles?
-            if input.shoot && frame.0 - self.last_shot_frame >= 10 {
+            if input.shoot && frame.0 - player.last_shot_frame >= 10 {
                 let e = ent.create();
-                entities::create_player_projectile(e, *pos, &lazy);
+                entities::create_player_projectile(e, player.id, *pos, &lazy);
+                sounds.single_write(Sound::PlayerShot);
+                accuracy.shots += 1;
 
                 // Update frame reference
-                self.last_shot_frame = frame.0;
+                player.last_shot_frame = frame.0;
+            }
+        }
+    }
+}
+
+/// Advances entities' animation clips and applies the current frame
+pub struct AnimationSystem;
+
+impl<'a> System<'a> for AnimationSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, game::Frames>,
+        Write<'a, EventChannel<AnimationEnded>>,
+        WriteStorage<'a, Animation>,
+        WriteStorage<'a, Rendered>,
+    );
+
+    fn run(&mut self, (ent, frame, mut ended, mut anim, mut rendered): Self::SystemData) {
+        for (e, anim, rendered) in (&ent, &mut anim, &mut rendered).join() {
+            let clip = match anim.clips.get(anim.current) {
+                Some(clip) => clip,
+                None => continue,
+            };
+
+            match clip.frame_at(frame.0.saturating_sub(anim.started)) {
+                Some(f) => rendered.colour = f.colour,
+
+                // A `Once` clip just ran out, let everyone know and
+                // move on to the next clip if there is one
+                None if !anim.finished => {
+                    let next = clip.next;
+
+                    anim.finished = true;
+                    ended.single_write(AnimationEnded { entity: e });
+
+                    if let Some(next) = next {
+                        anim.play(next, frame.0);
+                    }
+                }
+
+                None => {}
+            }
+        }
+    }
+}
+
+/// Removes `OneShot` entities once their animation has ended
+pub struct AnimationCleanup {
+    reader: ReaderId<AnimationEnded>,
+}
+
+impl AnimationCleanup {
+    pub fn new(world: &mut World) -> AnimationCleanup {
+        let reader = world
+            .write_resource::<EventChannel<AnimationEnded>>()
+            .register_reader();
+        AnimationCleanup { reader }
+    }
+}
+
+impl<'a> System<'a> for AnimationCleanup {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, EventChannel<AnimationEnded>>,
+        ReadStorage<'a, OneShot>,
+    );
+
+    fn run(&mut self, (ent, ended, one_shot): Self::SystemData) {
+        for ev in ended.read(&mut self.reader) {
+            // The entity may have already gone away (e.g. out of bounds)
+            if ent.is_alive(ev.entity) && one_shot.contains(ev.entity) {
+                ent.delete(ev.entity).expect("unexpected generation error");
+            }
+        }
+    }
+}
+
+/// Moves particles along and lets emitters give off new ones
+pub struct ParticleSystem;
+
+impl<'a> System<'a> for ParticleSystem {
+    type SystemData = (
+        Write<'a, ParticlePool>,
+        WriteStorage<'a, Emitter>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, (mut pool, mut emitter, pos): Self::SystemData) {
+        pool.update();
+
+        for (emitter, pos) in (&mut emitter, &pos).join() {
+            // Only spawn whole particles, saving the rest for later
+            emitter.carry += emitter.config.rate;
+            let count = emitter.carry.floor();
+            emitter.carry -= count;
+
+            let (dx, dy) = emitter.offset;
+            pool.spawn(&emitter.config, pos.x + dx, pos.y + dy, count as u32);
+        }
+    }
+}
+
+/// Removes score popups once they've been shown for long enough
+pub struct ScorePopupExpiry;
+
+impl<'a> System<'a> for ScorePopupExpiry {
+    type SystemData = (Entities<'a>, Read<'a, game::Frames>, ReadStorage<'a, ScoreText>);
+
+    fn run(&mut self, (ent, frame, score_text): Self::SystemData) {
+        // We only display the text for 60 frames,
+        // remove it after that time
+        for (e, score_text) in (&ent, &score_text).join() {
+            if frame.0 > score_text.frame + 60 {
+                ent.delete(e).expect("unexpected generation error");
             }
         }
     }
@@ -199,14 +532,25 @@ impl<'a> System<'a> for CollisionSystem {
         Entities<'a>,
         Read<'a, LazyUpdate>,
         Read<'a, game::Frames>,
+        Read<'a, Modifiers>,
+        Write<'a, game::Accuracy>,
+        Write<'a, Combos>,
         Write<'a, game::PlayerHealth>,
         Write<'a, game::PlayerScore>,
+        Write<'a, ParticlePool>,
+        Write<'a, EventChannel<Sound>>,
+        Write<'a, Stages>,
+        WriteStorage<'a, Animation>,
         WriteStorage<'a, Baddy>,
+        ReadStorage<'a, BossBaddy>,
+        ReadStorage<'a, ChallengeBaddy>,
         ReadStorage<'a, DamageBaddy>,
         ReadStorage<'a, DamagePlayer>,
+        ReadStorage<'a, Dive>,
         ReadStorage<'a, Player>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, Rendered>,
+        ReadStorage<'a, WaverBaddy>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
@@ -214,42 +558,106 @@ impl<'a> System<'a> for CollisionSystem {
             ent,
             lazy,
             frame,
+            mods,
+            mut accuracy,
+            mut combos,
             mut health,
             mut score,
+            mut particles,
+            mut sounds,
+            mut stages,
+            mut anim,
             mut baddy,
+            boss,
+            challenge,
             damage_b,
             damage_p,
+            dive,
             player,
             pos,
             rendered,
+            waver,
         ) = data;
 
-        // Grab the player's render area
-        let player_area = (&player, &rendered)
+        // Grab the players' render areas
+        let players = (&player, &*ent, &rendered)
             .join()
-            .map(|p| p.1.area)
-            .next()
-            .expect("no player rendered component?");
+            .map(|(p, e, r)| (p.id, e, r.area))
+            .collect::<Vec<_>>();
 
         // Go over all baddies and see if we hit em!
+        let mut spent = HashSet::new();
         for (b, b_pos, b_e, b_rendered) in (&mut baddy, &pos, &*ent, &rendered).join() {
-            // Go over entities that can hurt baddies
-            for (_, d_e, d_rendered) in (&damage_b, &*ent, &rendered).join() {
-                if b_rendered.area.overlaps(&d_rendered.area) {
+            let hitbox = scale_rect(&b_rendered.area, mods.hitbox_scale);
+
+            // Go over entities that can hurt baddies. They aren't really deleted until
+            // the end of the frame, so skip any that already hit something this frame.
+            for (d, d_e, d_rendered) in (&damage_b, &*ent, &rendered).join() {
+                if !spent.contains(&d_e) && hitbox.overlaps(&d_rendered.area) {
+                    spent.insert(d_e);
+
+                    // Sparks fly where the projectile hit
+                    let (x, y) = rect_center(&d_rendered.area);
+                    particles.burst(&particles::IMPACT, x, y);
+
+                    // Baddies already going down soak up shots, but they don't count as hits
                     if b.health > 0 {
+                        accuracy.hits += 1;
+
                         // Decrement baddy's health
                         b.health -= 1;
 
-                        // Baddy was vanquished! Update player
-                        // score and remove baddy
+                        // Baddy was vanquished! Update the score of
+                        // whoever shot it and remove baddy
                         if b.health == 0 {
-                            score.0 += b.score;
+                            // Diving baddies are worth more, and more again the longer the chain
+                            let worth = match dive.get(b_e) {
+                                Some(dive) if dive.diving && combos.enabled() => dive.score,
+                                _ => b.score,
+                            };
+                            let (points, chain) = combos.kill(d.player, frame.0, worth);
+                            if let Some(score) = score.0.get_mut(d.player) {
+                                *score += points;
+                            }
 
                             // Show little score popup
                             let e = ent.create();
-                            entities::create_score_popup(e, *b_pos, b.score, frame.0, &lazy);
+                            entities::create_score_popup(e, *b_pos, points, chain, frame.0, &lazy);
+
+                            // Shooting down a whole challenging stage group or wave earns a bonus
+                            let bonus = challenge
+                                .get(b_e)
+                                .and_then(|c| stages.shot_down(c.group))
+                                .or_else(|| waver.get(b_e).and_then(|w| combos.wave_kill(w.wave)));
+                            if let Some(bonus) = bonus {
+                                if let Some(score) = score.0.get_mut(d.player) {
+                                    *score += bonus;
+                                }
+
+                                let pos = Position {
+                                    x: b_pos.x,
+                                    y: b_pos.y - 16.,
+                                };
+                                entities::create_score_popup(ent.create(), pos, bonus, 0, frame.0, &lazy);
+                            }
+
+                            // And blow it up
+                            let (x, y) = rect_center(&b_rendered.area);
+                            particles.burst(&particles::EXPLOSION, x, y);
+                            entities::create_explosion(ent.create(), Position { x, y }, frame.0, &lazy);
+
+                            sounds.single_write(Sound::EnemyDeath);
 
                             ent.delete(b_e).expect("unexpected generation error");
+                        } else {
+                            // Otherwise let the player know they landed a hit
+                            sounds.single_write(Sound::EnemyHit);
+
+                            if let Some(anim) = anim.get_mut(b_e) {
+                                if anim.clips.contains_key("hit") {
+                                    anim.play("hit", frame.0);
+                                }
+                            }
                         }
                     }
 
@@ -259,18 +667,204 @@ impl<'a> System<'a> for CollisionSystem {
             }
         }
 
-        // Go over all entities that can damage the player
+        // Go over all entities that can damage the players
         for (_, e, rendered) in (&damage_p, &*ent, &rendered).join() {
             // Ouch, we hit a baddy or projectile :(
-            if rendered.area.overlaps(&player_area) {
-                if health.0 > 0. {
-                    // Decrement player's health
-                    health.0 -= 1.;
+            // Players who are already out don't get hit again
+            let hit = players.iter().find(|&&(id, _, area)| {
+                health.0.get(id).copied().unwrap_or_default() > 0. && rendered.area.overlaps(&area)
+            });
+
+            if let Some(&(id, p_e, player_area)) = hit {
+                // Decrement that player's health, and there goes their chain
+                health.0[id] -= 1.;
+                combos.break_chain(id);
+
+                let (x, y) = rect_center(&player_area);
+                particles.burst(&particles::PLAYER_HIT, x, y);
+                sounds.single_write(Sound::PlayerHit);
+
+                // That was their last hit, so they're out
+                if health.0[id] <= 0. {
+                    particles.burst(&particles::EXPLOSION, x, y);
+                    entities::create_explosion(ent.create(), Position { x, y }, frame.0, &lazy);
+                    ent.delete(p_e).expect("unexpected generation error");
                 }
 
-                // This baddy or projectile did its job, let it go now
-                ent.delete(e).expect("unexpected generation error");
+                // This baddy or projectile did its job, let it go now. Bosses stay
+                // though, they only count as beaten once they're shot down.
+                if !boss.contains(e) {
+                    ent.delete(e).expect("unexpected generation error");
+                }
             }
         }
     }
 }
+
+/// Center point of a rendered area
+fn rect_center(area: &ggez::graphics::Rect) -> (f32, f32) {
+    (area.x + area.w / 2., area.y + area.h / 2.)
+}
+
+/// An area grown or shrunk by `scale` around its center
+fn scale_rect(area: &ggez::graphics::Rect, scale: f32) -> ggez::graphics::Rect {
+    let (x, y) = rect_center(area);
+    let (w, h) = (area.w * scale, area.h * scale);
+    ggez::graphics::Rect::new(x - w / 2., y - h / 2., w, h)
+}
+
+/// Heading from `from` for `target` at `speed`, or straight down without one.
+/// Always at least a little downwards, so divers never turn back up the screen.
+fn dive_velocity(from: &Position, target: Option<Position>, speed: f32) -> Velocity {
+    let (dx, dy) = match target {
+        Some(target) => (target.x - from.x, (target.y - from.y).max(speed)),
+        None => (0., 1.),
+    };
+    let length = (dx * dx + dy * dy).sqrt();
+
+    Velocity {
+        x: dx / length * speed,
+        y: dy / length * speed,
+    }
+}
+
+/// Whichever of `positions` is closest to `to`
+fn nearest(positions: &[Position], to: &Position) -> Option<Position> {
+    let distance = |p: &Position| (p.x - to.x).powi(2) + (p.y - to.y).powi(2);
+    positions
+        .iter()
+        .min_by(|a, b| distance(a).partial_cmp(&distance(b)).expect("NaN distance"))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modes::Mode;
+    use crate::rules::Rules;
+    use crate::sim::Simulation;
+
+    fn baddy(world: &mut World, health: u64, area: [f32; 4]) -> Entity {
+        world
+            .create_entity()
+            .with(Baddy {
+                age: 0,
+                health,
+                score: 50,
+            }).with(Position { x: area[0], y: area[1] })
+            .with(Rendered {
+                area: area.into(),
+                colour: (0xFF, 0xFF, 0xFF),
+            }).build()
+    }
+
+    fn shot(world: &mut World, area: [f32; 4]) {
+        world
+            .create_entity()
+            .with(DamageBaddy { player: 0 })
+            .with(Position { x: area[0], y: area[1] })
+            .with(Rendered {
+                area: area.into(),
+                colour: (0x00, 0x00, 0xFF),
+            }).build();
+    }
+
+    #[test]
+    fn each_shot_hits_once() {
+        let mut sim = Simulation::new(1);
+        let world = &mut sim.world;
+
+        // One shot between two baddies only hurts one of them
+        let pair = [baddy(world, 3, [100., 100., 32., 32.]), baddy(world, 3, [110., 100., 32., 32.])];
+        shot(world, [120., 110., 4., 8.]);
+
+        // And a second shot at a baddy the first brought down doesn't count
+        baddy(world, 1, [300., 100., 32., 32.]);
+        shot(world, [310., 110., 4., 8.]);
+        shot(world, [312., 110., 4., 8.]);
+
+        CollisionSystem.run_now(world);
+        world.maintain();
+
+        assert_eq!(world.read_resource::<game::Accuracy>().hits, 2);
+        let baddies = world.read_storage::<Baddy>();
+        let health = pair.iter().map(|&e| baddies.get(e).unwrap().health).sum::<u64>();
+        assert_eq!(health, 5);
+    }
+
+    #[test]
+    fn noobs_dive_once_done_weaving() {
+        let mut sim = Simulation::new(1);
+        let world = &mut sim.world;
+        world.write_resource::<Modifiers>().dives = true;
+
+        // A noob on its last swing, about to turn back from the right-hand side
+        let noob = world
+            .create_entity()
+            .with(NoobBaddy)
+            .with(Baddy {
+                age: 0,
+                health: 3,
+                score: 100,
+            }).with(Dive {
+                score: 200,
+                diving: false,
+            }).with(Oscillates(1))
+            .with(Position {
+                x: 0.9 * game::GAME_WIDTH,
+                y: 100.,
+            }).with(Velocity { x: 2., y: 0. })
+            .build();
+        world
+            .create_entity()
+            .with(Player {
+                id: 0,
+                last_shot_frame: 0,
+            }).with(Position {
+                x: 0.5 * game::GAME_WIDTH,
+                y: 500.,
+            }).build();
+
+        BaddyActions.run_now(world);
+        world.maintain();
+
+        // It heads down and across at the player, and is worth its dive score if shot now
+        assert!(world.read_storage::<Dive>().get(noob).unwrap().diving);
+        let vel = *world.read_storage::<Velocity>().get(noob).unwrap();
+        assert!(vel.x < 0. && vel.y > 0.);
+    }
+
+    #[test]
+    fn ramming_a_boss_doesnt_beat_it() {
+        let rules = Rules {
+            mode: Mode::BossRush,
+            ..Rules::default()
+        };
+        let mut sim = Simulation::with_rules(1, 1, &rules);
+
+        // Wait for the first boss to turn up
+        while sim.world.read_storage::<BossBaddy>().is_empty() {
+            sim.tick();
+        }
+        let target = *(&sim.world.read_storage::<Player>(), &sim.world.read_storage::<Position>())
+            .join()
+            .next()
+            .unwrap()
+            .1;
+
+        // Then keep shoving it into the player
+        for _ in 0..30 {
+            {
+                let bosses = sim.world.read_storage::<BossBaddy>();
+                let mut positions = sim.world.write_storage::<Position>();
+                for (_, pos) in (&bosses, &mut positions).join() {
+                    *pos = target;
+                }
+            }
+            sim.tick();
+        }
+
+        assert_eq!(sim.world.read_storage::<BossBaddy>().count(), 1);
+        assert_eq!(sim.progress().status(sim.frame()).unwrap(), "BOSS 1/5");
+    }
+}
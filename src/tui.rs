@@ -0,0 +1,335 @@
+use crate::bot::Bot;
+use crate::components::Rendered;
+use crate::game::{self, GameOptions, InputState};
+use crate::input::{self, Action, Bindings};
+use crate::particles::ParticlePool;
+use crate::replay::Replay;
+use crate::sim::Simulation;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+use ggez::{GameError, GameResult};
+use specs::{Join, WorldExt};
+
+use std::collections::HashMap;
+use std::io::{self, Stdout, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Only redraw every this many ticks, terminals are slow
+const TICKS_PER_DRAW: u64 = 2;
+
+/// Terminals only tell us when a key is pressed (and repeated) but
+/// never when it's let go, so treat keys as held for this many ticks
+/// after we last heard about them
+const HOLD_TICKS: u64 = 12;
+
+/// Background colour of the game area
+const BG_COLOUR: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+/// Terminals report errors their own way
+fn tui_error(e: crossterm::ErrorKind) -> GameError {
+    GameError::RenderError(e.to_string())
+}
+
+/// Puts the terminal into raw mode on an alternate screen,
+/// and restores it when dropped, even if we bail out with an error
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new(out: &mut Stdout) -> GameResult<TerminalGuard> {
+        terminal::enable_raw_mode().map_err(tui_error)?;
+        queue!(out, terminal::EnterAlternateScreen, cursor::Hide).map_err(tui_error)?;
+        out.flush()?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        let _ = queue!(out, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Translate a terminal key into the ggez key it'd be bound as
+fn key_code(key: KeyCode) -> Option<ggez::event::KeyCode> {
+    let name = match key {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) if c.is_ascii_digit() => format!("Key{}", c),
+        KeyCode::Char(c) => c.to_ascii_uppercase().to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Enter => "Return".to_string(),
+        KeyCode::Esc => "Escape".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::Backspace => "Back".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        _ => return None,
+    };
+
+    input::key_from_name(&name)
+}
+
+/// Pixels we draw into before turning them into terminal cells.
+/// Each cell shows two pixels stacked on top of each other.
+struct PixelGrid {
+    width: usize,
+    height: usize,
+    // Game units per pixel
+    scale: f32,
+    pixels: Vec<(u8, u8, u8)>,
+}
+
+impl PixelGrid {
+    /// Fit the game area into a terminal `cols` by `rows` cells big
+    fn new(cols: u16, rows: u16) -> PixelGrid {
+        let (cols, rows) = (f32::from(cols.max(1)), f32::from(rows.max(1)));
+        let scale = (game::GAME_WIDTH / cols).max(game::GAME_HEIGHT / (rows * 2.));
+        let width = (game::GAME_WIDTH / scale) as usize;
+        let height = (game::GAME_HEIGHT / scale) as usize & !1;
+
+        PixelGrid {
+            width,
+            height,
+            scale,
+            pixels: vec![BG_COLOUR; width * height],
+        }
+    }
+
+    fn clear(&mut self) {
+        for p in &mut self.pixels {
+            *p = BG_COLOUR;
+        }
+    }
+
+    /// Fill every pixel the area overlaps, so even tiny
+    /// things show up as at least a single pixel
+    fn fill(&mut self, x: f32, y: f32, w: f32, h: f32, colour: (u8, u8, u8)) {
+        let to_px = |v: f32, max: usize| ((v / self.scale).max(0.) as usize).min(max);
+        let (x0, x1) = (to_px(x, self.width), to_px(x + w, self.width - 1) + 1);
+        let (y0, y1) = (to_px(y, self.height), to_px(y + h, self.height - 1) + 1);
+
+        for py in y0..y1.min(self.height) {
+            for px in x0..x1.min(self.width) {
+                self.pixels[py * self.width + px] = colour;
+            }
+        }
+    }
+
+    /// Write the grid out as half block characters, starting at `top`
+    fn render(&self, out: &mut Stdout, left: u16, top: u16) -> crossterm::Result<()> {
+        let rgb = |(r, g, b): (u8, u8, u8)| Color::Rgb { r, g, b };
+
+        for row in 0..self.height / 2 {
+            queue!(out, cursor::MoveTo(left, top + row as u16))?;
+
+            // Only change colours when we have to, it cuts the output way down
+            let mut last = None;
+            for x in 0..self.width {
+                let upper = self.pixels[row * 2 * self.width + x];
+                let lower = self.pixels[(row * 2 + 1) * self.width + x];
+
+                if last != Some((upper, lower)) {
+                    queue!(out, SetForegroundColor(rgb(upper)), SetBackgroundColor(rgb(lower)))?;
+                    last = Some((upper, lower));
+                }
+                queue!(out, Print('▀'))?;
+            }
+        }
+
+        queue!(out, ResetColor)
+    }
+}
+
+/// Play the game in the terminal until the player quits,
+/// or watch a replay if given one
+pub fn run(options: &GameOptions) -> GameResult<()> {
+    // Load the replay before taking over the terminal, so any error shows up
+    let playback = match &options.replay {
+        Some(path) => Some(Replay::load(path)?),
+        None => None,
+    };
+
+    let mut out = io::stdout();
+    let _guard = TerminalGuard::new(&mut out)?;
+
+    let bindings = Bindings::default();
+    let mut sim = match &playback {
+        Some(playback) => playback.simulation(),
+        None => Simulation::with_rules(options.seed, 1, &options.rules),
+    };
+    sim.set_max_particles(options.max_particles);
+    let mut replay = Replay::new(options.seed, options.rules.clone());
+    let mut bot = if options.bot { Some(Bot::new(0)) } else { None };
+
+    // Last tick each action was pressed on
+    let mut held: HashMap<Action, u64> = HashMap::new();
+    let mut paused = false;
+    let mut replay_over = false;
+    let mut tick = 0;
+
+    let (cols, rows) = terminal::size().map_err(tui_error)?;
+    let mut grid = PixelGrid::new(cols, rows.saturating_sub(1));
+
+    let tick_length = Duration::from_secs(1) / game::DESIRED_FPS;
+    let mut next_tick = Instant::now();
+
+    'game: loop {
+        // Deal with all the input that came in since last tick
+        while event::poll(Duration::from_secs(0)).map_err(tui_error)? {
+            match event::read().map_err(tui_error)? {
+                Event::Key(KeyEvent { code, modifiers }) => {
+                    // Raw mode eats Ctrl-C, so quit on it ourselves
+                    if code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL) {
+                        break 'game;
+                    }
+
+                    match key_code(code).and_then(|k| bindings.key(k)) {
+                        Some(Action::Quit) => break 'game,
+                        Some(Action::Pause) => paused = !paused,
+                        Some(action) => {
+                            held.insert(action, tick);
+                        }
+                        None => {}
+                    }
+                }
+
+                Event::Resize(cols, rows) => {
+                    grid = PixelGrid::new(cols, rows.saturating_sub(1));
+                    queue!(out, terminal::Clear(terminal::ClearType::All)).map_err(tui_error)?;
+                }
+
+                Event::Mouse(_) => {}
+            }
+        }
+
+        // Let go of anything we haven't heard about in a while
+        held.retain(|_, &mut pressed| tick - pressed < HOLD_TICKS);
+        let input = match &mut bot {
+            Some(bot) => bot.think(&sim.world),
+            None => {
+                let mut input = InputState::default();
+                for &action in held.keys() {
+                    action.apply(&mut input, true);
+                }
+
+                // Left on screen is right in a mirrored game
+                if sim.modifiers().mirror {
+                    input.mirrored()
+                } else {
+                    input
+                }
+            }
+        };
+
+        if !paused {
+            match &playback {
+                // Replays bring their own input, until they run out
+                Some(playback) => replay_over = !playback.step(&mut sim),
+                None if !sim.game_over() => {
+                    sim.set_input(0, input);
+                    replay.record(&input);
+                    sim.tick();
+                }
+                None => {}
+            }
+        }
+
+        if tick % TICKS_PER_DRAW == 0 {
+            let note = if paused {
+                Some("PAUSED")
+            } else if replay_over {
+                Some("END OF REPLAY")
+            } else {
+                None
+            };
+            draw(&mut out, &sim, &mut grid, note).map_err(tui_error)?;
+        }
+
+        tick += 1;
+
+        // Wait until it's time for the next tick
+        next_tick += tick_length;
+        let now = Instant::now();
+        if next_tick > now {
+            thread::sleep(next_tick - now);
+        } else {
+            next_tick = now;
+        }
+    }
+
+    if let (Some(path), None) = (&options.record, &playback) {
+        replay.save(path)?;
+    }
+
+    Ok(())
+}
+
+/// Draw the game and a line of HUD above it, with `note` in place of the stage's
+/// banner or results, e.g. that the game's paused
+fn draw(out: &mut Stdout, sim: &Simulation, grid: &mut PixelGrid, note: Option<&str>) -> crossterm::Result<()> {
+    grid.clear();
+    let mods = sim.modifiers();
+
+    for rendered in (&sim.world.read_storage::<Rendered>()).join() {
+        let area = rendered.area;
+        grid.fill(mods.screen_x(area.x, area.w), area.y, area.w, area.h, rendered.colour);
+    }
+
+    for ([x, y], _, (r, g, b, a)) in sim.world.read_resource::<ParticlePool>().iter() {
+        // Terminals can't blend, so fade particles towards the background
+        let fade = |c: u8| (u16::from(c) * u16::from(a) / 0xFF) as u8;
+        grid.fill(mods.screen_x(x, 0.), y, 0., 0., (fade(r), fade(g), fade(b)));
+    }
+
+    // Health as a bar of blocks, one per point, for each player
+    let full = mods.player_health as usize;
+    let stats = (0..sim.players())
+        .filter(|_| mods.hud)
+        .map(|id| {
+            let health = (sim.health(id).max(0.) as usize).min(full);
+            let empty = full - health;
+            let name = if sim.players() > 1 { format!("P{} ", id + 1) } else { String::new() };
+            format!(
+                "{}SCORE {:06} x{}  HEALTH {}{}",
+                name,
+                sim.score(id),
+                sim.multiplier(id),
+                "█".repeat(health),
+                "░".repeat(empty)
+            )
+        }).collect::<Vec<_>>()
+        .join("  ");
+    let stages = sim.stages();
+    let status = if sim.game_over() {
+        sim.ending().replace('\n', " ")
+    } else if let Some(note) = note {
+        note.to_string()
+    } else if let Some(results) = stages.results() {
+        results.lines().join("  ")
+    } else {
+        stages.banner().unwrap_or_default().replace('\n', " ")
+    };
+    let status = match sim.progress().status(sim.frame()) {
+        Some(progress) if !sim.game_over() => format!("{}  {}", progress, status),
+        _ => status,
+    };
+
+    queue!(
+        out,
+        cursor::MoveTo(0, 0),
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        Print(format!("{}  {}", stats, status)),
+    )?;
+
+    grid.render(out, 0, 1)?;
+    out.flush()?;
+
+    Ok(())
+}
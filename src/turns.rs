@@ -0,0 +1,108 @@
+use crate::game::DESIRED_FPS;
+use crate::sim::{Simulation, Snapshot};
+
+/// How long the next player gets to get ready when it's their turn
+const READY_FRAMES: u32 = 2 * DESIRED_FPS;
+
+/// Players taking turns on their own games, like the arcade original.
+/// Whoever's up plays on the one simulation, everyone else's game is saved
+/// away until it's their turn again. Each game has a single player in it,
+/// so it's always player 0 as far as the simulation's concerned.
+pub struct Turns {
+    // Whose turn it is, counting from 0
+    current: usize,
+    // Everyone else's game, none for whoever's playing
+    saved: Vec<Option<Snapshot>>,
+    // Frames left before whoever's up gets going
+    ready: u32,
+}
+
+impl Turns {
+    /// Start `players` players off on the same fresh game, player 1 first
+    pub fn new(sim: &mut Simulation, players: usize) -> Turns {
+        let saved = (0..players)
+            .map(|id| if id == 0 { None } else { Some(sim.snapshot()) })
+            .collect();
+
+        Turns {
+            current: 0,
+            saved,
+            ready: READY_FRAMES,
+        }
+    }
+
+    /// Whose turn it is
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// How many players are taking turns
+    pub fn players(&self) -> usize {
+        self.saved.len()
+    }
+
+    /// Player `id`'s health, from the game on screen if it's their turn
+    pub fn health(&self, sim: &Simulation, id: usize) -> f32 {
+        match &self.saved[id] {
+            Some(snapshot) => snapshot.health(0),
+            None => sim.health(0),
+        }
+    }
+
+    /// Player `id`'s score, from the game on screen if it's their turn
+    pub fn score(&self, sim: &Simulation, id: usize) -> u32 {
+        match &self.saved[id] {
+            Some(snapshot) => snapshot.score(0),
+            None => sim.score(0),
+        }
+    }
+
+    /// What player `id`'s kills are multiplied by. Anyone waiting
+    /// their turn has let their chain go by the time they're back.
+    pub fn multiplier(&self, sim: &Simulation, id: usize) -> u32 {
+        match &self.saved[id] {
+            Some(_) => 1,
+            None => sim.multiplier(0),
+        }
+    }
+
+    /// Count down while whoever's up gets ready, returns whether they still are
+    pub fn getting_ready(&mut self) -> bool {
+        if self.ready > 0 {
+            self.ready -= 1;
+        }
+        self.ready > 0
+    }
+
+    /// Whether to show who's up next
+    pub fn ready(&self) -> bool {
+        self.ready > 0
+    }
+
+    /// Hand over to the next player still in the game if whoever's up
+    /// just lost a ship, i.e. took a hit, or their game's over some other
+    /// way like running out of time. Returns whether anyone took over.
+    pub fn after_tick(&mut self, sim: &mut Simulation, health: f32) -> bool {
+        if sim.health(0) >= health && !sim.game_over() {
+            return false;
+        }
+
+        let players = self.players();
+        let next = (1..players)
+            .map(|i| (self.current + i) % players)
+            .find(|&id| self.saved[id].as_ref().is_some_and(|s| !s.game_over()));
+        let next = match next {
+            Some(next) => next,
+            // Nobody else left, so they keep going
+            None => return false,
+        };
+
+        let theirs = self.saved[next].take().expect("player up next has no saved game");
+        self.saved[self.current] = Some(sim.snapshot());
+        sim.restore(&theirs);
+
+        self.current = next;
+        self.ready = READY_FRAMES;
+        true
+    }
+}
@@ -0,0 +1,179 @@
+use ggez::conf::{FullscreenType, NumSamples};
+use ggez::graphics::{self, Canvas, DrawParam, FilterMode, MeshBuilder, Rect};
+use ggez::{Context, GameResult};
+
+use std::f32::consts::PI;
+
+/// Maps our fixed logical resolution onto whatever size the window is,
+/// keeping the aspect ratio and filling the rest with black bars.
+pub struct Viewport {
+    // The logical area we need to fit on screen
+    bounds: Rect,
+    // Only scale by whole numbers so pixels stay crisp
+    integer_scaling: bool,
+    fullscreen: bool,
+    // Window size in screen pixels
+    window: (f32, f32),
+    // For rotated (TATE) screens we draw everything here
+    // first and then draw it rotated onto the window
+    tate: Option<Canvas>,
+}
+
+impl Viewport {
+    pub fn new(
+        ctx: &mut Context,
+        bounds: Rect,
+        integer_scaling: bool,
+        fullscreen: bool,
+        tate: bool,
+    ) -> GameResult<Viewport> {
+        let tate = if tate {
+            let mut canvas = Canvas::new(ctx, bounds.w as u16, bounds.h as u16, NumSamples::One)?;
+            canvas.set_filter(FilterMode::Nearest);
+            Some(canvas)
+        } else {
+            None
+        };
+
+        let (w, h) = graphics::drawable_size(ctx);
+
+        let mut viewport = Viewport {
+            bounds,
+            integer_scaling,
+            fullscreen,
+            window: (w, h),
+            tate,
+        };
+        viewport.resize(ctx, w, h)?;
+
+        Ok(viewport)
+    }
+
+    /// Size a window has to be to fit the bounds exactly,
+    /// taking into account whether it's rotated
+    pub fn window_size(bounds: Rect, tate: bool) -> (f32, f32) {
+        if tate {
+            (bounds.h, bounds.w)
+        } else {
+            (bounds.w, bounds.h)
+        }
+    }
+
+    /// The area that actually ends up on screen.
+    /// When rotated this is the canvas, otherwise it's just our bounds.
+    fn display(&self) -> Rect {
+        let (w, h) = Viewport::window_size(self.bounds, self.tate.is_some());
+        if self.tate.is_some() {
+            Rect::new(0., 0., w, h)
+        } else {
+            self.bounds
+        }
+    }
+
+    /// How many screen pixels each logical pixel takes up
+    fn scale(&self) -> f32 {
+        let display = self.display();
+        let (w, h) = self.window;
+        let scale = (w / display.w).min(h / display.h);
+
+        // Can't go any smaller than 1 with whole numbers,
+        // so just squish it if the window's that small
+        if self.integer_scaling && scale >= 1. {
+            scale.floor()
+        } else {
+            scale
+        }
+    }
+
+    /// The logical area that covers the entire window,
+    /// with the display area right in the middle of it
+    fn screen_rect(&self) -> Rect {
+        let display = self.display();
+        let scale = self.scale();
+        let (w, h) = (self.window.0 / scale, self.window.1 / scale);
+
+        Rect::new(
+            display.x - (w - display.w) / 2.,
+            display.y - (h - display.h) / 2.,
+            w,
+            h,
+        )
+    }
+
+    /// Update the coordinate transform for the new window size
+    pub fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) -> GameResult<()> {
+        self.window = (width, height);
+        graphics::set_screen_coordinates(ctx, self.screen_rect())
+    }
+
+    /// Switch between windowed and fullscreen
+    pub fn toggle_fullscreen(&mut self, ctx: &mut Context) -> GameResult<()> {
+        self.fullscreen = !self.fullscreen;
+
+        let mode = if self.fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Windowed
+        };
+        graphics::set_fullscreen(ctx, mode)
+    }
+
+    /// Get ready to draw a frame
+    pub fn begin_frame(&self, ctx: &mut Context) -> GameResult<()> {
+        if let Some(canvas) = &self.tate {
+            graphics::set_canvas(ctx, Some(canvas));
+            graphics::set_screen_coordinates(ctx, self.bounds)?;
+        }
+
+        Ok(())
+    }
+
+    /// Put the frame on the window once everything's been drawn
+    pub fn end_frame(&self, ctx: &mut Context) -> GameResult<()> {
+        if let Some(canvas) = &self.tate {
+            graphics::set_canvas(ctx, None);
+            graphics::set_screen_coordinates(ctx, self.screen_rect())?;
+            graphics::clear(ctx, graphics::BLACK);
+
+            // Turn the frame a quarter clockwise around its center
+            let display = self.display();
+            let param = DrawParam::default()
+                .dest([display.w / 2., display.h / 2.])
+                .offset([0.5, 0.5])
+                .rotation(PI / 2.);
+            graphics::draw(ctx, canvas, param)?;
+        }
+
+        self.draw_letterbox(ctx)
+    }
+
+    /// Cover up anything that strayed outside the logical area
+    fn draw_letterbox(&self, ctx: &mut Context) -> GameResult<()> {
+        let display = self.display();
+        let screen = self.screen_rect();
+
+        // Nothing to cover if the aspect ratios match exactly
+        let (side, top) = (display.x - screen.x, display.y - screen.y);
+        if side <= 0. && top <= 0. {
+            return Ok(());
+        }
+
+        let mut bars = MeshBuilder::new();
+        let fill = graphics::DrawMode::fill();
+
+        // Left and right
+        if side > 0. {
+            bars.rectangle(fill, [screen.x, screen.y, side, screen.h].into(), graphics::BLACK);
+            bars.rectangle(fill, [display.right(), screen.y, side, screen.h].into(), graphics::BLACK);
+        }
+
+        // Top and bottom
+        if top > 0. {
+            bars.rectangle(fill, [screen.x, screen.y, screen.w, top].into(), graphics::BLACK);
+            bars.rectangle(fill, [screen.x, display.bottom(), screen.w, top].into(), graphics::BLACK);
+        }
+
+        let bars = bars.build(ctx)?;
+        graphics::draw(ctx, &bars, DrawParam::default())
+    }
+}